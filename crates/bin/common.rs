@@ -1,17 +1,23 @@
-use std::{io::BufRead as _, path::PathBuf};
+use std::{borrow::Cow, path::PathBuf};
 
 use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncBufReadExt as _;
+use tracing::debug;
 
 pub(crate) const SERVE_PATH: &str = env!("SERVE_PATH");
+pub(crate) const CHANGED_PATHS: &str = env!("CHANGED_PATHS");
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct StateForTesting {
     pub(crate) serve_path: PathBuf,
     pub(crate) serve_port: u16,
-    pub(crate) browser_pid: u32,
-    pub(crate) browser_debugging_address: String,
+    pub(crate) browser_pid: Option<u32>,
+    pub(crate) browser_debugging_address: Option<String>,
+    /// Of the most recent build that was not restarted; `None` if terminated by a signal,
+    /// if it exited successfully but `--require-nonempty-output` found its output empty
+    /// or if the build is external
+    pub(crate) last_build_exit_code: Option<i32>,
 }
 
 impl std::fmt::Display for StateForTesting {
@@ -23,6 +29,127 @@ impl std::fmt::Display for StateForTesting {
 
 pub(crate) const TESTING_MODE: &str = "_TESTING_MODE";
 
+/// Lines longer than this many bytes are truncated
+pub(crate) const MAX_LINE_LENGTH: usize = 64 * 1024;
+pub(crate) const TRUNCATION_MARKER: &str = " [truncated]";
+
+#[derive(Debug, Default)]
+struct LineBuffer {
+    bytes: Vec<u8>,
+    is_truncated: bool,
+}
+
+impl LineBuffer {
+    /// Consumes from `available` up to and including the first line feed.
+    /// Returns the number of bytes consumed and whether a line feed was among them.
+    fn extend(&mut self, available: &[u8]) -> (usize, bool) {
+        let (content, consumed, is_complete) =
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(index) => (&available[..index], index + 1, true),
+                None => (available, available.len(), false),
+            };
+
+        let room = MAX_LINE_LENGTH.saturating_sub(self.bytes.len());
+
+        if content.len() > room {
+            self.is_truncated = true;
+        }
+
+        self.bytes
+            .extend_from_slice(&content[..content.len().min(room)]);
+
+        (consumed, is_complete)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty() && !self.is_truncated
+    }
+
+    fn take(&mut self) -> String {
+        if self.bytes.last() == Some(&b'\r') {
+            self.bytes.pop();
+        }
+
+        // truncation might have split a character
+        let mut line = match String::from_utf8_lossy(&self.bytes) {
+            Cow::Borrowed(line) => line.to_owned(),
+            Cow::Owned(line) => {
+                debug!("replaced invalid UTF-8 in line: {line:?}");
+                line
+            }
+        };
+
+        if self.is_truncated {
+            line.push_str(TRUNCATION_MARKER);
+        }
+
+        *self = Self::default();
+        line
+    }
+}
+
+/// Like [`std::io::Lines`] and [`tokio::io::Lines`], except that memory use is bounded by
+/// [`MAX_LINE_LENGTH`]
+#[derive(Debug)]
+struct BoundedLines<R> {
+    reader: R,
+    buffer: LineBuffer,
+}
+
+impl<R> BoundedLines<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: LineBuffer::default(),
+        }
+    }
+}
+
+impl<R: std::io::BufRead> Iterator for BoundedLines<R> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(error) => return Some(Err(error)),
+            };
+
+            if available.is_empty() {
+                return (!self.buffer.is_empty()).then(|| Ok(self.buffer.take()));
+            }
+
+            let (consumed, is_complete) = self.buffer.extend(available);
+            self.reader.consume(consumed);
+
+            if is_complete {
+                return Some(Ok(self.buffer.take()));
+            }
+        }
+    }
+}
+
+impl<R: tokio::io::AsyncBufRead + Unpin> BoundedLines<R> {
+    async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+
+            if available.is_empty() {
+                return Ok((!self.buffer.is_empty()).then(|| self.buffer.take()));
+            }
+
+            let (consumed, is_complete) = self.buffer.extend(available);
+            self.reader.consume(consumed);
+
+            if is_complete {
+                return Ok(Some(self.buffer.take()));
+            }
+        }
+    }
+}
+
+/// The line handling ends at end of file or at the first read error,
+/// which is the result of the returned join handle
 pub(crate) trait ForStdoutputLine {
     type JoinHandle;
     type FnReturn;
@@ -37,19 +164,21 @@ pub(crate) trait ForStdoutputLine {
 }
 
 impl ForStdoutputLine for std::process::Child {
-    type JoinHandle = std::thread::JoinHandle<()>;
+    type JoinHandle = std::thread::JoinHandle<std::io::Result<()>>;
     type FnReturn = ();
     fn for_stderr_line(
         &mut self,
         mut f: impl (FnMut(&str) -> Self::FnReturn) + Send + 'static,
     ) -> Option<Self::JoinHandle> {
         let child_stderr = self.stderr.take()?;
-        let mut child_stderr_lines = std::io::BufReader::new(child_stderr).lines();
+        let child_stderr_lines = BoundedLines::new(std::io::BufReader::new(child_stderr));
 
         let join_handle = std::thread::spawn(move || {
-            while let Some(Ok(line)) = child_stderr_lines.next() {
-                f(&line);
+            for line in child_stderr_lines {
+                f(&line?);
             }
+
+            Ok(())
         });
 
         Some(join_handle)
@@ -60,12 +189,14 @@ impl ForStdoutputLine for std::process::Child {
         mut f: impl FnMut(&str) + Send + 'static,
     ) -> Option<Self::JoinHandle> {
         let child_stdout = self.stdout.take()?;
-        let mut child_stdout_lines = std::io::BufReader::new(child_stdout).lines();
+        let child_stdout_lines = BoundedLines::new(std::io::BufReader::new(child_stdout));
 
         let join_handle = std::thread::spawn(move || {
-            while let Some(Ok(line)) = child_stdout_lines.next() {
-                f(&line);
+            for line in child_stdout_lines {
+                f(&line?);
             }
+
+            Ok(())
         });
 
         Some(join_handle)
@@ -73,19 +204,21 @@ impl ForStdoutputLine for std::process::Child {
 }
 
 impl ForStdoutputLine for tokio::process::Child {
-    type JoinHandle = tokio::task::JoinHandle<()>;
+    type JoinHandle = tokio::task::JoinHandle<std::io::Result<()>>;
     type FnReturn = BoxFuture<'static, ()>;
     fn for_stderr_line(
         &mut self,
         mut f: impl (FnMut(&str) -> Self::FnReturn) + Send + 'static,
     ) -> Option<Self::JoinHandle> {
         let child_stderr = self.stderr.take()?;
-        let mut stderr_lines = tokio::io::BufReader::new(child_stderr).lines();
+        let mut stderr_lines = BoundedLines::new(tokio::io::BufReader::new(child_stderr));
 
         let join_handle = tokio::spawn(async move {
-            while let Ok(Some(line)) = stderr_lines.next_line().await {
+            while let Some(line) = stderr_lines.next_line().await? {
                 f(&line).await;
             }
+
+            Ok(())
         });
 
         Some(join_handle)
@@ -96,12 +229,14 @@ impl ForStdoutputLine for tokio::process::Child {
         mut f: impl (FnMut(&str) -> Self::FnReturn) + Send + 'static,
     ) -> Option<Self::JoinHandle> {
         let child_stdout = self.stdout.take()?;
-        let mut stdout_lines = tokio::io::BufReader::new(child_stdout).lines();
+        let mut stdout_lines = BoundedLines::new(tokio::io::BufReader::new(child_stdout));
 
         let join_handle = tokio::spawn(async move {
-            while let Ok(Some(line)) = stdout_lines.next_line().await {
+            while let Some(line) = stdout_lines.next_line().await? {
                 f(&line).await;
             }
+
+            Ok(())
         });
 
         Some(join_handle)
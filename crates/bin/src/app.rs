@@ -1,55 +1,114 @@
-use std::{convert::Infallible, path::PathBuf, sync::Arc, vec::Vec};
+use std::{
+    collections::BTreeSet,
+    convert::Infallible,
+    ffi::OsString,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+    vec::Vec,
+};
 
 use nix::{sys::signal::Signal::SIGTERM, unistd::Pid};
-use notify::INotifyWatcher;
 use rxrust::prelude::*;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 use crate::{
-    common::{SERVE_PATH, StateForTesting, TESTING_MODE},
+    common::{CHANGED_PATHS, SERVE_PATH, StateForTesting, TESTING_MODE},
     driver::{
-        browser::{Browser, BrowserCommand, BrowserEvent},
-        build::{BuildCommand, BuildEvent},
-        fswatch::{FsChange, FsWatchCommand, FsWatchEvent},
-        server::{ServeDir, Server, ServerCommand, ServerEvent},
-        signal::{SignalCommand, SignalEvent},
+        browser::{BrowserCommand, BrowserEvent, BrowserOptions, DevBrowser},
+        build::{
+            BuildCommand, BuildEvent, BuildLog, OnChange, Output, OutputStream, Retry,
+            ServePathStyle, Staging,
+        },
+        control::{ControlCommand, ControlEvent, ControlSocket, StateSocket},
+        fswatch::{FsChange, FsWatchCommand, FsWatchEvent, Watcher},
+        server::{ReloadMethod, ServeDir, Server, ServerCommand, ServerEvent, ServerOptions},
+        signal::{SignalCommand, SignalEvent, SignalKind},
+        timer::{TimerCommand, TimerEvent},
     },
 };
 
 #[derive(Default, Debug)]
-enum State {
+enum State<B: DevBrowser> {
     #[default]
     Blank,
     InstallingSignalHandler,
     Initializing {
         initial_build: InitialBuildState,
         server: Option<Server>,
-        watcher: Option<INotifyWatcher>,
+        watcher: Option<Watcher>,
+        browser: InitializingBrowserState<B>,
     },
     SpawningBrowser {
         server: Server,
-        watcher: INotifyWatcher,
+        watcher: Watcher,
     },
     Idle {
         server: Server,
-        watcher: INotifyWatcher,
-        browser: Browser,
+        watcher: Watcher,
+        browser: Option<B>,
+        build_failures: usize,
+    },
+    Batching {
+        changed_paths: BTreeSet<PathBuf>,
+        first_change: Instant,
+        last_change: Instant,
+        server: Server,
+        watcher: Watcher,
+        browser: Option<B>,
+        build_failures: usize,
     },
     BuildSpawning {
+        /// Whether there were changes since, upon which it is to be restarted once spawned
+        is_restarting: bool,
+        /// Including those of changes since, if restarting
+        changed_paths: BTreeSet<PathBuf>,
         server: Server,
-        watcher: INotifyWatcher,
-        browser: Browser,
+        watcher: Watcher,
+        browser: Option<B>,
+        /// Consecutive, preceding this build
+        build_failures: usize,
     },
     BuildWaiting {
         pid: Pid,
         is_restarting: bool,
+        /// Including those of changes since, if restarting
+        changed_paths: BTreeSet<PathBuf>,
+        /// Collected only if exiting on build failures
+        output: Vec<String>,
         server: Server,
-        watcher: INotifyWatcher,
-        browser: Browser,
+        watcher: Watcher,
+        browser: Option<B>,
+        build_failures: usize,
     },
     Reloading {
         server: Server,
-        watcher: INotifyWatcher,
+        watcher: Watcher,
+        /// Those of changes since the build, to be built once reloaded
+        queued_paths: Option<BTreeSet<PathBuf>>,
+    },
+    /// On SIGHUP, the project root is re-resolved and the watcher is recreated,
+    /// after which an initial build follows
+    Restarting {
+        server: Server,
+        /// The previous one, kept until the project root is re-resolved
+        watcher: Option<Watcher>,
+        browser: Option<B>,
+        build_failures: usize,
+    },
+    /// With `--check`, exiting once the initial build has finished and the watcher has been created
+    Checking {
+        /// `None` while running
+        build_succeeded: Option<bool>,
+        /// `None` while being created
+        watcher_created: Option<bool>,
+    },
+    /// Shutdown was requested during a build, which is allowed to finish
+    FinishingBuild {
+        /// `None` while spawning
+        pid: Option<Pid>,
+        server: Server,
+        watcher: Watcher,
     },
     ShuttingDown {
         server: ShuttingDownServerState,
@@ -65,6 +124,14 @@ enum InitialBuildState {
     Succeeded,
 }
 
+#[derive(Debug)]
+enum InitializingBrowserState<B: DevBrowser> {
+    Disabled,
+    Deferred,
+    Spawning,
+    Spawned(B),
+}
+
 #[derive(Debug)]
 enum ShuttingDownServerState {
     Spawning,
@@ -79,12 +146,12 @@ enum ShuttingDownWatcherState {
     Dropped,
 }
 
-impl State {
+impl<B: DevBrowser> State<B> {
     fn shut_down(
         server: Option<Server>,
-        watcher: Option<INotifyWatcher>,
+        watcher: Option<Watcher>,
         code: i32,
-    ) -> (Vec<Control>, State) {
+    ) -> (Vec<Control<B>>, State<B>) {
         let (controls, server) = if let Some(server) = server {
             (
                 vec![Control::Command(Command::Server(ServerCommand::Shutdown(
@@ -113,13 +180,65 @@ impl State {
         )
     }
 
-    fn terminate(code: i32) -> (Vec<Control>, State) {
+    fn server(&self) -> Option<&Server> {
+        match self {
+            State::Initializing { server, .. } => server.as_ref(),
+            State::SpawningBrowser { server, .. }
+            | State::Idle { server, .. }
+            | State::Batching { server, .. }
+            | State::BuildSpawning { server, .. }
+            | State::BuildWaiting { server, .. }
+            | State::Reloading { server, .. }
+            | State::Restarting { server, .. }
+            | State::FinishingBuild { server, .. } => Some(server),
+            State::Blank
+            | State::InstallingSignalHandler
+            | State::Checking { .. }
+            | State::ShuttingDown { .. }
+            | State::Terminating => None,
+        }
+    }
+
+    /// Following a change, rather than initially
+    fn is_building(&self) -> bool {
+        matches!(
+            self,
+            State::BuildSpawning { .. } | State::BuildWaiting { .. }
+        )
+    }
+
+    fn terminate(code: i32) -> (Vec<Control<B>>, State<B>) {
         (vec![Control::Exit(code)], State::Terminating)
     }
+
+    fn check(
+        build_succeeded: Option<bool>,
+        watcher_created: Option<bool>,
+    ) -> (Vec<Control<B>>, State<B>) {
+        match (build_succeeded, watcher_created) {
+            (Some(true), Some(true)) => {
+                info!("check passed");
+                State::terminate(0)
+            }
+            (Some(build_succeeded), Some(watcher_created)) => {
+                warn!(
+                    "check failed: build succeeded: {build_succeeded}, watcher created: {watcher_created}"
+                );
+                State::terminate(1)
+            }
+            _ => (
+                vec![],
+                State::Checking {
+                    build_succeeded,
+                    watcher_created,
+                },
+            ),
+        }
+    }
 }
 
 #[derive(Debug, derive_more::Display)]
-pub(crate) enum Event {
+pub(crate) enum Event<B: DevBrowser> {
     #[display("initializing")]
     Init,
     #[display("server: {_0}")]
@@ -127,23 +246,27 @@ pub(crate) enum Event {
     #[display("build: {_0}")]
     Build(BuildEvent),
     #[display("browser: {_0}")]
-    Browser(BrowserEvent),
+    Browser(BrowserEvent<B>),
     #[display("fs: {_0}")]
     Fs(FsWatchEvent),
     #[display("signal: {_0}")]
     Signal(SignalEvent),
+    #[display("timer: {_0}")]
+    Timer(TimerEvent),
+    #[display("control: {_0}")]
+    Control(ControlEvent),
 }
 
 #[derive(Debug, derive_more::Display)]
-pub(crate) enum Control {
+pub(crate) enum Control<B: DevBrowser> {
     #[display("command: {_0}")]
-    Command(Command),
+    Command(Command<B>),
     #[display("exit: {_0}")]
     Exit(i32),
 }
 
 #[derive(Debug, derive_more::Display)]
-pub(crate) enum Command {
+pub(crate) enum Command<B: DevBrowser> {
     #[display("build: {_0}")]
     Build(BuildCommand),
     #[display("server: {_0}")]
@@ -151,44 +274,462 @@ pub(crate) enum Command {
     #[display("fs: {_0}")]
     Fs(FsWatchCommand),
     #[display("browser: {_0}")]
-    Browser(BrowserCommand),
+    Browser(BrowserCommand<B>),
     #[display("signal: {_0}")]
     Signal(SignalCommand),
+    #[display("timer: {_0}")]
+    Timer(TimerCommand),
+    #[display("control: {_0}")]
+    Control(ControlCommand),
 }
 
 pub(crate) struct App {
     pub(crate) project_root: PathBuf,
+    /// Which is not resolved again on `SIGHUP`
+    pub(crate) explicit_project_root: Option<PathBuf>,
+    /// That of the process, from which the project root is resolved again on `SIGHUP`
+    pub(crate) current_dir: PathBuf,
+    /// Watched in addition to the project root
+    pub(crate) extra_watch_paths: Vec<PathBuf>,
+    /// Of the files whose changes trigger builds; any if empty
+    pub(crate) watch_extensions: Vec<String>,
+    /// Consulted in addition to those at the project root
+    pub(crate) ignore_files: Vec<PathBuf>,
     pub(crate) serve_dir: Arc<ServeDir>,
-    pub(crate) build_command_path: PathBuf,
+    /// Run in sequence; empty if the build is external, in which case the serve path is watched instead
+    pub(crate) build_command_paths: Vec<PathBuf>,
+    pub(crate) build_args: Vec<OsString>,
+    /// Overridden by those that are set regardless, e.g. `SERVE_PATH`
+    pub(crate) build_env: Vec<(String, String)>,
+    /// Whether the build commands inherit no environment
+    pub(crate) build_clean_env: bool,
+    /// The project root unless overridden
+    pub(crate) build_cwd: PathBuf,
+    /// Built into instead of the serve path, which it is exchanged with following successful builds
+    pub(crate) staging_path: Option<PathBuf>,
+    pub(crate) serve_path_style: ServePathStyle,
+    pub(crate) build_output_stream: OutputStream,
+    pub(crate) build_log: Option<BuildLog>,
+    pub(crate) build_timeout: Option<Duration>,
+    pub(crate) build_retry: Option<Retry>,
+    /// Run preceding builds that are due to changes
+    pub(crate) on_change: Option<OnChange>,
+    pub(crate) timestamp_build_output: bool,
+    pub(crate) isolate_build_tmp: bool,
+    /// A build that writes no files to the serve path is considered failed
+    pub(crate) require_nonempty_output: bool,
+    /// Whether to serve what the serve path already contains until the first change
+    pub(crate) no_initial_build: bool,
+    /// Whether a successful build that writes no files or no `index.html` is warned about
+    pub(crate) warn_empty: bool,
+    pub(crate) watch_batch_window: Option<Duration>,
+    pub(crate) poll_interval: Option<Duration>,
+    pub(crate) debounce: Duration,
+    pub(crate) exit_on_build_failure_count: usize,
+    pub(crate) graceful_build_on_shutdown: bool,
+    pub(crate) shutdown_grace: Option<Duration>,
+    pub(crate) reload_only_on_output_change: bool,
+    /// Whether the server is in maintenance while a build following a change runs
+    pub(crate) maintenance_during_build: bool,
+    pub(crate) reload_method: ReloadMethod,
+    /// Written following each successful build
+    pub(crate) manifest_path: Option<PathBuf>,
+    /// Listened at once initialized
+    pub(crate) control_socket: Option<Arc<ControlSocket>>,
+    /// Updated whenever the state would be printed in testing mode
+    pub(crate) state_socket: Option<Arc<StateSocket>>,
+    pub(crate) server_options: ServerOptions,
+    pub(crate) browser_options: BrowserOptions,
+    pub(crate) defer_browser_until_ready: bool,
+    pub(crate) no_browser: bool,
+    /// Whether to exit once the initial build has finished and the watcher has been created,
+    /// without serving or launching the browser
+    pub(crate) check: bool,
 }
 
 impl App {
-    pub(crate) fn run(
+    pub(crate) fn run<B: DevBrowser>(
         self,
-        events: SharedBoxedObservable<'static, Event, Infallible>,
-    ) -> SharedBoxedObservable<'static, Control, Infallible> {
+        events: SharedBoxedObservable<'static, Event<B>, Infallible>,
+    ) -> SharedBoxedObservable<'static, Control<B>, Infallible> {
         let mut state = State::Blank;
 
         events
             .start_with(vec![Event::Init])
-            .map(move |event| self.event_handler(&mut state, event))
+            .map(move |event| {
+                let controls = self.event_handler(&mut state, event);
+                self.update_maintenance(&state);
+                controls
+            })
             .flat_map(Shared::from_iter)
             .tap(|control| info!("{control}"))
             .box_it()
     }
 
-    fn event_handler(&self, state: &mut State, event: Event) -> Vec<Control> {
+    fn update_maintenance<B: DevBrowser>(&self, state: &State<B>) {
+        if self.maintenance_during_build
+            && let Some(server) = state.server()
+        {
+            server.maintenance().set(state.is_building());
+        }
+    }
+
+    fn spawn_build<B: DevBrowser>(&self, changed_paths: &BTreeSet<PathBuf>) -> Control<B> {
+        if self.build_command_paths.is_empty() {
+            unreachable!("the build is external")
+        }
+
+        let staging = self.staging_path.as_ref().map(|path| {
+            Box::new(Staging {
+                path: path.clone(),
+                serve_path: self.serve_dir.path().to_path_buf(),
+            })
+        });
+
+        let build_path = staging
+            .as_ref()
+            .map_or(self.serve_dir.path(), |staging| &staging.path);
+
+        let serve_path = self.serve_path_style.apply(build_path, &self.build_cwd);
+
+        let require_output = self
+            .require_nonempty_output
+            .then(|| build_path.to_path_buf());
+
+        let mut envs = self.build_env.clone();
+
+        envs.push((
+            SERVE_PATH.to_string(),
+            serve_path.to_str().unwrap().to_string(),
+        ));
+
+        if !changed_paths.is_empty() {
+            let changed_paths = changed_paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            envs.push((CHANGED_PATHS.to_string(), changed_paths));
+        }
+
+        Control::Command(Command::Build(BuildCommand::Spawn {
+            paths: self.build_command_paths.clone().into_boxed_slice(),
+            args: self.build_args.clone(),
+            cwd: self.build_cwd.clone(),
+            envs,
+            clean_env: self.build_clean_env,
+            output_stream: self.build_output_stream,
+            log: self.build_log.clone(),
+            timeout: self.build_timeout,
+            retry: self.build_retry.map(Box::new),
+            timestamp_output: self.timestamp_build_output,
+            on_change: self
+                .on_change
+                .clone()
+                .filter(|_| !changed_paths.is_empty())
+                .map(Box::new),
+            staging,
+            isolate_tmp: self.isolate_build_tmp,
+            require_output,
+            warn_empty: self.warn_empty.then(|| self.serve_dir.path().to_path_buf()),
+        }))
+    }
+
+    /// The serve path is watched instead of the project if the build is external
+    fn watch_init(&self, project_root: PathBuf) -> FsWatchCommand {
+        if self.build_command_paths.is_empty() {
+            FsWatchCommand::Init {
+                path: self.serve_dir.path().to_path_buf(),
+                extra_paths: Vec::new(),
+                extensions: Vec::new(),
+                ignore_files: Vec::new(),
+                poll_interval: self.poll_interval,
+                respect_ignores: false,
+            }
+        } else {
+            FsWatchCommand::Init {
+                path: project_root,
+                extra_paths: self.extra_watch_paths.clone(),
+                extensions: self.watch_extensions.clone(),
+                ignore_files: self.ignore_files.clone(),
+                poll_interval: self.poll_interval,
+                respect_ignores: true,
+            }
+        }
+    }
+
+    fn spawn_browser<B: DevBrowser>(&self, server: &Server) -> Control<B> {
+        Control::Command(Command::Browser(BrowserCommand::Spawn {
+            url: server.url(),
+            options: Box::new(self.browser_options.clone()),
+        }))
+    }
+
+    /// How long until a batch of changes is to be built, if at all
+    fn batch_remaining(&self, first_change: Instant, last_change: Instant) -> Option<Duration> {
+        let window = self
+            .watch_batch_window
+            .unwrap_or_default()
+            .saturating_sub(first_change.elapsed());
+
+        let debounce = self.debounce.saturating_sub(last_change.elapsed());
+        Some(window.max(debounce)).filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Builds, or if the build is external, reloads
+    fn handle_changes<B: DevBrowser>(
+        &self,
+        changed_paths: BTreeSet<PathBuf>,
+        server: Server,
+        watcher: Watcher,
+        browser: Option<B>,
+        build_failures: usize,
+    ) -> (Vec<Control<B>>, State<B>) {
+        if self.build_command_paths.is_empty() {
+            return self.reload(server, watcher, browser);
+        }
+
+        (
+            vec![self.spawn_build(&changed_paths)],
+            State::BuildSpawning {
+                is_restarting: false,
+                changed_paths,
+                server,
+                watcher,
+                browser,
+                build_failures,
+            },
+        )
+    }
+
+    fn reload<B: DevBrowser>(
+        &self,
+        mut server: Server,
+        watcher: Watcher,
+        browser: Option<B>,
+    ) -> (Vec<Control<B>>, State<B>) {
+        if let Some(manifest_path) = &self.manifest_path {
+            server.write_manifest(manifest_path);
+        }
+
+        if self.reload_only_on_output_change && !server.update_output_digest() {
+            info!("build output unchanged; not reloading");
+
+            return (
+                vec![],
+                State::Idle {
+                    server,
+                    watcher,
+                    browser,
+                    build_failures: 0,
+                },
+            );
+        }
+
+        let mut controls = Vec::new();
+
+        if self.reload_method.uses_script() {
+            controls.push(Control::Command(Command::Server(ServerCommand::Reload(
+                server.reload_trigger(),
+            ))));
+        }
+
+        match browser {
+            Some(browser) if self.reload_method.uses_cdp() => {
+                controls.push(Control::Command(Command::Browser(BrowserCommand::Reload(
+                    browser,
+                ))));
+
+                (
+                    controls,
+                    State::Reloading {
+                        server,
+                        watcher,
+                        queued_paths: None,
+                    },
+                )
+            }
+            browser => (
+                controls,
+                State::Idle {
+                    server,
+                    watcher,
+                    browser,
+                    build_failures: 0,
+                },
+            ),
+        }
+    }
+
+    fn finish_build<B: DevBrowser>(
+        &self,
+        pid: Option<Pid>,
+        server: Server,
+        watcher: Watcher,
+    ) -> (Vec<Control<B>>, State<B>) {
+        info!("waiting for the build to finish before shutting down");
+
+        let controls = self
+            .shutdown_grace
+            .map(|grace| Control::Command(Command::Timer(TimerCommand::Wait(grace))))
+            .into_iter()
+            .collect();
+
+        (
+            controls,
+            State::FinishingBuild {
+                pid,
+                server,
+                watcher,
+            },
+        )
+    }
+
+    /// Initially and following each build that was not restarted,
+    /// printed in testing mode and provided at the state socket
+    fn report_state<B: DevBrowser>(
+        &self,
+        server: &Server,
+        browser: Option<&B>,
+        last_build_exit_code: Option<i32>,
+    ) {
+        let state_for_testing = StateForTesting {
+            serve_path: self.serve_dir.path().to_path_buf(),
+            serve_port: server.address().port(),
+            browser_debugging_address: browser.map(B::debugging_address),
+            browser_pid: browser.and_then(B::pid),
+            last_build_exit_code,
+        };
+
+        if let Some(state_socket) = &self.state_socket {
+            state_socket.update(state_for_testing.clone());
+        }
+
+        if std::env::var(TESTING_MODE).is_ok() {
+            println!("{state_for_testing}");
+        }
+    }
+
+    fn idle<B: DevBrowser>(
+        &self,
+        mut server: Server,
+        watcher: Watcher,
+        browser: Option<B>,
+    ) -> (Vec<Control<B>>, State<B>) {
+        server.readiness().set();
+
+        if self.reload_only_on_output_change {
+            server.update_output_digest();
+        }
+
+        if let Some(manifest_path) = &self.manifest_path {
+            server.write_manifest(manifest_path);
+        }
+
+        let initial_build_exit_code =
+            (!self.build_command_paths.is_empty() && !self.no_initial_build).then_some(0);
+        self.report_state(&server, browser.as_ref(), initial_build_exit_code);
+
+        (
+            vec![],
+            State::Idle {
+                server,
+                watcher,
+                browser,
+                build_failures: 0,
+            },
+        )
+    }
+
+    fn event_handler<B: DevBrowser>(
+        &self,
+        state: &mut State<B>,
+        event: Event<B>,
+    ) -> Vec<Control<B>> {
         match &event {
-            event @ Event::Fs(FsWatchEvent::EventError(_)) => {
+            event @ (Event::Fs(FsWatchEvent::EventError(_))
+            | Event::Build(
+                BuildEvent::TimedOut(_) | BuildEvent::ReadError(_, _) | BuildEvent::SignalError(_),
+            )
+            | Event::Control(ControlEvent::Error(_))
+            | Event::Build(
+                BuildEvent::OnChangeSpawnError(_) | BuildEvent::SpawnError(_),
+            )
+            | Event::Browser(BrowserEvent::SpawnError(_))) => {
+                warn!("event: {event}");
+            }
+            event @ Event::Build(BuildEvent::OnChangeExited { exit_code, lines }) => {
+                if *exit_code == Some(0) {
+                    info!("event: {event}");
+                } else {
+                    warn!("event: {event}");
+                }
+
+                for (output, line) in lines {
+                    info!(
+                        source = "on-change",
+                        stream = %output,
+                        line = %line,
+                        "event: build: on-change output line"
+                    );
+                }
+            }
+            Event::Build(BuildEvent::OutputLine {
+                output: output @ Output::Out,
+                line,
+                command_index,
+                read_at,
+            }) => {
+                info!(
+                    source = "build",
+                    stream = %output,
+                    command = command_index,
+                    read_at = read_at.map(tracing::field::display),
+                    line = %line,
+                    "event: build: output line"
+                );
+            }
+            Event::Build(BuildEvent::OutputLine {
+                output: output @ Output::Err,
+                line,
+                command_index,
+                read_at,
+            }) => {
+                warn!(
+                    source = "build",
+                    stream = %output,
+                    command = command_index,
+                    read_at = read_at.map(tracing::field::display),
+                    line = %line,
+                    "event: build: output line"
+                );
+            }
+            event @ Event::Build(BuildEvent::Exited(exit_code)) if *exit_code != Some(0) => {
+                info!("event: {event}");
+
+                match exit_code {
+                    Some(exit_code) => warn!("build command exit status: {exit_code}"),
+                    None => warn!("build command exit status: none, terminated by a signal"),
+                }
+
+                if self.staging_path.is_some() {
+                    info!("not promoting the staged build output; the previous remains served");
+                }
+            }
+            event @ Event::Build(BuildEvent::EmptyOutput) => {
                 warn!("event: {event}");
+                warn!("build considered failed, as its output is empty");
+
+                if self.staging_path.is_some() {
+                    info!("not promoting the staged build output; the previous remains served");
+                }
             }
             _ => {
                 info!("event: {event}");
             }
         }
 
-        let build_command_path = self.build_command_path.clone();
-
         replace_with::replace_with_or_abort_and_return(state, |state| match (state, event) {
             (State::Blank, Event::Init) => (
                 vec![Control::Command(Command::Signal(
@@ -196,26 +737,65 @@ impl App {
                 ))],
                 State::InstallingSignalHandler,
             ),
-            (State::InstallingSignalHandler, Event::Signal(SignalEvent::HandlerInstalled)) => (
-                vec![
-                    Control::Command(Command::Build(BuildCommand::Spawn {
-                        path: build_command_path.clone(),
-                        envs: vec![(
-                            SERVE_PATH.to_string(),
-                            self.serve_dir.path().to_str().unwrap().to_string(),
-                        )],
-                    })),
+            (State::InstallingSignalHandler, Event::Signal(SignalEvent::HandlerInstalled))
+                if self.check =>
+            {
+                let watch_init = self.watch_init(self.project_root.clone());
+
+                (
+                    vec![
+                        self.spawn_build(&BTreeSet::new()),
+                        Control::Command(Command::Fs(watch_init)),
+                    ],
+                    State::Checking {
+                        build_succeeded: None,
+                        watcher_created: None,
+                    },
+                )
+            }
+            (State::InstallingSignalHandler, Event::Signal(SignalEvent::HandlerInstalled)) => {
+                let (mut controls, initial_build) =
+                    if self.build_command_paths.is_empty() || self.no_initial_build {
+                        (vec![], InitialBuildState::Succeeded)
+                    } else {
+                        (
+                            vec![self.spawn_build(&BTreeSet::new())],
+                            InitialBuildState::Pending,
+                        )
+                    };
+
+                let watch_init = self.watch_init(self.project_root.clone());
+
+                controls.extend([
                     Control::Command(Command::Server(ServerCommand::Spawn(
                         self.serve_dir.clone(),
+                        Box::new(self.server_options.clone()),
                     ))),
-                    Control::Command(Command::Fs(FsWatchCommand::Init(self.project_root.clone()))),
-                ],
-                State::Initializing {
-                    initial_build: InitialBuildState::Pending,
-                    server: None,
-                    watcher: None,
-                },
-            ),
+                    Control::Command(Command::Fs(watch_init)),
+                ]);
+
+                controls.extend(self.control_socket.clone().map(|socket| {
+                    Control::Command(Command::Control(ControlCommand::Listen(socket)))
+                }));
+
+                controls.extend(self.state_socket.clone().map(|socket| {
+                    Control::Command(Command::Control(ControlCommand::ServeState(socket)))
+                }));
+
+                (
+                    controls,
+                    State::Initializing {
+                        initial_build,
+                        server: None,
+                        watcher: None,
+                        browser: if self.no_browser {
+                            InitializingBrowserState::Disabled
+                        } else {
+                            InitializingBrowserState::Deferred
+                        },
+                    },
+                )
+            }
             (State::InstallingSignalHandler, Event::Signal(SignalEvent::HandlerInstallFail(_))) => {
                 State::terminate(1)
             }
@@ -227,17 +807,62 @@ impl App {
             (State::Blank, _) => unreachable!(),
             (_, Event::Init) => unreachable!(),
 
+            (
+                State::Checking {
+                    build_succeeded, ..
+                },
+                Event::Fs(
+                    FsWatchEvent::WatcherCreationError(_)
+                    | FsWatchEvent::WatcherWatchError(_)
+                    | FsWatchEvent::Git2Error(_),
+                ),
+            ) => State::check(build_succeeded, Some(false)),
+            (
+                State::Checking {
+                    build_succeeded,
+                    watcher_created,
+                },
+                Event::Fs(FsWatchEvent::Watching(watcher)),
+            ) => {
+                drop(watcher);
+                // following a watch error
+                State::check(build_succeeded, Some(watcher_created.unwrap_or(true)))
+            }
+            (
+                State::Checking {
+                    watcher_created, ..
+                },
+                Event::Build(BuildEvent::Exited(exit_code)),
+            ) => State::check(Some(exit_code == Some(0)), watcher_created),
+            (
+                State::Checking {
+                    watcher_created, ..
+                },
+                Event::Build(
+                    BuildEvent::SpawnError(_)
+                    | BuildEvent::EmptyOutput
+                    | BuildEvent::PromotionError(_)
+                    | BuildEvent::WaitError(_),
+                ),
+            ) => State::check(Some(false), watcher_created),
+            (
+                State::Checking { .. },
+                Event::Signal(SignalEvent::Received(SignalKind::Sigint | SignalKind::Sigterm)),
+            ) => State::terminate(1),
+            (state @ State::Checking { .. }, _) => (vec![], state),
+
             (
                 State::Initializing {
                     server, watcher, ..
                 },
-                Event::Signal(SignalEvent::Received(_)),
+                Event::Signal(SignalEvent::Received(SignalKind::Sigint | SignalKind::Sigterm)),
             ) => State::shut_down(server, watcher, 0),
             (
                 State::Initializing {
                     initial_build: InitialBuildState::Pending,
                     server,
                     watcher,
+                    ..
                 },
                 Event::Build(BuildEvent::SpawnError(_)),
             ) => State::shut_down(server, watcher, 1),
@@ -246,9 +871,16 @@ impl App {
                     initial_build: InitialBuildState::Pending,
                     server: Some(server),
                     watcher,
+                    ..
                 },
-                Event::Build(BuildEvent::WaitError(_)),
+                Event::Build(BuildEvent::WaitError(_) | BuildEvent::PromotionError(_)),
             ) => State::shut_down(Some(server), watcher, 1),
+            (
+                State::BuildWaiting {
+                    server, watcher, ..
+                },
+                Event::Build(BuildEvent::WaitError(_) | BuildEvent::PromotionError(_)),
+            ) => State::shut_down(Some(server), Some(watcher), 1),
             (
                 state @ State::Initializing {
                     initial_build: InitialBuildState::Pending,
@@ -267,6 +899,9 @@ impl App {
                     initial_build: InitialBuildState::Pending,
                     server: Some(server),
                     watcher: Some(watcher),
+                    browser:
+                        browser @ (InitializingBrowserState::Disabled
+                        | InitializingBrowserState::Deferred),
                 },
                 Event::Build(BuildEvent::Exited(Some(0))),
             )
@@ -275,6 +910,9 @@ impl App {
                     initial_build: InitialBuildState::Succeeded,
                     server: None,
                     watcher: Some(watcher),
+                    browser:
+                        browser @ (InitializingBrowserState::Disabled
+                        | InitializingBrowserState::Deferred),
                 },
                 Event::Server(ServerEvent::Spawn(server)),
             )
@@ -283,19 +921,54 @@ impl App {
                     initial_build: InitialBuildState::Succeeded,
                     server: Some(server),
                     watcher: None,
+                    browser:
+                        browser @ (InitializingBrowserState::Disabled
+                        | InitializingBrowserState::Deferred),
                 },
                 Event::Fs(FsWatchEvent::Watching(watcher)),
-            ) => (
-                vec![Control::Command(Command::Browser(BrowserCommand::Spawn {
-                    url: format!("http://{}", server.address()),
-                }))],
-                State::SpawningBrowser { server, watcher },
-            ),
+            ) => match browser {
+                InitializingBrowserState::Deferred => (
+                    vec![self.spawn_browser(&server)],
+                    State::SpawningBrowser { server, watcher },
+                ),
+                InitializingBrowserState::Disabled => self.idle(server, watcher, None),
+                InitializingBrowserState::Spawning | InitializingBrowserState::Spawned(_) => {
+                    unreachable!()
+                }
+            },
+            (
+                State::Initializing {
+                    initial_build: InitialBuildState::Pending,
+                    server: Some(server),
+                    watcher: Some(watcher),
+                    browser: InitializingBrowserState::Spawned(browser),
+                },
+                Event::Build(BuildEvent::Exited(Some(0))),
+            )
+            | (
+                State::Initializing {
+                    initial_build: InitialBuildState::Succeeded,
+                    server: Some(server),
+                    watcher: None,
+                    browser: InitializingBrowserState::Spawned(browser),
+                },
+                Event::Fs(FsWatchEvent::Watching(watcher)),
+            )
+            | (
+                State::Initializing {
+                    initial_build: InitialBuildState::Succeeded,
+                    server: Some(server),
+                    watcher: Some(watcher),
+                    browser: InitializingBrowserState::Spawning,
+                },
+                Event::Browser(BrowserEvent::Spawn(browser)),
+            ) => self.idle(server, watcher, Some(browser)),
             (
                 State::Initializing {
                     initial_build: InitialBuildState::Pending,
                     server,
                     watcher,
+                    browser,
                 },
                 Event::Build(BuildEvent::Exited(Some(0))),
             ) => (
@@ -304,6 +977,7 @@ impl App {
                     initial_build: InitialBuildState::Succeeded,
                     server,
                     watcher,
+                    browser,
                 },
             ),
             (
@@ -311,8 +985,9 @@ impl App {
                     initial_build: InitialBuildState::Pending,
                     server,
                     watcher,
+                    ..
                 },
-                Event::Build(BuildEvent::Exited(None | Some(_))),
+                Event::Build(BuildEvent::Exited(None | Some(_)) | BuildEvent::EmptyOutput),
             ) => State::shut_down(server, watcher, 1),
             (
                 State::Initializing {
@@ -320,16 +995,28 @@ impl App {
                         initial_build @ (InitialBuildState::Pending | InitialBuildState::Succeeded),
                     server: None,
                     watcher,
+                    browser,
                 },
                 Event::Server(ServerEvent::Spawn(server)),
-            ) => (
-                vec![],
-                State::Initializing {
-                    initial_build,
-                    server: Some(server),
-                    watcher,
-                },
-            ),
+            ) => {
+                let (controls, browser) = match browser {
+                    InitializingBrowserState::Deferred if !self.defer_browser_until_ready => (
+                        vec![self.spawn_browser(&server)],
+                        InitializingBrowserState::Spawning,
+                    ),
+                    browser => (vec![], browser),
+                };
+
+                (
+                    controls,
+                    State::Initializing {
+                        initial_build,
+                        server: Some(server),
+                        watcher,
+                        browser,
+                    },
+                )
+            }
             (
                 State::Initializing {
                     server,
@@ -343,6 +1030,7 @@ impl App {
                     initial_build,
                     server,
                     watcher: None,
+                    browser,
                 },
                 Event::Fs(FsWatchEvent::Watching(watcher)),
             ) => (
@@ -351,6 +1039,7 @@ impl App {
                     initial_build,
                     server,
                     watcher: Some(watcher),
+                    browser,
                 },
             ),
             (
@@ -362,18 +1051,174 @@ impl App {
                 Event::Fs(FsWatchEvent::WatcherWatchError(_)),
             ) => State::shut_down(server, watcher, 1),
             (
-                State::SpawningBrowser { server, watcher }
-                | State::Idle {
-                    server, watcher, ..
-                }
-                | State::BuildSpawning {
-                    server, watcher, ..
-                }
-                | State::BuildWaiting {
-                    server, watcher, ..
-                }
-                | State::Reloading {
-                    server, watcher, ..
+                State::Initializing {
+                    initial_build,
+                    server,
+                    watcher,
+                    browser: InitializingBrowserState::Spawning,
+                },
+                Event::Browser(BrowserEvent::Spawn(browser)),
+            ) => (
+                vec![],
+                State::Initializing {
+                    initial_build,
+                    server,
+                    watcher,
+                    browser: InitializingBrowserState::Spawned(browser),
+                },
+            ),
+            (
+                State::Initializing {
+                    server,
+                    watcher,
+                    browser: InitializingBrowserState::Spawning,
+                    ..
+                },
+                Event::Browser(BrowserEvent::SpawnError(_)),
+            ) => State::shut_down(server, watcher, 1),
+            (
+                State::Idle {
+                    server,
+                    watcher,
+                    browser,
+                    build_failures,
+                },
+                Event::Signal(SignalEvent::Received(SignalKind::Sighup)),
+            ) => (
+                vec![Control::Command(Command::Fs(
+                    FsWatchCommand::ResolveProjectRoot {
+                        explicit: self.explicit_project_root.clone(),
+                        origin: self.current_dir.clone(),
+                    },
+                ))],
+                State::Restarting {
+                    server,
+                    watcher: Some(watcher),
+                    browser,
+                    build_failures,
+                },
+            ),
+            (
+                State::Restarting {
+                    server,
+                    watcher: Some(watcher),
+                    browser,
+                    build_failures,
+                },
+                Event::Fs(FsWatchEvent::ProjectRootResolved(project_root)),
+            ) => {
+                info!("restarting, recreating the watcher");
+                drop(watcher);
+
+                (
+                    vec![Control::Command(Command::Fs(self.watch_init(project_root)))],
+                    State::Restarting {
+                        server,
+                        watcher: None,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
+            (
+                State::Restarting {
+                    server,
+                    watcher: Some(watcher),
+                    browser,
+                    build_failures,
+                },
+                Event::Fs(FsWatchEvent::ProjectRootError(error)),
+            ) => {
+                warn!("not restarting: {error:#}");
+
+                (
+                    vec![],
+                    State::Idle {
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
+            (
+                state @ (State::Initializing { .. }
+                | State::SpawningBrowser { .. }
+                | State::Batching { .. }
+                | State::BuildSpawning { .. }
+                | State::BuildWaiting { .. }
+                | State::Reloading { .. }
+                | State::Restarting { .. }
+                | State::FinishingBuild { .. }),
+                Event::Signal(SignalEvent::Received(SignalKind::Sighup)),
+            ) => {
+                warn!("SIGHUP ignored, as restarting is only possible while idle");
+                (vec![], state)
+            }
+            (
+                State::Restarting {
+                    server,
+                    watcher: None,
+                    browser,
+                    build_failures,
+                },
+                Event::Fs(FsWatchEvent::Watching(watcher)),
+            ) => self.handle_changes(BTreeSet::new(), server, watcher, browser, build_failures),
+            (
+                State::Restarting {
+                    server, watcher, ..
+                },
+                Event::Fs(
+                    FsWatchEvent::WatcherCreationError(_)
+                    | FsWatchEvent::WatcherWatchError(_)
+                    | FsWatchEvent::Git2Error(_),
+                ),
+            ) => State::shut_down(Some(server), watcher, 1),
+            (
+                State::Restarting {
+                    server, watcher, ..
+                },
+                Event::Signal(SignalEvent::Received(_)),
+            ) => State::shut_down(Some(server), watcher, 0),
+            // shutdown was requested while the project root was being re-resolved
+            (
+                state,
+                Event::Fs(FsWatchEvent::ProjectRootResolved(_) | FsWatchEvent::ProjectRootError(_)),
+            ) => (vec![], state),
+            (
+                State::BuildSpawning {
+                    server, watcher, ..
+                },
+                Event::Signal(SignalEvent::Received(_)),
+            ) if self.graceful_build_on_shutdown => self.finish_build(None, server, watcher),
+            (
+                State::BuildWaiting {
+                    pid,
+                    server,
+                    watcher,
+                    ..
+                },
+                Event::Signal(SignalEvent::Received(_)),
+            ) if self.graceful_build_on_shutdown => self.finish_build(Some(pid), server, watcher),
+            (
+                State::SpawningBrowser { server, watcher }
+                | State::Idle {
+                    server, watcher, ..
+                }
+                | State::Batching {
+                    server, watcher, ..
+                }
+                | State::BuildSpawning {
+                    server, watcher, ..
+                }
+                | State::BuildWaiting {
+                    server, watcher, ..
+                }
+                | State::Reloading {
+                    server, watcher, ..
+                }
+                | State::FinishingBuild {
+                    server, watcher, ..
                 },
                 Event::Signal(SignalEvent::Received(_)),
             ) => State::shut_down(Some(server), Some(watcher), 0),
@@ -383,33 +1228,17 @@ impl App {
                 }
                 | State::SpawningBrowser { .. }
                 | State::Idle { .. }
+                | State::Batching { .. }
                 | State::BuildSpawning { .. }
-                | State::BuildWaiting { .. }),
+                | State::BuildWaiting { .. }
+                | State::Restarting { .. }
+                | State::FinishingBuild { .. }),
                 Event::Fs(FsWatchEvent::EventError(_)),
             ) => (vec![], state),
             (
                 State::SpawningBrowser { server, watcher },
                 Event::Browser(BrowserEvent::Spawn(browser)),
-            ) => {
-                if std::env::var(TESTING_MODE).is_ok() {
-                    let state_for_testing = StateForTesting {
-                        serve_path: self.serve_dir.path().to_path_buf(),
-                        serve_port: server.address().port(),
-                        browser_debugging_address: browser.debugging_address(),
-                        browser_pid: browser.pid(),
-                    };
-                    println!("{state_for_testing}");
-                }
-
-                (
-                    vec![],
-                    State::Idle {
-                        server,
-                        watcher,
-                        browser,
-                    },
-                )
-            }
+            ) => self.idle(server, watcher, Some(browser)),
             (
                 State::SpawningBrowser {
                     server, watcher, ..
@@ -421,162 +1250,548 @@ impl App {
                     server,
                     browser,
                     watcher,
+                    build_failures,
                 },
                 Event::Fs(FsWatchEvent::Change(FsChange {
-                    is_ignored: false, ..
+                    path,
+                    ignored: None,
+                    ..
                 })),
-            ) => (
-                vec![Control::Command(Command::Build(BuildCommand::Spawn {
-                    path: build_command_path.clone(),
-                    envs: vec![(
-                        SERVE_PATH.to_string(),
-                        self.serve_dir.path().to_str().unwrap().to_string(),
-                    )],
-                }))],
-                State::BuildSpawning {
+            ) => {
+                let changed_paths = BTreeSet::from([path]);
+                let now = Instant::now();
+
+                match self.batch_remaining(now, now) {
+                    Some(remaining) => (
+                        vec![Control::Command(Command::Timer(TimerCommand::Wait(
+                            remaining,
+                        )))],
+                        State::Batching {
+                            changed_paths,
+                            first_change: now,
+                            last_change: now,
+                            server,
+                            browser,
+                            watcher,
+                            build_failures,
+                        },
+                    ),
+                    None => {
+                        self.handle_changes(changed_paths, server, watcher, browser, build_failures)
+                    }
+                }
+            }
+            (
+                State::Idle {
+                    server,
+                    browser,
+                    watcher,
+                    build_failures,
+                },
+                Event::Control(ControlEvent::Rebuild),
+            ) => self.handle_changes(BTreeSet::new(), server, watcher, browser, build_failures),
+            (
+                State::Batching {
+                    mut changed_paths,
+                    first_change,
+                    server,
+                    watcher,
+                    browser,
+                    build_failures,
+                    ..
+                },
+                Event::Fs(FsWatchEvent::Change(FsChange {
+                    path,
+                    ignored: None,
+                    ..
+                })),
+            ) => {
+                changed_paths.insert(path);
+
+                (
+                    vec![],
+                    State::Batching {
+                        changed_paths,
+                        first_change,
+                        last_change: Instant::now(),
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
+            (
+                State::Batching {
+                    changed_paths,
+                    first_change,
+                    last_change,
                     server,
+                    watcher,
                     browser,
+                    build_failures,
+                },
+                Event::Timer(TimerEvent::Elapsed),
+            ) if let Some(remaining) = self.batch_remaining(first_change, last_change) => (
+                vec![Control::Command(Command::Timer(TimerCommand::Wait(
+                    remaining,
+                )))],
+                State::Batching {
+                    changed_paths,
+                    first_change,
+                    last_change,
+                    server,
                     watcher,
+                    browser,
+                    build_failures,
                 },
             ),
+            (
+                State::Batching {
+                    changed_paths,
+                    server,
+                    watcher,
+                    browser,
+                    build_failures,
+                    ..
+                },
+                Event::Timer(TimerEvent::Elapsed),
+            ) => {
+                let paths = changed_paths
+                    .iter()
+                    .map(|path| format!("{path:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                info!("batch of {} changed paths: {paths}", changed_paths.len());
+                self.handle_changes(changed_paths, server, watcher, browser, build_failures)
+            }
             (
                 State::BuildSpawning {
                     server, watcher, ..
+                }
+                | State::BuildWaiting {
+                    server, watcher, ..
                 },
                 Event::Build(BuildEvent::SpawnError(_)),
             ) => State::shut_down(Some(server), Some(watcher), 1),
             (
                 State::BuildSpawning {
+                    is_restarting,
+                    changed_paths,
                     server,
                     watcher,
                     browser,
+                    build_failures,
                 },
                 Event::Build(BuildEvent::Spawn(pid)),
-            ) => (
-                vec![],
+            ) => {
+                let controls = if is_restarting {
+                    vec![Control::Command(Command::Build(BuildCommand::Signal(
+                        pid, SIGTERM,
+                    )))]
+                } else {
+                    vec![]
+                };
+
+                (
+                    controls,
+                    State::BuildWaiting {
+                        pid,
+                        is_restarting,
+                        changed_paths,
+                        output: vec![],
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
+            (
                 State::BuildWaiting {
-                    pid,
-                    is_restarting: false,
+                    is_restarting,
+                    changed_paths,
+                    output,
                     server,
                     watcher,
                     browser,
+                    build_failures,
+                    ..
                 },
-            ),
+                Event::Build(BuildEvent::Spawn(pid)),
+            ) => {
+                // the preceding command of the sequence exited before it could be terminated
+                let controls = if is_restarting {
+                    vec![Control::Command(Command::Build(BuildCommand::Signal(
+                        pid, SIGTERM,
+                    )))]
+                } else {
+                    vec![]
+                };
+
+                (
+                    controls,
+                    State::BuildWaiting {
+                        pid,
+                        is_restarting,
+                        changed_paths,
+                        output,
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
             (
                 State::BuildWaiting {
                     pid,
-                    is_restarting: false,
+                    is_restarting,
+                    mut changed_paths,
+                    output,
                     server,
                     watcher,
                     browser,
+                    build_failures,
                 },
-                Event::Fs(FsWatchEvent::Change(FsChange {
-                    is_ignored: false, ..
-                })),
-            ) => (
-                vec![Control::Command(Command::Build(BuildCommand::Signal(
-                    pid, SIGTERM,
-                )))],
+                event @ (Event::Fs(FsWatchEvent::Change(FsChange { ignored: None, .. }))
+                | Event::Control(ControlEvent::Rebuild)),
+            ) => {
+                if let Event::Fs(FsWatchEvent::Change(FsChange { path, .. })) = event {
+                    changed_paths.insert(path);
+                }
+
+                let controls = if is_restarting {
+                    vec![]
+                } else {
+                    vec![Control::Command(Command::Build(BuildCommand::Signal(
+                        pid, SIGTERM,
+                    )))]
+                };
+
+                (
+                    controls,
+                    State::BuildWaiting {
+                        pid,
+                        is_restarting: true,
+                        changed_paths,
+                        output,
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
+            (
                 State::BuildWaiting {
                     pid,
-                    is_restarting: true,
+                    is_restarting,
+                    changed_paths,
+                    mut output,
                     server,
                     watcher,
                     browser,
+                    build_failures,
                 },
-            ),
+                Event::Build(BuildEvent::OutputLine {
+                    output: stream,
+                    line,
+                    ..
+                }),
+            ) if self.exit_on_build_failure_count > 0 => {
+                output.push(format!("{stream}: {line}"));
+
+                (
+                    vec![],
+                    State::BuildWaiting {
+                        pid,
+                        is_restarting,
+                        changed_paths,
+                        output,
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
             (
                 state @ (State::Initializing {
                     initial_build: InitialBuildState::Pending,
                     ..
                 }
                 | State::BuildSpawning { .. }
-                | State::BuildWaiting { .. }),
-                Event::Build(BuildEvent::OutputLine { .. }),
+                | State::BuildWaiting { .. }
+                | State::FinishingBuild { .. }),
+                Event::Build(
+                    BuildEvent::OutputLine { .. }
+                    | BuildEvent::TimedOut(_)
+                    | BuildEvent::ReadError(_, _)
+                    | BuildEvent::OnChangeExited { .. }
+                    | BuildEvent::OnChangeSpawnError(_),
+                ),
             ) => (vec![], state),
-            (state, Event::Fs(FsWatchEvent::Change(_))) => (vec![], state),
             (
-                State::BuildWaiting {
+                State::BuildSpawning {
+                    is_restarting: true,
+                    changed_paths,
+                    server,
+                    watcher,
+                    browser,
+                    build_failures,
+                },
+                Event::Build(BuildEvent::Skipped),
+            ) => (
+                vec![self.spawn_build(&changed_paths)],
+                State::BuildSpawning {
                     is_restarting: false,
+                    changed_paths,
                     server,
+                    watcher,
                     browser,
+                    build_failures,
+                },
+            ),
+            (
+                State::BuildSpawning {
+                    is_restarting: false,
+                    server,
                     watcher,
+                    browser,
+                    build_failures,
                     ..
                 },
-                Event::Build(BuildEvent::Exited(Some(0))),
+                Event::Build(BuildEvent::Skipped),
             ) => (
-                vec![Control::Command(Command::Browser(BrowserCommand::Reload(
+                vec![],
+                State::Idle {
+                    server,
+                    watcher,
                     browser,
-                )))],
-                State::Reloading { server, watcher },
+                    build_failures,
+                },
             ),
-            (state @ State::BuildWaiting { .. }, Event::Build(BuildEvent::SignalSent(_, _))) => {
-                (vec![], state)
+            (
+                State::BuildSpawning {
+                    mut changed_paths,
+                    server,
+                    watcher,
+                    browser,
+                    build_failures,
+                    ..
+                },
+                event @ (Event::Fs(FsWatchEvent::Change(FsChange { ignored: None, .. }))
+                | Event::Control(ControlEvent::Rebuild)),
+            ) => {
+                if let Event::Fs(FsWatchEvent::Change(FsChange { path, .. })) = event {
+                    changed_paths.insert(path);
+                }
+
+                (
+                    vec![],
+                    State::BuildSpawning {
+                        is_restarting: true,
+                        changed_paths,
+                        server,
+                        watcher,
+                        browser,
+                        build_failures,
+                    },
+                )
+            }
+            (
+                State::Reloading {
+                    server,
+                    watcher,
+                    queued_paths,
+                },
+                event @ (Event::Fs(FsWatchEvent::Change(FsChange { ignored: None, .. }))
+                | Event::Control(ControlEvent::Rebuild)),
+            ) => {
+                let mut queued_paths = queued_paths.unwrap_or_default();
+
+                if let Event::Fs(FsWatchEvent::Change(FsChange { path, .. })) = event {
+                    queued_paths.insert(path);
+                }
+
+                (
+                    vec![],
+                    State::Reloading {
+                        server,
+                        watcher,
+                        queued_paths: Some(queued_paths),
+                    },
+                )
+            }
+            (state, Event::Fs(FsWatchEvent::Change(_))) => (vec![], state),
+            (state, Event::Control(_)) => (vec![], state),
+            (
+                State::BuildWaiting {
+                    is_restarting: false,
+                    server,
+                    browser,
+                    watcher,
+                    ..
+                },
+                Event::Build(BuildEvent::Exited(Some(0))),
+            ) => {
+                self.report_state(&server, browser.as_ref(), Some(0));
+                self.reload(server, watcher, browser)
             }
+            // the process might have exited in the meantime, which is reported regardless
+            (
+                state @ (State::BuildSpawning { .. } | State::BuildWaiting { .. }),
+                Event::Build(BuildEvent::SignalSent(_, _) | BuildEvent::SignalError(_)),
+            ) => (vec![], state),
             (
                 State::BuildWaiting {
                     is_restarting: true,
+                    changed_paths,
                     server,
                     watcher,
                     browser,
+                    build_failures,
                     ..
                 },
-                Event::Build(BuildEvent::Exited(_)),
+                Event::Build(BuildEvent::Exited(_) | BuildEvent::EmptyOutput),
             ) => (
-                vec![Control::Command(Command::Build(BuildCommand::Spawn {
-                    path: build_command_path.clone(),
-                    envs: vec![(
-                        SERVE_PATH.to_string(),
-                        self.serve_dir.path().to_str().unwrap().to_string(),
-                    )],
-                }))],
+                vec![self.spawn_build(&changed_paths)],
                 State::BuildSpawning {
+                    is_restarting: false,
+                    changed_paths,
                     server,
                     watcher,
                     browser,
+                    build_failures,
                 },
             ),
             (
                 State::BuildWaiting {
                     is_restarting: false,
+                    output,
                     server,
                     watcher,
                     browser,
+                    build_failures,
                     ..
                 },
-                Event::Build(BuildEvent::Exited(_)),
+                Event::Build(event @ (BuildEvent::Exited(_) | BuildEvent::EmptyOutput)),
+            ) => {
+                // empty output is a failure, even though the build exited successfully
+                let exit_code = match event {
+                    BuildEvent::Exited(exit_code) => exit_code,
+                    _ => None,
+                };
+
+                self.report_state(&server, browser.as_ref(), exit_code);
+                let build_failures = build_failures + 1;
+
+                if build_failures == self.exit_on_build_failure_count {
+                    error!(
+                        "build failed {build_failures} consecutive times; output of the last:\n{}",
+                        output.join("\n")
+                    );
+
+                    State::shut_down(Some(server), Some(watcher), 1)
+                } else {
+                    (
+                        vec![],
+                        State::Idle {
+                            server,
+                            watcher,
+                            browser,
+                            build_failures,
+                        },
+                    )
+                }
+            }
+            (
+                State::FinishingBuild {
+                    server, watcher, ..
+                },
+                Event::Build(BuildEvent::Spawn(pid)),
             ) => (
                 vec![],
-                State::Idle {
+                State::FinishingBuild {
+                    pid: Some(pid),
                     server,
                     watcher,
-                    browser,
                 },
             ),
+            (
+                State::FinishingBuild {
+                    server, watcher, ..
+                },
+                Event::Build(
+                    BuildEvent::SpawnError(_)
+                    | BuildEvent::Skipped
+                    | BuildEvent::Exited(_)
+                    | BuildEvent::EmptyOutput
+                    | BuildEvent::WaitError(_)
+                    | BuildEvent::PromotionError(_),
+                ),
+            ) => State::shut_down(Some(server), Some(watcher), 0),
+            (
+                state @ (State::FinishingBuild { .. }
+                | State::ShuttingDown { .. }
+                | State::Terminating),
+                Event::Build(_),
+            ) => (vec![], state),
             (_, Event::Build(_)) => unreachable!(),
             (
-                State::Reloading { server, watcher },
-                Event::Browser(BrowserEvent::Reload(browser)),
-            ) => (
-                vec![],
-                State::Idle {
+                State::Reloading {
                     server,
                     watcher,
-                    browser,
+                    queued_paths,
                 },
-            ),
+                Event::Browser(
+                    BrowserEvent::Reload(browser) | BrowserEvent::ReloadError(browser, ..),
+                ),
+            ) => match queued_paths {
+                Some(queued_paths) => {
+                    self.handle_changes(queued_paths, server, watcher, Some(browser), 0)
+                }
+                None => (
+                    vec![],
+                    State::Idle {
+                        server,
+                        watcher,
+                        browser: Some(browser),
+                        build_failures: 0,
+                    },
+                ),
+            },
             (
-                State::Reloading { server, watcher },
-                Event::Browser(BrowserEvent::ReloadError(browser, ..)),
-            ) => (
-                vec![],
-                State::Idle {
+                state @ (State::ShuttingDown { .. } | State::Terminating),
+                Event::Browser(BrowserEvent::Spawn(_) | BrowserEvent::SpawnError(_)),
+            ) => (vec![], state),
+            (_, Event::Browser(_)) => unreachable!(),
+            (
+                State::FinishingBuild {
+                    pid,
                     server,
                     watcher,
-                    browser,
                 },
-            ),
-            (_, Event::Browser(_)) => unreachable!(),
+                Event::Timer(TimerEvent::Elapsed),
+            ) => {
+                warn!("the build did not finish within the shutdown grace period");
+                let (mut controls, state) = State::shut_down(Some(server), Some(watcher), 0);
+
+                if let Some(pid) = pid {
+                    controls.push(Control::Command(Command::Build(BuildCommand::Signal(
+                        pid, SIGTERM,
+                    ))));
+                }
+
+                (controls, state)
+            }
+            (
+                state @ (State::ShuttingDown { .. } | State::Terminating),
+                Event::Timer(TimerEvent::Elapsed),
+            ) => (vec![], state),
+            (_, Event::Timer(_)) => unreachable!(),
             (
                 State::ShuttingDown {
                     server: ShuttingDownServerState::Spawning,
@@ -706,6 +1921,7 @@ impl App {
             (State::Terminating, Event::Signal(SignalEvent::Received(_))) => {
                 (vec![], State::Terminating)
             }
+            (state, Event::Server(ServerEvent::Reload)) => (vec![], state),
             (_, Event::Server(_)) => unreachable!(),
             value @ (_, Event::Fs(_)) => unreachable!("{value:#?}"),
         })
@@ -1,16 +1,444 @@
-use std::path::PathBuf;
+use std::{
+    ffi::OsString,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
 
-use clap::Parser as _;
-use tracing::debug;
+use crate::{
+    driver::{
+        browser::{Viewport, WindowPosition},
+        build::{OutputStream, ServePathStyle},
+        server::{EtagStrategy, ReloadMethod},
+    },
+    logging::LogFormat,
+};
 
 #[derive(Debug, Clone, clap::Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group = clap::ArgGroup::new("build").args(["build_command", "shell"]),
+)]
 pub(crate) struct Args {
-    /// The build command
-    pub(crate) build_command: PathBuf,
+    #[command(subcommand)]
+    pub(crate) subcommand: Option<Subcommand>,
+    /// The build commands, which are run in sequence until one fails,
+    /// the build succeeding only if all of them succeed
+    #[arg(required_unless_present_any = ["external_build", "shell"])]
+    pub(crate) build_command: Vec<PathBuf>,
+    /// A command line to run via `/bin/sh -c` as the build command, instead of the path of one
+    #[arg(long, value_name = "COMMAND")]
+    pub(crate) shell: Option<String>,
+    /// An argument to pass to each build command, e.g. `--build-arg --release`;
+    /// may be provided multiple times
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        requires = "build_command",
+        conflicts_with = "shell"
+    )]
+    pub(crate) build_arg: Vec<OsString>,
+    /// An environment variable to set for the build commands, e.g. `--build-env NODE_ENV=production`;
+    /// may be provided multiple times
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env, requires = "build")]
+    pub(crate) build_env: Vec<(String, String)>,
+    /// Rather than inheriting the environment, provide the build commands with only
+    /// the variables of `--build-env` and those that conveyorbelt sets, e.g. `SERVE_PATH`
+    #[arg(long, requires = "build")]
+    pub(crate) build_clean_env: bool,
+    /// The working directory of the build commands, by default the project root
+    #[arg(long, requires = "build")]
+    pub(crate) build_cwd: Option<PathBuf>,
+    /// The directory that is watched for changes,
+    /// by default the git toplevel of the current directory, or else the current directory itself
+    #[arg(long)]
+    pub(crate) project_root: Option<PathBuf>,
+    /// A directory to watch for changes in addition to the project root, e.g. one outside of it,
+    /// within which ignore files do not apply; may be provided multiple times
+    #[arg(long, requires = "build")]
+    pub(crate) watch: Vec<PathBuf>,
+    /// Extensions, comma-separated, e.g. `html,css,js`, of the files whose changes trigger builds,
+    /// rather than those of any file
+    #[arg(long, value_delimiter = ',', value_parser = parse_extension, requires = "build")]
+    pub(crate) watch_ext: Vec<String>,
+    /// A file of ignore patterns, relative to the project root, in the format of `.gitignore`,
+    /// consulted in addition to `.ignore` and `.conveyorbeltignore`; may be provided multiple times
+    #[arg(long, requires = "build")]
+    pub(crate) ignore_file: Vec<PathBuf>,
+    /// Rather than running a build command, watch the serve path,
+    /// which some external process writes the build output to, and reload on its changes
+    #[arg(long, conflicts_with = "build")]
+    pub(crate) external_build: bool,
+    /// A command that is run on each change before the build command,
+    /// receiving the same environment variables
+    #[arg(long)]
+    pub(crate) on_change: Option<PathBuf>,
+    /// Skip the build if the on-change command fails
+    #[arg(long, requires = "on_change")]
+    pub(crate) on_change_required: bool,
+    /// Which output streams of the build command are logged; the others are discarded
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) build_output_stream: OutputStream,
+    /// Prefix logged lines of build output with the time at which they were read,
+    /// in seconds since the Unix epoch
+    #[arg(long)]
+    pub(crate) timestamp_build_output: bool,
+    /// Provide each build with a fresh `TMPDIR`, which is removed once it finishes
+    #[arg(long)]
+    pub(crate) isolate_build_tmp: bool,
+    /// Consider a build that succeeds without having written any files to the serve path failed
+    #[arg(long, conflicts_with = "external_build")]
+    pub(crate) require_nonempty_output: bool,
+    /// Serve what the serve path already contains, e.g. with `--serve-dir`, building only on the first change
+    #[arg(long, requires = "build", conflicts_with = "check")]
+    pub(crate) no_initial_build: bool,
+    /// Warn following each successful build that writes no files or no `index.html` to the serve path
+    #[arg(long, conflicts_with = "external_build")]
+    pub(crate) warn_empty: bool,
+    /// Build into a staging directory beside the serve path, which is exchanged with it atomically
+    /// only once the build succeeds, so that the output of a build in progress or a failed one is never served;
+    /// if it is within the repository, `.conveyorbelt-staging-*` must be ignored by git
+    #[arg(long, conflicts_with = "external_build")]
+    pub(crate) stage_build_output: bool,
+    /// Whether the serve path is provided to the build command as an absolute path
+    /// or as one relative to its working directory, which is `--build-cwd` or else the project root
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) serve_path_style: ServePathStyle,
+    /// The address to serve at
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    pub(crate) host: IpAddr,
+    /// The port to serve at; an available port is chosen if omitted
+    #[arg(long)]
+    pub(crate) port: Option<u16>,
+    /// An inherited file descriptor of a listening TCP socket to serve on,
+    /// as passed by a supervisor that owns the port
+    #[arg(long, conflicts_with_all = ["host", "port"])]
+    pub(crate) listen_fd: Option<i32>,
+    /// Serve only HTTP/2 rather than HTTP/1.1 as well;
+    /// without `--tls`, with prior knowledge (h2c), which browsers do not speak
+    #[arg(long)]
+    pub(crate) http2: bool,
+    /// Serve HTTPS, e.g. for the secure context that service workers require,
+    /// with an ephemeral self-signed certificate unless `--tls-cert` and `--tls-key` are provided
+    #[arg(long)]
+    pub(crate) tls: bool,
+    /// A PEM file of the certificate chain to serve HTTPS with
+    #[arg(long, requires_all = ["tls", "tls_key"])]
+    pub(crate) tls_cert: Option<PathBuf>,
+    /// A PEM file of the private key of `--tls-cert`
+    #[arg(long, requires_all = ["tls", "tls_cert"])]
+    pub(crate) tls_key: Option<PathBuf>,
+    /// Serve files and directories whose names begin with a dot, e.g. `.well-known/`
+    #[arg(long)]
+    pub(crate) serve_hidden: bool,
+    /// Serve the targets of symbolic links rather than responding with `403`
+    #[arg(long)]
+    pub(crate) follow_symlinks: bool,
+    /// Redirect requests for directories to their trailing slash form
+    #[arg(long)]
+    pub(crate) redirect_trailing_slash: bool,
+    /// Respond to requests for paths that are not found and have no extension
+    /// with the root `index.html`, for single-page applications with client side routing
+    #[arg(long)]
+    pub(crate) spa: bool,
+    /// The status code of responses for files that are not found
+    #[arg(long, default_value_t = 404, value_parser = clap::value_parser!(u16).range(100..=599))]
+    pub(crate) page404_status: u16,
+    /// The page that server errors are responded to with, instead of `50x.html` of the serve path
+    #[arg(long)]
+    pub(crate) page50x: Option<PathBuf>,
+    /// Respond to all requests with the maintenance page
+    #[arg(long)]
+    pub(crate) maintenance_mode: bool,
+    /// Respond to requests with the maintenance page while a build following a change runs,
+    /// rather than with the output of the previous build
+    #[arg(long, requires = "build", conflicts_with = "maintenance_mode")]
+    pub(crate) maintenance_during_build: bool,
+    /// The maintenance page, instead of a default message
+    #[arg(long)]
+    pub(crate) maintenance_file: Option<PathBuf>,
+    /// The status code of responses with the maintenance page
+    #[arg(long, default_value_t = 503, value_parser = clap::value_parser!(u16).range(100..=599))]
+    pub(crate) maintenance_status: u16,
+    /// How ETags of served files are computed
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) etag_strategy: EtagStrategy,
+    /// Compress responses with gzip or brotli, as accepted by the client,
+    /// and serve precompressed `.gz` and `.br` files where present
+    #[arg(long)]
+    pub(crate) compression: bool,
+    /// Log the method, URI and response status of each served request
+    #[arg(long)]
+    pub(crate) access_log: bool,
+    /// Log at warn level, regardless of `--access-log`, the requests that took longer than this
+    /// many milliseconds to respond to
+    #[arg(long)]
+    pub(crate) slow_request_ms: Option<u64>,
+    /// Respond with CORS headers to requests from these origins, comma-separated,
+    /// e.g. `http://localhost:3000`, or from any, with `*`
+    #[arg(long, value_parser = parse_cors)]
+    pub(crate) cors: Option<String>,
+    /// How pages are reloaded following each successful build:
+    /// the launched browser's via CDP, any that run a script injected into served HTML, or both;
+    /// `websocket` is accepted as an alias of `script`
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) reload_method: ReloadMethod,
+    /// Restore the scroll position of the page after each reload
+    #[arg(long)]
+    pub(crate) reload_preserve_scroll: bool,
+    /// A query string for the URL that the browser navigates to,
+    /// in which `{build}` is replaced with the number of successful builds since the initial one
+    #[arg(long)]
+    pub(crate) start_url_query: Option<String>,
+    /// Launch the browser only once the initial build has succeeded,
+    /// rather than as soon as the server is up
+    #[arg(long, action = clap::ArgAction::Set, default_value_t = true)]
+    pub(crate) defer_browser_until_ready: bool,
+    /// Navigate the launched browser's page to the served URL and reload it following builds;
+    /// with `false` the page remains blank, e.g. for automation that opens its own pages
+    #[arg(long, action = clap::ArgAction::Set, default_value_t = true)]
+    pub(crate) open: bool,
+    /// The dimensions that the browser's pages are emulated at, as `<width>x<height>`,
+    /// or `off` for those of the browser window
+    #[arg(long, value_parser = parse_viewport, default_value = "off")]
+    pub(crate) viewport: Viewport,
+    /// The position of the browser window's top left corner on the screen, as `<x>,<y>`,
+    /// e.g. for it to land on a particular display
+    #[arg(long, value_parser = parse_window_position, conflicts_with = "no_browser")]
+    pub(crate) window_position: Option<WindowPosition>,
+    /// Maximize the browser window, following `--window-position` if provided
+    #[arg(long, conflicts_with_all = ["no_browser", "headless"])]
+    pub(crate) maximize: bool,
+    /// Launch the browser without a window, e.g. for driving its page via CDP in CI
+    #[arg(long, conflicts_with_all = ["no_browser", "browser_ws_url"])]
+    pub(crate) headless: bool,
+    /// Serve and build without launching a browser
+    #[arg(long)]
+    pub(crate) no_browser: bool,
+    /// Validate the setup by resolving the project root, creating the watcher and running one build,
+    /// then exit, with 0 only if all succeeded, without serving or launching a browser
+    #[arg(long, requires = "build")]
+    pub(crate) check: bool,
+    /// A directory to serve from, which is retained on exit, instead of a temporary one;
+    /// if it is within the repository it must be ignored by git
+    #[arg(long)]
+    pub(crate) serve_dir: Option<PathBuf>,
+    /// Octal permission mode of the serve path, e.g. `750`
+    #[arg(long, value_parser = parse_permissions)]
+    pub(crate) serve_dir_permissions: Option<u32>,
+    /// WebSocket debugging URL of an already running browser to connect to instead of launching one
+    #[arg(long)]
+    pub(crate) browser_ws_url: Option<String>,
+    /// A URL to open in an additional page of the browser, which is not reloaded; repeatable
+    #[arg(long)]
+    pub(crate) open_extra: Vec<String>,
+    /// A directory into which the browser saves downloads without prompting
+    #[arg(long)]
+    pub(crate) downloads_dir: Option<PathBuf>,
+    /// A directory into which the page is captured as a PNG, named by the time and the build number,
+    /// once it has loaded following each successful build
+    #[arg(long)]
+    pub(crate) screenshot_dir: Option<PathBuf>,
+    /// A pattern of URLs, in the `URLPattern` constructor string syntax, e.g. `*://fonts.example/*`,
+    /// requests to which are blocked in the browser's page; repeatable
+    #[arg(long)]
+    pub(crate) block_url: Vec<String>,
+    /// Of the launched browser's stderr, log only lines that are not known noise,
+    /// such as GPU and D-Bus warnings, rather than all lines at debug level
+    #[arg(long, conflicts_with = "browser_ws_url")]
+    pub(crate) quiet_browser: bool,
+    /// A file to write, on exit, an HTTP Archive of the network activity of the browser's page to
+    #[arg(long)]
+    pub(crate) har: Option<PathBuf>,
+    /// Discard the HTTP Archive entries of previous navigations on each reload,
+    /// rather than appending those of each reload as a page
+    #[arg(long, requires = "har")]
+    pub(crate) har_reset_on_reload: bool,
+    /// The chromium executable to launch, instead of one that is found on `PATH`,
+    /// e.g. of a particular release channel
+    #[arg(long, conflicts_with = "browser_ws_url")]
+    pub(crate) browser_executable: Option<PathBuf>,
+    /// Serve recent build output at `/__conveyorbelt/build-log`,
+    /// limited to the last `n` lines by a `tail=<n>` query parameter
+    /// and streamed when requested with `Accept: text/event-stream`
+    #[arg(long)]
+    pub(crate) build_log_api: bool,
+    /// Seconds after which a build process is terminated and considered failed
+    #[arg(long)]
+    pub(crate) build_timeout: Option<u64>,
+    /// Rerun a build command that exits with a non-zero status up to this many times per build
+    /// before the build is considered failed
+    #[arg(long, requires = "build", default_value_t = 0)]
+    pub(crate) build_retries: usize,
+    /// Milliseconds to wait before each rerun of `--build-retries`
+    #[arg(long, requires = "build", default_value_t = 1000)]
+    pub(crate) build_retry_delay: u64,
+    /// Refuse to start while another instance with this option is running in the same repository
+    #[arg(long)]
+    pub(crate) single_instance: bool,
+    /// A file to log the Chrome DevTools Protocol messages exchanged with the browser to
+    #[arg(long)]
+    pub(crate) browser_cdp_log: Option<PathBuf>,
+    /// The format of the log, which is written to stderr
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) log_format: LogFormat,
+    /// Log only warnings and errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub(crate) quiet: bool,
+    /// Log debug events as well; repeat to log trace events too
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub(crate) verbose: u8,
+    /// Milliseconds following a change during which further changes are collected,
+    /// to be reported together and built once
+    #[arg(long)]
+    pub(crate) watch_batch_window: Option<u64>,
+    /// Milliseconds without further changes to wait for before building,
+    /// so that a burst of changes results in a single build
+    #[arg(long, default_value_t = 0)]
+    pub(crate) debounce: u64,
+    /// Poll for changes every this many milliseconds instead of relying on native notifications,
+    /// which do not arrive on some networked and virtual filesystems
+    #[arg(long)]
+    pub(crate) poll: Option<u64>,
+    /// Exit after this many consecutive failed builds, logging the output of the last;
+    /// `0` never exits
+    #[arg(long, default_value_t = 0)]
+    pub(crate) exit_on_build_failure_count: usize,
+    /// On a shutdown signal during a build, let the build finish before exiting;
+    /// a second signal exits immediately
+    #[arg(long)]
+    pub(crate) graceful_build_on_shutdown: bool,
+    /// Seconds to wait for the build to finish on shutdown, after which it is terminated;
+    /// unlimited if omitted
+    #[arg(long, requires = "graceful_build_on_shutdown")]
+    pub(crate) shutdown_grace: Option<u64>,
+    /// Seconds to wait on shutdown for open connections to close, after which they are dropped,
+    /// and for the build to finish in lieu of `--shutdown-grace`; unlimited if omitted
+    #[arg(long)]
+    pub(crate) shutdown_timeout: Option<u64>,
+    /// On shutdown, log the served files that were not requested during the session
+    #[arg(long)]
+    pub(crate) report_unused_assets: bool,
+    /// Respond at `/health` with `503 Service Unavailable` until the initial build has succeeded
+    /// and with `200 OK` thereafter, for readiness probes
+    #[arg(long)]
+    pub(crate) health: bool,
+    /// Serve at `/__conveyorbelt/stream` the served file at the `path` query parameter,
+    /// `index.html` by default, as a chunked response followed by a trailer of the number of chunks,
+    /// for exercising streaming clients; trailers are sent only over HTTP/2
+    #[arg(long)]
+    pub(crate) stream_test: bool,
+    /// Milliseconds between the chunks of `/__conveyorbelt/stream`
+    #[arg(long, default_value_t = 100)]
+    pub(crate) stream_test_delay: u64,
+    /// A path at which to create a Unix domain socket, removed on exit,
+    /// to which writing the line `rebuild` triggers a build
+    #[arg(long)]
+    pub(crate) control_socket: Option<PathBuf>,
+    /// A path at which to create a Unix domain socket, removed on exit,
+    /// to each connection to which the state is written once initialized as a JSON line:
+    /// serve path and port, browser pid and debugging address and last build exit code
+    #[arg(long)]
+    pub(crate) state_socket: Option<PathBuf>,
+    /// A file to write, following each successful build, a JSON object to,
+    /// of the served files' paths to their sizes, modification times and SHA-256 content hashes;
+    /// if it is within the repository it should be ignored by git, lest writing it trigger a build
+    #[arg(long)]
+    pub(crate) manifest: Option<PathBuf>,
+    /// A file to write the URL that is served at to once listening, e.g. for editor integrations;
+    /// removed on exit
+    #[arg(long)]
+    pub(crate) url_file: Option<PathBuf>,
+    /// Following a successful build, reload only if the served files differ
+    /// from those following the previous one
+    #[arg(long)]
+    pub(crate) reload_only_on_output_change: bool,
 }
 
-pub(crate) fn parse() -> Args {
-    let args = Args::parse();
-    debug!("arguments parsed: {args:?}");
-    args
+#[derive(Debug, Clone, clap::Subcommand)]
+pub(crate) enum Subcommand {
+    /// Scaffold an example build command in the current repository
+    Init,
+}
+
+fn parse_permissions(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("not an octal permission mode: {mode:?}")),
+    }
+}
+
+fn parse_extension(extension: &str) -> Result<String, String> {
+    let extension = extension.trim().trim_start_matches('.');
+
+    if extension.is_empty() {
+        return Err("empty extension".to_owned());
+    }
+
+    Ok(extension.to_owned())
+}
+
+fn parse_env(variable: &str) -> Result<(String, String), String> {
+    match variable.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("not of the form `<key>=<value>`: {variable:?}")),
+    }
+}
+
+fn parse_cors(origins: &str) -> Result<String, String> {
+    if origins.trim() == "*" {
+        return Ok("*".to_owned());
+    }
+
+    let origins = origins
+        .split(',')
+        .map(str::trim)
+        .map(|origin| {
+            let is_valid = origin.parse::<hyper::Uri>().is_ok_and(|uri| {
+                uri.scheme().is_some()
+                    && uri.authority().is_some()
+                    && uri.path() == "/"
+                    && uri.query().is_none()
+            });
+
+            if is_valid && !origin.ends_with('/') {
+                Ok(origin)
+            } else {
+                Err(format!(
+                    "neither `*` nor an origin of the form `<scheme>://<host>[:<port>]`: {origin:?}"
+                ))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(origins.join(","))
+}
+
+fn parse_window_position(position: &str) -> Result<WindowPosition, String> {
+    position
+        .split_once(',')
+        .and_then(|(x, y)| {
+            Some(WindowPosition {
+                x: x.trim().parse().ok()?,
+                y: y.trim().parse().ok()?,
+            })
+        })
+        .ok_or_else(|| format!("not of the form `<x>,<y>`, e.g. `1920,0`: {position:?}"))
+}
+
+fn parse_viewport(viewport: &str) -> Result<Viewport, String> {
+    if viewport == "off" {
+        return Ok(Viewport::Off);
+    }
+
+    let size = viewport
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+
+    match size {
+        Some((width, height)) if width > 0 && height > 0 => Ok(Viewport::Size { width, height }),
+        _ => Err(format!(
+            "neither `off` nor of the form `<width>x<height>`, e.g. `1280x720`: {viewport:?}"
+        )),
+    }
 }
@@ -1,5 +1,7 @@
 pub(crate) mod browser;
 pub(crate) mod build;
+pub(crate) mod control;
 pub(crate) mod fswatch;
 pub(crate) mod server;
 pub(crate) mod signal;
+pub(crate) mod timer;
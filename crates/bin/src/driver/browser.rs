@@ -1,44 +1,171 @@
-use std::convert::Infallible;
+mod har;
+
+use std::{
+    convert::Infallible,
+    fmt::Debug,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Context as _, anyhow, bail};
 use chromiumoxide::{
     BrowserConfig,
-    cdp::browser_protocol::target::{CloseTargetParams, GetTargetsParams},
+    cdp::browser_protocol::{
+        browser::{
+            Bounds, GetWindowForTargetParams, SetDownloadBehaviorBehavior,
+            SetDownloadBehaviorParams, SetWindowBoundsParams, WindowState,
+        },
+        network::{BlockPattern, EnableParams, SetBlockedUrLsParams},
+        page::CaptureScreenshotFormat,
+        target::{CloseTargetParams, GetTargetsParams},
+    },
+    handler::HandlerConfig,
+    page::ScreenshotParams,
 };
+use futures::FutureExt as _;
 use rxrust::prelude::*;
 use tempfile::tempdir;
 use tokio_stream::{StreamExt as _, wrappers::ReceiverStream};
-use tracing::debug;
+use tracing::{debug, info, warn};
+
+use crate::common::{ForStdoutputLine as _, TESTING_MODE};
+
+pub(crate) use self::har::HarRecorder;
+
+/// Lower case substrings of lines that the browser writes to stderr unsolicited,
+/// which are not indicative of a problem with the served page
+const NOISE_PATTERNS: &[&str] = &[
+    "dbus",
+    "bus.cc",
+    "object_proxy.cc",
+    "gpu",
+    "fontconfig",
+    "viz_main_impl",
+    "sandbox_linux",
+    "gtk-warning",
+    "alsa",
+    "libva",
+    "vaapi",
+];
+
+/// Precedes the debugging address in the browser's stderr
+const DEVTOOLS_LISTENING_PREFIX: &str = "DevTools listening on ";
+
+/// Of the implementation `B`, which is chosen where the driver is created
+pub(crate) struct BrowserDriver<B: DevBrowser> {
+    event_sender: tokio::sync::mpsc::Sender<BrowserEvent<B>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BrowserOptions {
+    pub(crate) reload_preserve_scroll: bool,
+    pub(crate) start_url_query: Option<String>,
+    pub(crate) ws_url: Option<String>,
+    /// Opened in addition, but never reloaded
+    pub(crate) extra_urls: Vec<String>,
+    /// Absolute; downloads are accepted into it without prompting
+    pub(crate) downloads_dir: Option<PathBuf>,
+    /// URL patterns, in the `URLPattern` constructor string syntax,
+    /// of requests of the page that are blocked
+    pub(crate) blocked_urls: Vec<String>,
+    /// Of a launched browser's stderr, log only lines that do not match [`NOISE_PATTERNS`],
+    /// as warnings, rather than all lines, at debug level
+    pub(crate) quiet_browser: bool,
+    /// Of the browser to launch, instead of one that is detected
+    pub(crate) executable: Option<PathBuf>,
+    /// Whether the browser is launched without a window
+    pub(crate) headless: bool,
+    /// Absolute; the page is captured into it once loaded following each successful build
+    pub(crate) screenshot_dir: Option<PathBuf>,
+    /// Whether the page is navigated to the served URL and reloaded;
+    /// otherwise it remains blank
+    pub(crate) open: bool,
+    /// Records the network activity of the page while it is open
+    pub(crate) har: Option<HarRecorder>,
+    pub(crate) viewport: Viewport,
+    pub(crate) window_position: Option<WindowPosition>,
+    /// Whether the browser window is maximized, following its positioning
+    pub(crate) maximize: bool,
+}
+
+/// Of the top left corner of the browser window, in screen pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WindowPosition {
+    pub(crate) x: i64,
+    pub(crate) y: i64,
+}
+
+/// The dimensions that pages are emulated at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Viewport {
+    /// Those of the browser window
+    #[default]
+    Off,
+    Size {
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Viewport {
+    fn emulated(self) -> Option<chromiumoxide::handler::viewport::Viewport> {
+        match self {
+            Self::Off => None,
+            Self::Size { width, height } => Some(chromiumoxide::handler::viewport::Viewport {
+                width,
+                height,
+                ..Default::default()
+            }),
+        }
+    }
+}
 
-use crate::common::TESTING_MODE;
+impl BrowserOptions {
+    const BUILD_PLACEHOLDER: &str = "{build}";
 
-pub(crate) struct BrowserDriver {
-    event_sender: tokio::sync::mpsc::Sender<BrowserEvent>,
+    fn navigation_url(&self, url: &str, build: u64) -> String {
+        match &self.start_url_query {
+            Some(query) => format!(
+                "{url}/?{}",
+                query.replace(Self::BUILD_PLACEHOLDER, &build.to_string())
+            ),
+            None => url.to_owned(),
+        }
+    }
+
+    fn is_navigation_url_per_build(&self) -> bool {
+        self.start_url_query
+            .as_ref()
+            .is_some_and(|query| query.contains(Self::BUILD_PLACEHOLDER))
+    }
 }
 
 #[derive(Debug, derive_more::Display)]
-pub(crate) enum BrowserCommand {
+pub(crate) enum BrowserCommand<B: DevBrowser> {
     #[display("spawn and go to {url}")]
-    Spawn { url: String },
+    Spawn {
+        url: String,
+        options: Box<BrowserOptions>,
+    },
     #[display("reload")]
-    Reload(Browser),
+    Reload(B),
 }
 
 #[derive(Debug, derive_more::Display)]
-pub(crate) enum BrowserEvent {
+pub(crate) enum BrowserEvent<B: DevBrowser> {
     #[display("spawned")]
-    Spawn(Browser),
+    Spawn(B),
     #[display("spawn error: {_0}")]
     SpawnError(anyhow::Error),
     #[display("reloaded")]
-    Reload(Browser),
+    Reload(B),
     #[display("reload error: {_1}")]
-    ReloadError(Browser, anyhow::Error),
+    ReloadError(B, anyhow::Error),
 }
 
-impl BrowserDriver {
+impl<B: DevBrowser> BrowserDriver<B> {
     pub(crate) fn new() -> (
-        SharedBoxedObservable<'static, BrowserEvent, Infallible>,
+        SharedBoxedObservable<'static, BrowserEvent<B>, Infallible>,
         Self,
     ) {
         let (event_sender, event_receiver) = tokio::sync::mpsc::channel(1);
@@ -49,16 +176,19 @@ impl BrowserDriver {
         )
     }
 
-    pub(crate) fn effect(&self, command: BrowserCommand) -> impl Future<Output = ()> + 'static {
+    pub(crate) fn effect(&self, command: BrowserCommand<B>) -> impl Future<Output = ()> + 'static {
         let event_sender = self.event_sender.clone();
 
         async move {
             let event = match command {
-                BrowserCommand::Spawn { url: address } => match Browser::spawn(address).await {
+                BrowserCommand::Spawn {
+                    url: address,
+                    options,
+                } => match B::spawn(address, options).await {
                     Ok(browser) => BrowserEvent::Spawn(browser),
                     Err(error) => BrowserEvent::SpawnError(error),
                 },
-                BrowserCommand::Reload(browser) => match browser.reload().await {
+                BrowserCommand::Reload(mut browser) => match browser.reload().await {
                     Ok(_) => BrowserEvent::Reload(browser),
                     Err(error) => BrowserEvent::ReloadError(browser, error),
                 },
@@ -69,53 +199,109 @@ impl BrowserDriver {
 }
 
 #[derive(Debug)]
-//pub(crate) struct Browser(&'static mut chromiumoxide::Browser);
-pub(crate) struct Browser {
-    handle: &'static chromiumoxide::Browser,
-    pid: u32,
-    page: chromiumoxide::Page,
+enum BrowserHandle {
+    /// Connected to after launching, so that its stderr remains ours to read
+    Launched {
+        browser: Box<chromiumoxide::Browser>,
+        pid: u32,
+    },
+    /// Not leaked, because the lifecycle of the browser is not ours
+    Connected(Box<chromiumoxide::Browser>),
 }
 
-impl Browser {
-    pub(crate) fn pid(&self) -> u32 {
-        self.pid
+impl BrowserHandle {
+    fn browser(&self) -> &chromiumoxide::Browser {
+        match self {
+            Self::Launched { browser, .. } => browser,
+            Self::Connected(browser) => browser,
+        }
     }
 
-    pub(crate) fn debugging_address(&self) -> String {
-        self.handle.websocket_address().clone()
-    }
+    async fn launch(options: &BrowserOptions) -> anyhow::Result<Self> {
+        let BrowserOptions {
+            quiet_browser: quiet,
+            viewport,
+            ..
+        } = *options;
 
-    pub(crate) async fn spawn(url: String) -> anyhow::Result<Self> {
         let browser_data_dir = tempdir().context("failed to create temporary browser data dir")?;
 
         debug!("browser data dir: {browser_data_dir:?}");
 
         let mut browser_config_builder = BrowserConfig::builder()
-            .with_head()
-            .viewport(None)
+            .viewport(viewport.emulated())
             .user_data_dir(browser_data_dir.path())
             .port(0);
 
-        if std::env::var(TESTING_MODE).is_ok() {
-            browser_config_builder = browser_config_builder.launch_timeout(Duration::from_mins(15));
+        // headless is the default
+        if !options.headless {
+            browser_config_builder = browser_config_builder.with_head();
+        }
+
+        if let Some(executable) = &options.executable {
+            browser_config_builder = browser_config_builder.chrome_executable(executable);
         }
 
+        let launch_timeout = if std::env::var(TESTING_MODE).is_ok() {
+            Duration::from_mins(15)
+        } else {
+            Duration::from_secs(20)
+        };
+
         let browser_config = browser_config_builder
             .build()
             .map_err(|e| anyhow!("failed to build browser config: {e}"))?;
 
         debug!("browser config: {browser_config:?}");
 
-        let (mut browser, mut handler) = chromiumoxide::Browser::launch(browser_config)
+        // killed on drop, until leaked
+        let mut child = browser_config
+            .launch()
+            .context("failed to launch browser")?
+            .into_inner();
+
+        let pid = child.id().context("failed to obtain browser pid")?;
+
+        let (ws_url_sender, ws_url_receiver) = tokio::sync::oneshot::channel();
+        let mut ws_url_sender = Some(ws_url_sender);
+
+        child
+            .for_stderr_line(move |line| {
+                if let Some(ws_url) = line.strip_prefix(DEVTOOLS_LISTENING_PREFIX)
+                    && let Some(ws_url_sender) = ws_url_sender.take()
+                {
+                    let _ = ws_url_sender.send(ws_url.trim().to_owned());
+                }
+
+                if !quiet {
+                    debug!(source = "browser", line = %line, "browser stderr line");
+                } else if !is_noise(line) {
+                    warn!(source = "browser", line = %line, "browser stderr line");
+                }
+
+                async {}.boxed()
+            })
+            .context("failed to obtain browser stderr")?;
+
+        let ws_url = tokio::time::timeout(launch_timeout, ws_url_receiver)
             .await
-            .context("failed to launch browser")?;
+            .context("timed out waiting for the browser's debugging address")?
+            .context("browser exited before reporting its debugging address")?;
 
-        let pid = browser
-            .get_mut_child()
-            .context("failed to obtain mutable reference to browser Child")?
-            .as_mut_inner()
-            .id()
-            .context("failed to obtain browser pid")?;
+        let handler_config = HandlerConfig {
+            viewport: viewport.emulated(),
+            // so that a self-signed certificate served with `--tls` does not block the page
+            ignore_https_errors: true,
+            ..HandlerConfig::default()
+        };
+
+        let (browser, mut handler) =
+            chromiumoxide::Browser::connect_with_config(&ws_url, handler_config)
+                .await
+                .with_context(|| format!("failed to connect to launched browser at {ws_url}"))?;
+
+        // dropping it would kill the browser process
+        Box::leak(Box::new(child));
 
         tokio::spawn(async move { while handler.next().await.is_some() {} });
 
@@ -143,16 +329,298 @@ impl Browser {
             .await
             .context("close newtab page")?;
 
-        let page = browser.new_page(url).await.context("creating page")?;
-
-        Ok(Self {
-            handle: Box::leak(Box::new(browser)),
+        Ok(Self::Launched {
+            browser: Box::new(browser),
             pid,
-            page,
         })
     }
-    pub(crate) async fn reload(&self) -> anyhow::Result<()> {
-        self.page.reload().await.context("reloading")?;
+
+    async fn connect(ws_url: &str, viewport: Viewport) -> anyhow::Result<Self> {
+        let handler_config = HandlerConfig {
+            viewport: viewport.emulated(),
+            // so that a self-signed certificate served with `--tls` does not block the page
+            ignore_https_errors: true,
+            ..HandlerConfig::default()
+        };
+
+        let (browser, mut handler) =
+            chromiumoxide::Browser::connect_with_config(ws_url, handler_config)
+                .await
+                .with_context(|| format!("failed to connect to browser at {ws_url}"))?;
+
+        tokio::spawn(async move { while handler.next().await.is_some() {} });
+        Ok(Self::Connected(Box::new(browser)))
+    }
+}
+
+fn is_noise(line: &str) -> bool {
+    let line = line.to_lowercase();
+    NOISE_PATTERNS.iter().any(|pattern| line.contains(pattern))
+}
+
+#[derive(Debug)]
+pub(crate) struct Browser {
+    handle: BrowserHandle,
+    page: chromiumoxide::Page,
+    url: String,
+    build: u64,
+    options: Box<BrowserOptions>,
+}
+
+/// A browser that the served pages are previewed in,
+/// of which chromium, driven via CDP, is the only implementation so far
+pub(crate) trait DevBrowser: Debug + Send + Sized + 'static {
+    fn spawn(
+        url: String,
+        options: Box<BrowserOptions>,
+    ) -> impl Future<Output = anyhow::Result<Self>> + Send;
+    /// Following a build
+    fn reload(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Via which the browser may be driven by others
+    fn debugging_address(&self) -> String;
+    /// `None` when connected to rather than launched
+    fn pid(&self) -> Option<u32>;
+}
+
+impl DevBrowser for Browser {
+    fn pid(&self) -> Option<u32> {
+        match self.handle {
+            BrowserHandle::Launched { pid, .. } => Some(pid),
+            BrowserHandle::Connected(_) => None,
+        }
+    }
+
+    fn debugging_address(&self) -> String {
+        self.handle.browser().websocket_address().clone()
+    }
+
+    async fn spawn(url: String, options: Box<BrowserOptions>) -> anyhow::Result<Self> {
+        let handle = match &options.ws_url {
+            Some(ws_url) => BrowserHandle::connect(ws_url, options.viewport).await?,
+            None => BrowserHandle::launch(&options).await?,
+        };
+
+        if let Some(downloads_dir) = &options.downloads_dir {
+            let params = SetDownloadBehaviorParams::builder()
+                .behavior(SetDownloadBehaviorBehavior::Allow)
+                .download_path(downloads_dir.to_string_lossy())
+                .build()
+                .map_err(|e| anyhow!("failed to build download behavior params: {e}"))?;
+
+            handle
+                .browser()
+                .execute(params)
+                .await
+                .context("setting download behavior")?;
+        }
+
+        let build = 0;
+
+        let page = handle
+            .browser()
+            .new_page("about:blank")
+            .await
+            .context("creating page")?;
+
+        Self::place_window(handle.browser(), &page, &options).await?;
+
+        if options.open {
+            Self::block_urls(&page, &options.blocked_urls).await?;
+
+            let navigation_url = options.navigation_url(&url, build);
+
+            if let Some(har) = &options.har {
+                har.record(&page).await?;
+                har.start_page(build, &navigation_url);
+            }
+
+            page.goto(navigation_url).await.context("navigating")?;
+        }
+
+        for extra_url in &options.extra_urls {
+            handle
+                .browser()
+                .new_page(extra_url.as_str())
+                .await
+                .with_context(|| format!("creating page for {extra_url}"))?;
+        }
+
+        let browser = Self {
+            handle,
+            page,
+            url,
+            build,
+            options,
+        };
+
+        if browser.options.open {
+            browser.capture_screenshot().await;
+        }
+
+        Ok(browser)
+    }
+
+    async fn reload(&mut self) -> anyhow::Result<()> {
+        self.build += 1;
+
+        if !self.options.open {
+            return Ok(());
+        }
+        Self::block_urls(&self.page, &self.options.blocked_urls).await?;
+
+        if let Some(har) = &self.options.har {
+            har.start_page(
+                self.build,
+                &self.options.navigation_url(&self.url, self.build),
+            );
+        }
+
+        let scroll_position = if self.options.reload_preserve_scroll {
+            Some(self.scroll_position().await?)
+        } else {
+            None
+        };
+
+        if self.options.is_navigation_url_per_build() {
+            self.page
+                .goto(self.options.navigation_url(&self.url, self.build))
+                .await
+                .context("navigating")?;
+        } else {
+            self.page.reload().await.context("reloading")?;
+        }
+
+        if let Some((x, y)) = scroll_position {
+            // the content dimensions may have changed, so clamp
+            self.page
+                .evaluate(format!(
+                    "window.scrollTo(
+                        Math.max(0, Math.min({x}, document.documentElement.scrollWidth - window.innerWidth)),
+                        Math.max(0, Math.min({y}, document.documentElement.scrollHeight - window.innerHeight)),
+                    )"
+                ))
+                .await
+                .context("restoring scroll position")?;
+        }
+
+        self.capture_screenshot().await;
+        Ok(())
+    }
+}
+
+impl Browser {
+    async fn place_window(
+        browser: &chromiumoxide::Browser,
+        page: &chromiumoxide::Page,
+        options: &BrowserOptions,
+    ) -> anyhow::Result<()> {
+        if options.window_position.is_none() && !options.maximize {
+            return Ok(());
+        }
+
+        let window_id = browser
+            .execute(
+                GetWindowForTargetParams::builder()
+                    .target_id(page.target_id().clone())
+                    .build(),
+            )
+            .await
+            .context("getting the browser window")?
+            .result
+            .window_id;
+
+        // a window state other than normal cannot be combined with a position
+        let bounds = options
+            .window_position
+            .map(|WindowPosition { x, y }| Bounds {
+                left: Some(x),
+                top: Some(y),
+                ..Bounds::default()
+            })
+            .into_iter()
+            .chain(options.maximize.then(|| Bounds {
+                window_state: Some(WindowState::Maximized),
+                ..Bounds::default()
+            }));
+
+        for bounds in bounds {
+            browser
+                .execute(SetWindowBoundsParams::new(window_id, bounds))
+                .await
+                .context("setting the browser window bounds")?;
+        }
+
         Ok(())
     }
+
+    async fn block_urls(page: &chromiumoxide::Page, patterns: &[String]) -> anyhow::Result<()> {
+        if patterns.is_empty() {
+            return Ok(());
+        }
+
+        page.execute(EnableParams::default())
+            .await
+            .context("enabling network domain")?;
+
+        let params = SetBlockedUrLsParams::builder()
+            .url_patterns(
+                patterns
+                    .iter()
+                    .map(|pattern| BlockPattern::new(pattern, true))
+                    .collect::<Vec<_>>(),
+            )
+            .build();
+
+        page.execute(params).await.context("setting blocked URLs")?;
+        Ok(())
+    }
+
+    /// Failure is not failure of the reload, so it is merely logged
+    async fn capture_screenshot(&self) {
+        let Some(screenshot_dir) = &self.options.screenshot_dir else {
+            return;
+        };
+
+        let result = async {
+            // the page might still be navigating, e.g. due to a redirect
+            self.page
+                .wait_for_navigation()
+                .await
+                .context("waiting for the page to load")?;
+
+            let png = self
+                .page
+                .screenshot(
+                    ScreenshotParams::builder()
+                        .format(CaptureScreenshotFormat::Png)
+                        .build(),
+                )
+                .await
+                .context("capturing screenshot")?;
+
+            let millis = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+
+            let path = screenshot_dir.join(format!("{millis}-build-{}.png", self.build));
+            std::fs::write(&path, png).with_context(|| format!("writing {path:?}"))?;
+            anyhow::Ok(path)
+        }
+        .await;
+
+        match result {
+            Ok(path) => info!("screenshot written to {path:?}"),
+            Err(error) => warn!("failed to capture screenshot: {error:#}"),
+        }
+    }
+
+    async fn scroll_position(&self) -> anyhow::Result<(f64, f64)> {
+        self.page
+            .evaluate("[window.scrollX, window.scrollY]")
+            .await
+            .context("obtaining scroll position")?
+            .into_value()
+            .context("deserializing scroll position")
+    }
 }
@@ -0,0 +1,474 @@
+use std::{
+    collections::HashMap,
+    io::Write as _,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use chromiumoxide::cdp::browser_protocol::network::{
+    EventLoadingFinished, EventRequestWillBeSent, EventResponseReceived, Headers, ResourceTiming,
+    Response,
+};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use tokio_stream::StreamExt as _;
+
+/// The network activity of the browser's page, as HTTP Archive 1.2 entries,
+/// grouped into a HAR page per navigation
+#[derive(Debug, Clone)]
+pub(crate) struct HarRecorder {
+    recording: Arc<Mutex<Recording>>,
+    /// Discard the entries of previous navigations on each reload
+    reset_on_reload: bool,
+}
+
+#[derive(Debug, Default)]
+struct Recording {
+    pages: Vec<Page>,
+    /// In order of their requests having been sent
+    entries: Vec<Entry>,
+    /// Indices into `entries` of requests that have not finished loading, by CDP request ID
+    in_flight: HashMap<String, usize>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    pageref: Option<String>,
+    /// Seconds since the Unix epoch
+    started: f64,
+    /// Monotonic seconds, as are the other CDP timestamps
+    sent_at: f64,
+    request: Request,
+    response: Option<(Response, Option<String>)>,
+    finished_at: Option<f64>,
+    body_size: i64,
+}
+
+impl HarRecorder {
+    pub(crate) fn new(reset_on_reload: bool) -> Self {
+        Self {
+            recording: Arc::default(),
+            reset_on_reload,
+        }
+    }
+
+    /// Subscribes to the network events of `page`, until it is closed
+    pub(super) async fn record(&self, page: &chromiumoxide::Page) -> anyhow::Result<()> {
+        let mut requests = page
+            .event_listener::<EventRequestWillBeSent>()
+            .await
+            .context("subscribing to requests")?;
+
+        let mut responses = page
+            .event_listener::<EventResponseReceived>()
+            .await
+            .context("subscribing to responses")?;
+
+        let mut finishes = page
+            .event_listener::<EventLoadingFinished>()
+            .await
+            .context("subscribing to loading finishes")?;
+
+        let recording = self.recording.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(event) = requests.next() => recording.lock().unwrap().request(&event),
+                    Some(event) = responses.next() => recording.lock().unwrap().response(&event),
+                    Some(event) = finishes.next() => recording.lock().unwrap().finish(&event),
+                    else => break,
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Entries of requests that follow belong to a new page, of the navigation to `url`
+    pub(super) fn start_page(&self, build: u64, url: &str) {
+        let mut recording = self.recording.lock().unwrap();
+
+        if self.reset_on_reload {
+            *recording = Recording::default();
+        }
+
+        recording.pages.push(Page {
+            started_date_time: iso8601(now()),
+            id: format!("build_{build}"),
+            title: url.to_owned(),
+            page_timings: PageTimings {},
+        });
+    }
+
+    /// Writes the finished entries to `path`, atomically
+    pub(crate) fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let document = self.recording.lock().unwrap().document();
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let mut temp_file = tempfile::NamedTempFile::new_in(parent)
+            .with_context(|| format!("failed to create a temporary file in {parent:?}"))?;
+
+        serde_json::to_writer_pretty(&mut temp_file, &document)?;
+        temp_file.write_all(b"\n")?;
+
+        temp_file
+            .persist(path)
+            .with_context(|| format!("failed to write {path:?}"))?;
+
+        Ok(())
+    }
+}
+
+impl Recording {
+    fn request(&mut self, event: &EventRequestWillBeSent) {
+        let request_id = event.request_id.inner();
+        let sent_at = *event.timestamp.inner();
+
+        // the same request ID is reused for the request that a redirect results in
+        if let Some(redirect_response) = &event.redirect_response
+            && let Some(index) = self.in_flight.remove(request_id)
+        {
+            let entry = &mut self.entries[index];
+            entry.response = Some((redirect_response.clone(), Some(event.request.url.clone())));
+            entry.finished_at = Some(sent_at);
+            entry.body_size = 0;
+        }
+
+        let url = match &event.request.url_fragment {
+            Some(fragment) => format!("{}{fragment}", event.request.url),
+            None => event.request.url.clone(),
+        };
+
+        self.in_flight
+            .insert(request_id.clone(), self.entries.len());
+
+        self.entries.push(Entry {
+            pageref: self.pages.last().map(|page| page.id.clone()),
+            started: *event.wall_time.inner(),
+            sent_at,
+            request: Request {
+                method: event.request.method.clone(),
+                query_string: query_string(&url),
+                url,
+                http_version: String::new(),
+                cookies: Vec::new(),
+                headers: name_values(&event.request.headers),
+                headers_size: -1,
+                body_size: -1,
+            },
+            response: None,
+            finished_at: None,
+            body_size: -1,
+        });
+    }
+
+    fn response(&mut self, event: &EventResponseReceived) {
+        if let Some(&index) = self.in_flight.get(event.request_id.inner()) {
+            self.entries[index].response = Some((event.response.clone(), None));
+        }
+    }
+
+    fn finish(&mut self, event: &EventLoadingFinished) {
+        if let Some(index) = self.in_flight.remove(event.request_id.inner()) {
+            let entry = &mut self.entries[index];
+            entry.finished_at = Some(*event.timestamp.inner());
+            // the size of the headers is not known separately, so this includes it
+            entry.body_size = event.encoded_data_length as i64;
+        }
+    }
+
+    /// Of the entries that have received a response and finished loading
+    fn document(&self) -> Document {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let (response, redirect_url) = entry.response.as_ref()?;
+                let finished_at = entry.finished_at?;
+                let timings = Timings::new(entry.sent_at, response.timing.as_ref(), finished_at);
+                let http_version = response.protocol.clone().unwrap_or_default();
+
+                Some(HarEntry {
+                    pageref: entry.pageref.clone(),
+                    started_date_time: iso8601(entry.started),
+                    time: timings.total(),
+                    request: Request {
+                        http_version: http_version.clone(),
+                        ..entry.request.clone()
+                    },
+                    response: HarResponse {
+                        status: response.status,
+                        status_text: response.status_text.clone(),
+                        http_version,
+                        cookies: Vec::new(),
+                        headers: name_values(&response.headers),
+                        content: Content {
+                            size: entry.body_size,
+                            mime_type: response.mime_type.clone(),
+                        },
+                        redirect_url: redirect_url.clone().unwrap_or_default(),
+                        headers_size: -1,
+                        body_size: entry.body_size,
+                    },
+                    cache: Cache {},
+                    timings,
+                })
+            })
+            .collect();
+
+        Document {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                pages: self.pages.clone(),
+                entries,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Document {
+    log: Log,
+}
+
+#[derive(Debug, Serialize)]
+struct Log {
+    version: &'static str,
+    creator: Creator,
+    pages: Vec<Page>,
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Page {
+    started_date_time: String,
+    id: String,
+    title: String,
+    page_timings: PageTimings,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct PageTimings {}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pageref: Option<String>,
+    started_date_time: String,
+    /// Milliseconds, the sum of the non-negative timings but `ssl`, which `connect` includes
+    time: f64,
+    request: Request,
+    response: HarResponse,
+    cache: Cache,
+    timings: Timings,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    query_string: Vec<NameValue>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: i64,
+    status_text: String,
+    http_version: String,
+    cookies: Vec<NameValue>,
+    headers: Vec<NameValue>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Cache {}
+
+#[derive(Debug, Clone, Serialize)]
+struct NameValue {
+    name: String,
+    value: String,
+}
+
+/// Milliseconds, `-1` where not applicable
+#[derive(Debug, Serialize)]
+struct Timings {
+    blocked: f64,
+    dns: f64,
+    connect: f64,
+    send: f64,
+    wait: f64,
+    receive: f64,
+    ssl: f64,
+}
+
+impl Timings {
+    /// `timing` is absent for responses that are not from the network, which are considered received throughout
+    fn new(sent_at: f64, timing: Option<&ResourceTiming>, finished_at: f64) -> Self {
+        let Some(timing) = timing else {
+            return Self {
+                blocked: -1.0,
+                dns: -1.0,
+                connect: -1.0,
+                send: 0.0,
+                wait: 0.0,
+                receive: ((finished_at - sent_at) * 1000.0).max(0.0),
+                ssl: -1.0,
+            };
+        };
+
+        let span = |start: f64, end: f64| {
+            if start >= 0.0 && end >= start {
+                end - start
+            } else {
+                -1.0
+            }
+        };
+
+        let first_start = [timing.dns_start, timing.connect_start, timing.send_start]
+            .into_iter()
+            .find(|start| *start >= 0.0)
+            .unwrap_or_default();
+
+        Self {
+            blocked: ((timing.request_time - sent_at) * 1000.0 + first_start).max(0.0),
+            dns: span(timing.dns_start, timing.dns_end),
+            connect: span(timing.connect_start, timing.connect_end),
+            send: span(timing.send_start, timing.send_end).max(0.0),
+            wait: (timing.receive_headers_end - timing.send_end).max(0.0),
+            receive: ((finished_at - timing.request_time) * 1000.0 - timing.receive_headers_end)
+                .max(0.0),
+            ssl: span(timing.ssl_start, timing.ssl_end),
+        }
+    }
+
+    fn total(&self) -> f64 {
+        [
+            self.blocked,
+            self.dns,
+            self.connect,
+            self.send,
+            self.wait,
+            self.receive,
+        ]
+        .into_iter()
+        .filter(|timing| *timing >= 0.0)
+        .sum()
+    }
+}
+
+/// CDP joins the values of a repeated header with newlines
+fn name_values(headers: &Headers) -> Vec<NameValue> {
+    let Some(headers) = headers.inner().as_object() else {
+        return Vec::new();
+    };
+
+    headers
+        .iter()
+        .flat_map(|(name, value)| {
+            value
+                .as_str()
+                .unwrap_or_default()
+                .split('\n')
+                .map(|value| NameValue {
+                    name: name.clone(),
+                    value: value.to_owned(),
+                })
+        })
+        .collect()
+}
+
+fn query_string(url: &str) -> Vec<NameValue> {
+    let Some((_, query)) = url.split_once('?') else {
+        return Vec::new();
+    };
+
+    let query = query.split_once('#').map_or(query, |(query, _)| query);
+
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+            NameValue {
+                name: decode(name),
+                value: decode(value),
+            }
+        })
+        .collect()
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// `seconds` since the Unix epoch, in UTC, with millisecond precision
+fn iso8601(seconds: f64) -> String {
+    let millis = (seconds * 1000.0) as i64;
+    let days = millis.div_euclid(86_400_000);
+    let millis_of_day = millis.rem_euclid(86_400_000);
+
+    // the civil from days algorithm of Howard Hinnant
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        millis_of_day / 3_600_000,
+        millis_of_day / 60_000 % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000,
+    )
+}
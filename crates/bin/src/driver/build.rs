@@ -1,19 +1,47 @@
-use std::{convert::Infallible, path::PathBuf, process::Stdio};
+mod log;
+
+use std::{
+    convert::Infallible,
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Context;
 use futures::FutureExt;
 use nix::{
+    fcntl::{AT_FDCWD, RenameFlags, renameat2},
     sys::signal::{SIGTERM, Signal},
     unistd::Pid,
 };
 use rxrust::prelude::*;
-use tokio::{process::Command, sync::mpsc};
+use tokio::{
+    process::Command,
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
 use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
 
 use crate::common::ForStdoutputLine as _;
 
+pub(crate) use self::log::BuildLog;
+
 pub(crate) struct BuildDriver {
     event_sender: mpsc::Sender<BuildEvent>,
+    process: Arc<Mutex<BuildProcess>>,
+}
+
+/// So that only a build process that has not exited is signalled, as the pid may have been reused
+#[derive(Debug, Default)]
+enum BuildProcess {
+    #[default]
+    NotRunning,
+    Running(Pid),
+    /// Exited unsuccessfully, to be rerun once the retry delay has elapsed, unless cancelled
+    RetryPending(Pid, oneshot::Sender<()>),
 }
 
 #[derive(Debug, derive_more::Display)]
@@ -23,9 +51,36 @@ pub(crate) enum BuildEvent {
     #[display("spawn error: {_0:#}")]
     SpawnError(anyhow::Error),
     #[display("{output}: {line}")]
-    OutputLine { output: Output, line: String },
+    OutputLine {
+        output: Output,
+        line: String,
+        /// Index of the build command, only if there are multiple
+        command_index: Option<usize>,
+        /// Only if timestamping build output
+        read_at: Option<Timestamp>,
+    },
+    #[display("timed out after {_0:?}")]
+    TimedOut(Duration),
+    /// Of the last build command that was run, which is the first that failed, if any
     #[display("exited with {_0:?}")]
     Exited(Option<i32>),
+    #[display("on-change command exited with {exit_code:?}")]
+    OnChangeExited {
+        exit_code: Option<i32>,
+        lines: Vec<(Output, String)>,
+    },
+    #[display("on-change command spawn error: {_0:#}")]
+    OnChangeSpawnError(anyhow::Error),
+    /// Because the required on-change command failed
+    #[display("build skipped")]
+    Skipped,
+    #[display("error reading {_0}: {_1}")]
+    ReadError(Output, std::io::Error),
+    /// Though all build commands succeeded, which is thus considered a failure
+    #[display("no files were written to the serve path")]
+    EmptyOutput,
+    #[display("error promoting staged build output: {_0}")]
+    PromotionError(nix::errno::Errno),
     #[display("error waiting for termination: {_0}")]
     WaitError(std::io::Error),
     #[display("error sending signal: {_0}")]
@@ -34,6 +89,29 @@ pub(crate) enum BuildEvent {
     SignalSent(Pid, Signal),
 }
 
+/// Displayed as seconds since the Unix epoch, with microsecond resolution
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timestamp(SystemTime);
+
+impl Timestamp {
+    fn now() -> Self {
+        Self(SystemTime::now())
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let since_epoch = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        write!(
+            f,
+            "{}.{:06}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros()
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy, derive_more::Display)]
 pub(crate) enum Output {
     #[display("stdout")]
@@ -42,113 +120,521 @@ pub(crate) enum Output {
     Err,
 }
 
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub(crate) enum OutputStream {
+    Stdout,
+    Stderr,
+    #[default]
+    Both,
+}
+
+impl OutputStream {
+    fn includes(self, output: Output) -> bool {
+        matches!(
+            (self, output),
+            (Self::Both, _) | (Self::Stdout, Output::Out) | (Self::Stderr, Output::Err)
+        )
+    }
+}
+
+/// How the serve path is provided to the build process
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub(crate) enum ServePathStyle {
+    #[default]
+    Absolute,
+    /// Relative to the working directory of the build process
+    Relative,
+}
+
+impl ServePathStyle {
+    /// Both paths are expected to be absolute
+    pub(crate) fn apply(self, serve_path: &Path, cwd: &Path) -> PathBuf {
+        match self {
+            Self::Absolute => serve_path.to_path_buf(),
+            Self::Relative => {
+                let common = serve_path
+                    .components()
+                    .zip(cwd.components())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                let ups = cwd.components().skip(common).map(|_| Component::ParentDir);
+                let relative: PathBuf = ups.chain(serve_path.components().skip(common)).collect();
+
+                if relative.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    relative
+                }
+            }
+        }
+    }
+}
+
+/// A command that is run on each change, before the build process is spawned
+#[derive(Debug, Clone)]
+pub(crate) struct OnChange {
+    pub(crate) path: PathBuf,
+    /// Whether the build is skipped if the command fails
+    pub(crate) is_required: bool,
+}
+
+/// Of a build command that exits with a non-zero status, not once timed out
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Retry {
+    /// Per build, shared by the sequence
+    pub(crate) count: usize,
+    pub(crate) delay: Duration,
+}
+
+/// Build output is written to `path` and exchanged with the serve path once the build succeeds,
+/// so that the output of a build in progress or of a failed one is never served
+#[derive(Debug, Clone)]
+pub(crate) struct Staging {
+    pub(crate) path: PathBuf,
+    pub(crate) serve_path: PathBuf,
+}
+
+impl Staging {
+    /// Atomically, so that no request observes an intermediate state
+    fn promote(&self) -> nix::Result<()> {
+        renameat2(
+            AT_FDCWD,
+            &self.path,
+            AT_FDCWD,
+            &self.serve_path,
+            RenameFlags::RENAME_EXCHANGE,
+        )
+    }
+
+    /// So that a build starts from empty, rather than from the previously promoted output.
+    /// The directory itself is kept, for its permissions; the build may have removed it, though
+    fn reset(&self) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.path)?;
+
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() {
+                std::fs::remove_dir_all(entry.path())?;
+            } else {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, derive_more::Display)]
 pub(crate) enum BuildCommand {
-    #[display("spawn {path:?} with env {envs:?}")]
+    #[display("spawn {paths:?} with args {args:?} in {cwd:?} with env {envs:?}")]
     Spawn {
-        path: PathBuf,
+        /// Run in sequence, until one fails
+        paths: Box<[PathBuf]>,
+        /// Passed to each of them
+        args: Vec<OsString>,
+        cwd: PathBuf,
         envs: Vec<(String, String)>,
+        /// Whether the build processes receive only `envs`, rather than the inherited environment as well
+        clean_env: bool,
+        output_stream: OutputStream,
+        log: Option<BuildLog>,
+        timeout: Option<Duration>,
+        retry: Option<Box<Retry>>,
+        timestamp_output: bool,
+        on_change: Option<Box<OnChange>>,
+        staging: Option<Box<Staging>>,
+        /// Whether `TMPDIR` is set to a directory that is created for this build and removed following it
+        isolate_tmp: bool,
+        /// A directory in which the build must write at least one file to succeed
+        require_output: Option<PathBuf>,
+        /// The serve path, which is warned about if it lacks files or `index.html` following a success
+        warn_empty: Option<PathBuf>,
     },
     #[display("send {_1} to {_0}")]
     Signal(Pid, Signal),
 }
 
 impl BuildDriver {
+    /// How long a timed out build process is given to exit after SIGTERM before it is killed
+    const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
     pub(crate) fn new() -> (SharedBoxedObservable<'static, BuildEvent, Infallible>, Self) {
         let (event_sender, event_receiver) = mpsc::channel(1);
-        let driver = Self { event_sender };
+        let driver = Self {
+            event_sender,
+            process: Arc::default(),
+        };
         (
             Shared::from_stream(ReceiverStream::new(event_receiver)).box_it(),
             driver,
         )
     }
 
+    /// Returns whether it succeeded
+    async fn run_on_change(
+        path: &Path,
+        cwd: &Path,
+        envs: &[(String, String)],
+        event_sender: &mpsc::Sender<BuildEvent>,
+    ) -> bool {
+        let output_result = Command::new(path)
+            .current_dir(cwd)
+            .envs(envs.iter().cloned())
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("spawn on-change process");
+
+        let output = match output_result {
+            Ok(output) => output,
+            Err(error) => {
+                event_sender
+                    .send(BuildEvent::OnChangeSpawnError(error))
+                    .await
+                    .unwrap();
+
+                return false;
+            }
+        };
+
+        let lines = [(Output::Out, &output.stdout), (Output::Err, &output.stderr)]
+            .into_iter()
+            .flat_map(|(stream, bytes)| {
+                String::from_utf8_lossy(bytes)
+                    .lines()
+                    .map(|line| (stream, line.to_owned()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        event_sender
+            .send(BuildEvent::OnChangeExited {
+                exit_code: output.status.code(),
+                lines,
+            })
+            .await
+            .unwrap();
+
+        output.status.success()
+    }
+
     pub(crate) fn effect(&self, command: BuildCommand) -> impl Future<Output = ()> + 'static {
         let event_sender = self.event_sender.clone();
+        let process = self.process.clone();
         async move {
             match command {
-                BuildCommand::Spawn { path, envs } => {
-                    let spawn_result = Command::new(path.clone())
-                        .envs(envs.clone())
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::piped())
-                        .spawn()
-                        .context("spawn build process");
-
-                    let mut child = match spawn_result {
-                        Ok(child) => child,
-                        Err(error) => {
-                            event_sender
-                                .send(BuildEvent::SpawnError(error))
-                                .await
-                                .unwrap();
-                            return;
-                        }
-                    };
+                BuildCommand::Spawn {
+                    paths,
+                    args,
+                    cwd,
+                    mut envs,
+                    clean_env,
+                    output_stream,
+                    log,
+                    timeout,
+                    retry,
+                    timestamp_output,
+                    on_change,
+                    staging,
+                    isolate_tmp,
+                    require_output,
+                    warn_empty,
+                } => {
+                    // removed once dropped, at the end of the build
+                    let build_tmp = if isolate_tmp {
+                        let result = tempfile::Builder::new()
+                            .prefix("conveyorbelt-build-tmp-")
+                            .tempdir()
+                            .context("create build temporary dir");
 
-                    let event_sender_clone = event_sender.clone();
-                    let stdout_join_handle = child
-                        .for_stdout_line(move |line| {
-                            let line = line.to_owned();
-                            let event_sender = event_sender_clone.clone();
-                            async move {
+                        match result {
+                            Ok(build_tmp) => Some(build_tmp),
+                            Err(error) => {
                                 event_sender
-                                    .send(BuildEvent::OutputLine {
-                                        output: Output::Out,
-                                        line,
-                                    })
+                                    .send(BuildEvent::SpawnError(error))
                                     .await
                                     .unwrap();
+
+                                return;
                             }
-                            .boxed()
-                        })
-                        .unwrap();
+                        }
+                    } else {
+                        None
+                    };
 
-                    let event_sender_clone = event_sender.clone();
-                    let stderr_join_handle = child
-                        .for_stderr_line(move |line| {
-                            let line = line.to_owned();
-                            let event_sender = event_sender_clone.clone();
-                            async move {
-                                event_sender
-                                    .send(BuildEvent::OutputLine {
-                                        output: Output::Err,
-                                        line,
+                    if let Some(build_tmp) = &build_tmp {
+                        envs.push((
+                            "TMPDIR".to_owned(),
+                            build_tmp.path().to_string_lossy().into_owned(),
+                        ));
+                    }
+
+                    let event = async {
+                        if let Some(on_change) = on_change {
+                            let is_success =
+                                Self::run_on_change(&on_change.path, &cwd, &envs, &event_sender)
+                                    .await;
+
+                            if !is_success && on_change.is_required {
+                                return BuildEvent::Skipped;
+                            }
+                        }
+
+                        if let Some(staging) = &staging
+                            && let Err(error) = staging.reset()
+                        {
+                            let error = anyhow::Error::new(error)
+                                .context(format!("reset staging dir {:?}", staging.path));
+
+                            return BuildEvent::SpawnError(error);
+                        }
+
+                        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+                        let is_sequence = paths.len() > 1;
+                        let mut retries_left = retry.as_ref().map_or(0, |retry| retry.count);
+
+                        for (index, path) in paths.into_iter().enumerate() {
+                            let command_index = is_sequence.then_some(index);
+
+                            loop {
+                                let mut command = Command::new(&path);
+
+                                if clean_env {
+                                    command.env_clear();
+                                }
+
+                                let spawn_result = command
+                                    .args(&args)
+                                    .current_dir(&cwd)
+                                    .envs(envs.clone())
+                                    .stdout(Stdio::piped())
+                                    .stderr(Stdio::piped())
+                                    .spawn()
+                                    .context("spawn build process");
+
+                                let mut child = match spawn_result {
+                                    Ok(child) => child,
+                                    Err(error) => return BuildEvent::SpawnError(error),
+                                };
+
+                                let event_sender_clone = event_sender.clone();
+                                let log_clone = log.clone();
+                                let stdout_join_handle = child
+                                    .for_stdout_line(move |line| {
+                                        if !output_stream.includes(Output::Out) {
+                                            return async {}.boxed();
+                                        }
+
+                                        let read_at = timestamp_output.then(Timestamp::now);
+
+                                        if let Some(log) = &log_clone {
+                                            log.push(line.to_owned());
+                                        }
+
+                                        let line = line.to_owned();
+                                        let event_sender = event_sender_clone.clone();
+                                        async move {
+                                            event_sender
+                                                .send(BuildEvent::OutputLine {
+                                                    output: Output::Out,
+                                                    line,
+                                                    command_index,
+                                                    read_at,
+                                                })
+                                                .await
+                                                .unwrap();
+                                        }
+                                        .boxed()
                                     })
-                                    .await
                                     .unwrap();
-                            }
-                            .boxed()
-                        })
-                        .unwrap();
 
-                    let pid = match child.id().context("obtain build process id") {
-                        Ok(pid) => Pid::from_raw(pid as i32),
-                        Err(error) => {
-                            event_sender
-                                .send(BuildEvent::SpawnError(error))
-                                .await
-                                .unwrap();
+                                let event_sender_clone = event_sender.clone();
+                                let log_clone = log.clone();
+                                let stderr_join_handle = child
+                                    .for_stderr_line(move |line| {
+                                        if !output_stream.includes(Output::Err) {
+                                            return async {}.boxed();
+                                        }
+
+                                        let read_at = timestamp_output.then(Timestamp::now);
+
+                                        if let Some(log) = &log_clone {
+                                            log.push(line.to_owned());
+                                        }
+
+                                        let line = line.to_owned();
+                                        let event_sender = event_sender_clone.clone();
+                                        async move {
+                                            event_sender
+                                                .send(BuildEvent::OutputLine {
+                                                    output: Output::Err,
+                                                    line,
+                                                    command_index,
+                                                    read_at,
+                                                })
+                                                .await
+                                                .unwrap();
+                                        }
+                                        .boxed()
+                                    })
+                                    .unwrap();
+
+                                let pid = match child.id().context("obtain build process id") {
+                                    Ok(pid) => Pid::from_raw(pid as i32),
+                                    Err(error) => return BuildEvent::SpawnError(error),
+                                };
+
+                                *process.lock().unwrap() = BuildProcess::Running(pid);
+                                event_sender.send(BuildEvent::Spawn(pid)).await.unwrap();
+
+                                let wait_result = match timeout.zip(deadline) {
+                                    Some((timeout, deadline)) => {
+                                        match tokio::time::timeout_at(deadline, child.wait()).await
+                                        {
+                                            Ok(wait_result) => wait_result,
+                                            Err(_elapsed) => {
+                                                event_sender
+                                                    .send(BuildEvent::TimedOut(timeout))
+                                                    .await
+                                                    .unwrap();
+
+                                                Self::terminate(&mut child, pid).await
+                                            }
+                                        }
+                                    }
+                                    None => child.wait().await,
+                                };
+
+                                let wait_event = match wait_result {
+                                    Ok(exit_status) if exit_status.success() => None,
+                                    Ok(exit_status) => Some(BuildEvent::Exited(exit_status.code())),
+                                    Err(error) => Some(BuildEvent::WaitError(error)),
+                                };
 
-                            return;
+                                let is_retrying =
+                                    matches!(wait_event, Some(BuildEvent::Exited(Some(_))))
+                                        && retries_left > 0
+                                        && deadline
+                                            .is_none_or(|deadline| Instant::now() < deadline);
+
+                                let retry_cancellation = if is_retrying {
+                                    let (sender, receiver) = oneshot::channel();
+                                    *process.lock().unwrap() =
+                                        BuildProcess::RetryPending(pid, sender);
+                                    Some(receiver)
+                                } else {
+                                    *process.lock().unwrap() = BuildProcess::NotRunning;
+                                    None
+                                };
+
+                                // TODO await concurrently
+                                for (output, join_handle) in [
+                                    (Output::Err, stderr_join_handle),
+                                    (Output::Out, stdout_join_handle),
+                                ] {
+                                    if let Err(error) = join_handle.await.unwrap() {
+                                        event_sender
+                                            .send(BuildEvent::ReadError(output, error))
+                                            .await
+                                            .unwrap();
+                                    }
+                                }
+
+                                match (wait_event, retry_cancellation) {
+                                    (
+                                        Some(BuildEvent::Exited(Some(exit_code))),
+                                        Some(retry_cancellation),
+                                    ) => {
+                                        retries_left -= 1;
+                                        let Retry { count, delay } = **retry.as_ref().unwrap();
+
+                                        warn!(
+                                            "build command {path:?} exited with {exit_code}; \
+                                            retry {} of {count} in {delay:?}",
+                                            count - retries_left
+                                        );
+
+                                        tokio::select! {
+                                            _ = tokio::time::sleep(delay) => {
+                                                *process.lock().unwrap() = BuildProcess::NotRunning;
+                                            }
+                                            _ = retry_cancellation => {
+                                                return BuildEvent::Exited(Some(exit_code));
+                                            }
+                                        }
+                                    }
+                                    (Some(wait_event), _) => return wait_event,
+                                    (None, _) => break,
+                                }
+                            }
+                        }
+
+                        if let Some(path) = &require_output {
+                            match contains_file(path) {
+                                Ok(true) => {}
+                                Ok(false) => return BuildEvent::EmptyOutput,
+                                // rather than failing a build whose output could not be checked
+                                Err(error) => warn!("failed to check {path:?} for files: {error}"),
+                            }
                         }
-                    };
 
-                    event_sender.send(BuildEvent::Spawn(pid)).await.unwrap();
+                        if let Some(staging) = &staging
+                            && let Err(errno) = staging.promote()
+                        {
+                            return BuildEvent::PromotionError(errno);
+                        }
 
-                    let wait_event = match child.wait().await {
-                        Ok(exit_status) => BuildEvent::Exited(exit_status.code()),
-                        Err(error) => BuildEvent::WaitError(error),
-                    };
+                        if let Some(path) = &warn_empty {
+                            match contains_file(path) {
+                                Ok(false) => warn!(
+                                    "the build succeeded without writing any files to {path:?}"
+                                ),
+                                Ok(true) if !path.join("index.html").exists() => warn!(
+                                    "the build succeeded without writing index.html to {path:?}"
+                                ),
+                                Ok(true) => {}
+                                Err(error) => warn!("failed to check {path:?} for files: {error}"),
+                            }
+                        }
 
-                    // TODO await concurrently
-                    stderr_join_handle.await.unwrap();
-                    stdout_join_handle.await.unwrap();
+                        BuildEvent::Exited(Some(0))
+                    }
+                    .await;
 
-                    event_sender.send(wait_event).await.unwrap();
+                    // so that it is removed by the time the build is reported to have finished
+                    drop(build_tmp);
+                    event_sender.send(event).await.unwrap();
                 }
 
                 BuildCommand::Signal(pid, signal) => {
+                    let is_running = {
+                        let mut process = process.lock().unwrap();
+
+                        match std::mem::take(&mut *process) {
+                            BuildProcess::RetryPending(pending, retry_cancellation)
+                                if pending == pid =>
+                            {
+                                info!(
+                                    "cancelling the pending retry of the build instead of signalling the exited {pid}"
+                                );
+                                let _ = retry_cancellation.send(());
+                                false
+                            }
+                            current => {
+                                let is_running = matches!(current, BuildProcess::Running(running) if running == pid);
+                                *process = current;
+                                is_running
+                            }
+                        }
+                    };
+
+                    // its exit is reported regardless
+                    if !is_running {
+                        return;
+                    }
+
                     if let Err(error) = nix::sys::signal::kill(pid, signal) {
                         event_sender
                             .send(BuildEvent::SignalError(error))
@@ -157,11 +643,40 @@ impl BuildDriver {
                     };
 
                     event_sender
-                        .send(BuildEvent::SignalSent(pid, SIGTERM))
+                        .send(BuildEvent::SignalSent(pid, signal))
                         .await
                         .unwrap();
                 }
             }
         }
     }
+
+    async fn terminate(
+        child: &mut tokio::process::Child,
+        pid: Pid,
+    ) -> std::io::Result<std::process::ExitStatus> {
+        // the process might have exited in the meantime
+        let _ = nix::sys::signal::kill(pid, SIGTERM);
+
+        match tokio::time::timeout(Self::KILL_GRACE_PERIOD, child.wait()).await {
+            Ok(wait_result) => wait_result,
+            Err(_elapsed) => {
+                child.start_kill()?;
+                child.wait().await
+            }
+        }
+    }
+}
+
+/// Whether `dir` or any of its descendant directories contains an entry that is not a directory
+fn contains_file(dir: &Path) -> std::io::Result<bool> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if !entry.file_type()?.is_dir() || contains_file(&entry.path())? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
 }
@@ -0,0 +1,47 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::broadcast;
+
+/// The most recent lines of build output, across builds
+#[derive(Debug, Clone)]
+pub(crate) struct BuildLog {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    sender: broadcast::Sender<String>,
+}
+
+impl BuildLog {
+    const CAPACITY: usize = 1000;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            lines: Arc::default(),
+            sender: broadcast::Sender::new(Self::CAPACITY),
+        }
+    }
+
+    pub(super) fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+
+        if lines.len() == Self::CAPACITY {
+            lines.pop_front();
+        }
+
+        lines.push_back(line.clone());
+        // there may be no subscribers
+        let _ = self.sender.send(line);
+    }
+
+    /// The last `n` lines, or all of them
+    pub(crate) fn tail(&self, n: Option<usize>) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        let skip = n.map_or(0, |n| lines.len().saturating_sub(n));
+        lines.iter().skip(skip).cloned().collect()
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.sender.subscribe()
+    }
+}
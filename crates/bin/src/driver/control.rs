@@ -0,0 +1,187 @@
+use std::{convert::Infallible, path::Path, sync::Arc};
+
+use anyhow::Context as _;
+use rxrust::{Observable as _, ObservableFactory as _, Shared, SharedBoxedObservable};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, watch},
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::common::StateForTesting;
+
+/// The line that a client writes to the control socket to trigger a build
+const REBUILD: &str = "rebuild";
+
+/// A bound Unix domain socket, at which clients trigger builds
+#[derive(Debug)]
+pub(crate) struct ControlSocket(std::os::unix::net::UnixListener);
+
+impl ControlSocket {
+    pub(crate) fn bind(path: &Path) -> anyhow::Result<Self> {
+        bind(path, "control").map(Self)
+    }
+}
+
+/// A bound Unix domain socket, at which clients obtain the state as a JSON line
+#[derive(Debug)]
+pub(crate) struct StateSocket {
+    listener: std::os::unix::net::UnixListener,
+    /// `None` until initialized
+    state: watch::Sender<Option<StateForTesting>>,
+}
+
+impl StateSocket {
+    pub(crate) fn bind(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self {
+            listener: bind(path, "state")?,
+            state: watch::Sender::new(None),
+        })
+    }
+
+    pub(crate) fn update(&self, state: StateForTesting) {
+        self.state.send_replace(Some(state));
+    }
+}
+
+fn bind(path: &Path, purpose: &str) -> anyhow::Result<std::os::unix::net::UnixListener> {
+    let listener = std::os::unix::net::UnixListener::bind(path)
+        .with_context(|| format!("failed to bind {purpose} socket at {path:?}"))?;
+
+    listener
+        .set_nonblocking(true)
+        .with_context(|| format!("could not set {purpose} socket non-blocking"))?;
+
+    Ok(listener)
+}
+
+#[derive(Debug, derive_more::Display)]
+pub(crate) enum ControlCommand {
+    #[display("listen")]
+    Listen(Arc<ControlSocket>),
+    #[display("serve state")]
+    ServeState(Arc<StateSocket>),
+}
+
+#[derive(Debug, derive_more::Display)]
+pub(crate) enum ControlEvent {
+    #[display("rebuild requested")]
+    Rebuild,
+    #[display("error: {_0:#}")]
+    Error(anyhow::Error),
+}
+
+pub(crate) struct ControlDriver {
+    event_sender: mpsc::Sender<ControlEvent>,
+}
+
+impl ControlDriver {
+    pub(crate) fn new() -> (
+        SharedBoxedObservable<'static, ControlEvent, Infallible>,
+        Self,
+    ) {
+        let (event_sender, event_receiver) = mpsc::channel(1);
+        let driver = Self { event_sender };
+        (
+            Shared::from_stream(ReceiverStream::new(event_receiver)).box_it(),
+            driver,
+        )
+    }
+
+    pub(crate) fn effect(&self, command: ControlCommand) -> impl Future<Output = ()> + 'static {
+        let event_sender = self.event_sender.clone();
+        async move {
+            let (listener, purpose) = match &command {
+                ControlCommand::Listen(socket) => (&socket.0, "control"),
+                ControlCommand::ServeState(socket) => (&socket.listener, "state"),
+            };
+
+            let listener = match listener
+                .try_clone()
+                .and_then(UnixListener::from_std)
+                .with_context(|| format!("listening at {purpose} socket"))
+            {
+                Ok(listener) => listener,
+                Err(error) => {
+                    event_sender.send(ControlEvent::Error(error)).await.unwrap();
+                    return;
+                }
+            };
+
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(error) => {
+                        event_sender
+                            .send(ControlEvent::Error(
+                                anyhow::Error::new(error).context("accepting connection"),
+                            ))
+                            .await
+                            .unwrap();
+
+                        continue;
+                    }
+                };
+
+                match &command {
+                    ControlCommand::Listen(_) => {
+                        tokio::spawn(Self::serve(stream, event_sender.clone()));
+                    }
+                    ControlCommand::ServeState(socket) => {
+                        tokio::spawn(Self::serve_state(stream, socket.state.subscribe()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the state once initialized and closes the connection
+    async fn serve_state(
+        mut stream: UnixStream,
+        mut state: watch::Receiver<Option<StateForTesting>>,
+    ) {
+        let line = match state.wait_for(Option::is_some).await {
+            Ok(state) => format!("{}\n", state.as_ref().unwrap()),
+            // shutting down
+            Err(_) => return,
+        };
+
+        // the client is gone
+        let _ = stream.write_all(line.as_bytes()).await;
+    }
+
+    /// Responds to each line with `ok` or with an error message
+    async fn serve(stream: UnixStream, event_sender: mpsc::Sender<ControlEvent>) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(error) => {
+                    event_sender
+                        .send(ControlEvent::Error(
+                            anyhow::Error::new(error).context("reading from connection"),
+                        ))
+                        .await
+                        .unwrap();
+
+                    break;
+                }
+            };
+
+            let response = if line.trim() == REBUILD {
+                event_sender.send(ControlEvent::Rebuild).await.unwrap();
+                "ok\n".to_owned()
+            } else {
+                format!("unknown command: {line:?}\n")
+            };
+
+            if writer.write_all(response.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+}
@@ -1,22 +1,65 @@
-use git2::Repository;
-use notify::{INotifyWatcher, RecursiveMode, Watcher as _};
+use notify::{INotifyWatcher, PollWatcher, RecursiveMode, Watcher as _};
 use rxrust::prelude::*;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
 
-use std::{convert::Infallible, path::PathBuf};
+use crate::project_path::IgnoreRepository;
+
+use std::{
+    convert::Infallible,
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// At the project root, consulted in addition to those of git
+const IGNORE_FILE_NAMES: [&str; 2] = [".ignore", ".conveyorbeltignore"];
 
 #[derive(Debug, derive_more::Display)]
 pub(crate) enum FsWatchCommand {
-    #[display("init at {_0:?}")]
-    Init(PathBuf),
+    #[display("init at {path:?}")]
+    Init {
+        path: PathBuf,
+        /// Watched as well, with ignores respected only within `path`
+        extra_paths: Vec<PathBuf>,
+        /// Of the files whose changes are not ignored; any if empty
+        extensions: Vec<String>,
+        /// Consulted in addition to those at `path`, with patterns relative to it
+        ignore_files: Vec<PathBuf>,
+        poll_interval: Option<Duration>,
+        /// Those of git and of the ignore files
+        respect_ignores: bool,
+    },
+    #[display("resolve the project root")]
+    ResolveProjectRoot {
+        explicit: Option<PathBuf>,
+        origin: PathBuf,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) enum Watcher {
+    Native(INotifyWatcher),
+    /// For filesystems on which native notifications do not arrive
+    Poll(PollWatcher),
+}
+
+impl Watcher {
+    fn watch(&mut self, path: &std::path::Path) -> notify::Result<()> {
+        match self {
+            Self::Native(watcher) => watcher.watch(path, RecursiveMode::Recursive),
+            Self::Poll(watcher) => watcher.watch(path, RecursiveMode::Recursive),
+        }
+    }
 }
 
 #[derive(Debug, derive_more::Display)]
 pub(crate) enum FsWatchEvent {
     WatcherCreationError(notify::Error),
     #[display("watcher created")]
-    Watching(INotifyWatcher),
+    Watching(Watcher),
     #[display("watch error: {_0}")]
     WatcherWatchError(notify::Error),
     #[display("event error: {_0}")]
@@ -25,13 +68,26 @@ pub(crate) enum FsWatchEvent {
     Change(FsChange),
     #[display("git2 error: {_0}")]
     Git2Error(git2::Error),
+    #[display("project root resolved: {_0:?}")]
+    ProjectRootResolved(PathBuf),
+    #[display("project root error: {_0:#}")]
+    ProjectRootError(anyhow::Error),
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct FsChange {
     pub(crate) path: PathBuf,
     pub(crate) kind: FsChangeKind,
-    pub(crate) is_ignored: bool,
+    pub(crate) ignored: Option<Ignored>,
+}
+
+/// Why a change is ignored
+#[derive(Debug, Clone, Copy, derive_more::Display)]
+pub(crate) enum Ignored {
+    #[display("git ignored")]
+    Git,
+    #[display("extension not watched")]
+    Extension,
 }
 
 impl std::fmt::Display for FsChange {
@@ -40,8 +96,8 @@ impl std::fmt::Display for FsChange {
         let kind = &self.kind;
         let mut string = format!("{path:?}");
 
-        if self.is_ignored {
-            string.push_str(" (git ignored)");
+        if let Some(ignored) = self.ignored {
+            string.push_str(&format!(" ({ignored})"));
         }
 
         string.push_str(&format!(" {kind}"));
@@ -94,9 +150,25 @@ impl FsWatchDriver {
         let event_sender = self.event_sender.clone();
         async move {
             match command {
-                FsWatchCommand::Init(path_buf) => {
+                FsWatchCommand::ResolveProjectRoot { explicit, origin } => {
+                    let event =
+                        match crate::project_path::obtain(explicit.as_deref(), &origin).await {
+                            Ok(project_root) => FsWatchEvent::ProjectRootResolved(project_root),
+                            Err(error) => FsWatchEvent::ProjectRootError(error),
+                        };
+
+                    event_sender.send(event).await.unwrap();
+                }
+                FsWatchCommand::Init {
+                    path: path_buf,
+                    extra_paths,
+                    extensions,
+                    ignore_files,
+                    poll_interval,
+                    respect_ignores,
+                } => {
                     let event_sender_clone = event_sender.clone();
-                    let repository = match Repository::open_from_env() {
+                    let repository = match IgnoreRepository::open(&path_buf) {
                         Ok(repository) => repository,
                         Err(error) => {
                             event_sender_clone
@@ -106,6 +178,41 @@ impl FsWatchDriver {
                         }
                     };
 
+                    if respect_ignores {
+                        // libgit2 anchors added rules at the work tree rather than at the project root
+                        let prefix = repository
+                            .workdir()
+                            .and_then(|workdir| workdir.canonicalize().ok())
+                            .and_then(|workdir| {
+                                path_buf.strip_prefix(workdir).ok().map(Path::to_path_buf)
+                            })
+                            .unwrap_or_default();
+
+                        let ignore_file_paths = IGNORE_FILE_NAMES
+                            .into_iter()
+                            .map(|file_name| path_buf.join(file_name))
+                            .chain(ignore_files);
+
+                        for path in ignore_file_paths {
+                            let rules = match std::fs::read_to_string(&path) {
+                                Ok(rules) => rules,
+                                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                                Err(error) => {
+                                    warn!("failed to read {path:?}: {error}");
+                                    continue;
+                                }
+                            };
+
+                            let rules = rebase_ignore_rules(&rules, &prefix);
+
+                            if let Err(error) = repository.add_ignore_rule(&rules) {
+                                warn!("failed to add ignore rules of {path:?}: {error}");
+                            }
+                        }
+                    }
+
+                    let root = path_buf.clone();
+
                     let event_handler = move |event| {
                         let event: notify::Event = match event {
                             Ok(event) => event,
@@ -137,9 +244,12 @@ impl FsWatchDriver {
                         };
 
                         for path in event.paths {
-                            let is_ignored = match repository.is_path_ignored(&path) {
-                                Ok(is_ignored) => is_ignored,
-                                Err(error) => {
+                            let is_ignored = match (respect_ignores && path.starts_with(&root))
+                                .then(|| repository.is_ignored(&path))
+                            {
+                                None => false,
+                                Some(Ok(is_ignored)) => is_ignored,
+                                Some(Err(error)) => {
                                     event_sender_clone
                                         .blocking_send(FsWatchEvent::Git2Error(error))
                                         .unwrap();
@@ -147,16 +257,47 @@ impl FsWatchDriver {
                                 }
                             };
 
+                            let is_watched_extension = extensions.is_empty()
+                                || path.extension().and_then(OsStr::to_str).is_some_and(
+                                    |extension| {
+                                        extensions.iter().any(|watched| watched == extension)
+                                    },
+                                );
+
+                            let ignored = if is_ignored {
+                                Some(Ignored::Git)
+                            } else if !is_watched_extension {
+                                Some(Ignored::Extension)
+                            } else {
+                                None
+                            };
+
                             event_sender_clone
                                 .blocking_send(FsWatchEvent::Change(FsChange {
                                     path,
                                     kind,
-                                    is_ignored,
+                                    ignored,
                                 }))
                                 .unwrap();
                         }
                     };
-                    let mut watcher = match notify::recommended_watcher(event_handler) {
+                    let watcher_result = match poll_interval {
+                        Some(interval) => {
+                            info!(
+                                "polling for changes every {interval:?}, which costs more CPU than native notifications"
+                            );
+
+                            PollWatcher::new(
+                                event_handler,
+                                notify::Config::default().with_poll_interval(interval),
+                            )
+                            .map(Watcher::Poll)
+                        }
+                        None => INotifyWatcher::new(event_handler, notify::Config::default())
+                            .map(Watcher::Native),
+                    };
+
+                    let mut watcher = match watcher_result {
                         Ok(watcher) => watcher,
                         Err(error) => {
                             event_sender
@@ -167,11 +308,15 @@ impl FsWatchDriver {
                         }
                     };
 
-                    if let Err(error) = watcher.watch(&path_buf, RecursiveMode::Recursive) {
-                        event_sender
-                            .send(FsWatchEvent::WatcherWatchError(error))
-                            .await
-                            .unwrap();
+                    for path in [&path_buf].into_iter().chain(&extra_paths) {
+                        if let Err(error) = watcher.watch(path) {
+                            event_sender
+                                .send(FsWatchEvent::WatcherWatchError(error))
+                                .await
+                                .unwrap();
+
+                            break;
+                        }
                     }
                     event_sender
                         .send(FsWatchEvent::Watching(watcher))
@@ -182,3 +327,46 @@ impl FsWatchDriver {
         }
     }
 }
+
+/// Rewrites gitignore `rules` that are relative to `prefix`, a relative directory,
+/// so that they are relative to the directory that `prefix` is relative to
+fn rebase_ignore_rules(rules: &str, prefix: &Path) -> String {
+    if prefix.as_os_str().is_empty() {
+        return rules.to_owned();
+    }
+
+    let prefix = prefix
+        .to_string_lossy()
+        .chars()
+        .fold(String::new(), |mut escaped, char| {
+            if matches!(char, '*' | '?' | '[' | '\\' | '!' | '#' | ' ') {
+                escaped.push('\\');
+            }
+
+            escaped.push(char);
+            escaped
+        });
+
+    rules
+        .lines()
+        .map(|line| {
+            if line.trim().is_empty() || line.starts_with('#') {
+                return line.to_owned();
+            }
+
+            let (negation, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => ("!", pattern),
+                None => ("", line),
+            };
+
+            // a pattern with a slash other than a trailing one is anchored
+            if pattern.trim_end_matches('/').contains('/') {
+                let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+                format!("{negation}{prefix}/{pattern}")
+            } else {
+                format!("{negation}{prefix}/**/{pattern}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,30 +1,74 @@
+mod access_log;
+mod build_log;
+mod etag;
+mod health;
+mod live_reload;
+mod maintenance;
+mod manifest;
+mod output_digest;
+mod stream_test;
+mod tls;
+mod unused_assets;
+
 use std::{
     convert::Infallible,
-    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
-    path::PathBuf,
+    fs::Permissions,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
+    os::{
+        fd::{BorrowedFd, FromRawFd as _, RawFd},
+        unix::fs::PermissionsExt as _,
+    },
+    path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
-use anyhow::Context as _;
-use hyper::StatusCode;
+use anyhow::{Context as _, bail};
+use hyper::{
+    Body, Request, StatusCode, Uri,
+    header::IF_NONE_MATCH,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+};
 use rxrust::prelude::*;
+use socket2::{SockRef, Socket, Type};
 use static_web_server::{
     handler::{RequestHandler, RequestHandlerOpts},
-    service::RouterService,
+    maintenance_mode,
+    settings::CompressionLevel,
 };
 use tempfile::TempDir;
 use tokio::{
-    sync::{mpsc, oneshot},
+    net::TcpStream,
+    sync::{mpsc, watch},
     task::JoinHandle,
 };
+use tokio_rustls::server::TlsStream;
 use tokio_stream::wrappers::ReceiverStream;
+use tracing::warn;
 
-#[derive(Debug, derive_more::Deref)]
-pub(crate) struct ServeDir(TempDir);
+use crate::driver::build::BuildLog;
+
+pub(crate) use self::{
+    etag::EtagStrategy,
+    health::Readiness,
+    live_reload::{ReloadMethod, ReloadTrigger},
+    maintenance::Maintenance,
+    tls::TlsCertificate,
+    unused_assets::RequestedPaths,
+};
+
+#[derive(Debug)]
+pub(crate) enum ServeDir {
+    Temporary(TempDir),
+    /// Provided by the user and retained on exit
+    Persistent(PathBuf),
+}
 
 impl std::fmt::Display for ServeDir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.0.path())
+        write!(f, "{:?}", self.path())
     }
 }
 
@@ -32,18 +76,88 @@ impl std::fmt::Display for ServeDir {
 // command: server: server at address 127.0.0.1:40521
 
 impl ServeDir {
-    pub(crate) fn obtain() -> anyhow::Result<Self> {
-        let temp_dir = TempDir::new()?;
-        Ok(Self(temp_dir))
+    pub(crate) fn obtain(
+        path: Option<&Path>,
+        project_root: &Path,
+        permissions: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        let serve_dir = match path {
+            None => Self::Temporary(TempDir::new()?),
+            Some(path) => {
+                std::fs::create_dir_all(path)
+                    .with_context(|| format!("failed to create {path:?}"))?;
+
+                let path = path
+                    .canonicalize()
+                    .with_context(|| format!("failed to canonicalize {path:?}"))?;
+
+                if path.starts_with(project_root)
+                    && !crate::project_path::is_ignored(project_root, &path)?
+                {
+                    bail!("serve dir {path:?} is in the repository but not ignored by git");
+                }
+
+                Self::Persistent(path)
+            }
+        };
+
+        if let Some(mode) = permissions {
+            let path = serve_dir.path();
+            std::fs::set_permissions(path, Permissions::from_mode(mode))
+                .with_context(|| format!("failed to set permissions {mode:o} of {path:?}"))?;
+        }
+
+        Ok(serve_dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            Self::Temporary(temp_dir) => temp_dir.path(),
+            Self::Persistent(path) => path,
+        }
+    }
+
+    /// A directory beside it, so on the same filesystem, with the same permissions,
+    /// for build output to be staged in
+    pub(crate) fn create_staging_dir(&self, project_root: &Path) -> anyhow::Result<TempDir> {
+        let path = self.path();
+        let parent = path
+            .parent()
+            .with_context(|| format!("serve dir {path:?} has no parent"))?;
+
+        let staging_dir = tempfile::Builder::new()
+            .prefix(".conveyorbelt-staging-")
+            .tempdir_in(parent)
+            .with_context(|| format!("failed to create staging dir in {parent:?}"))?;
+
+        if staging_dir.path().starts_with(project_root)
+            && !crate::project_path::is_ignored(project_root, staging_dir.path())?
+        {
+            bail!(
+                "staging dir {:?} is in the repository but not ignored by git",
+                staging_dir.path()
+            );
+        }
+
+        let permissions = std::fs::metadata(path)
+            .with_context(|| format!("failed to obtain metadata of {path:?}"))?
+            .permissions();
+
+        std::fs::set_permissions(staging_dir.path(), permissions)
+            .with_context(|| format!("failed to set permissions of {:?}", staging_dir.path()))?;
+
+        Ok(staging_dir)
     }
 }
 
 #[derive(Debug, derive_more::Display)]
 pub(crate) enum ServerCommand {
     #[display("spawn at {_0}")]
-    Spawn(Arc<ServeDir>),
+    Spawn(Arc<ServeDir>, Box<ServerOptions>),
     #[display("shutdown")]
     Shutdown(Server),
+    #[display("trigger reload")]
+    Reload(ReloadTrigger),
 }
 
 #[derive(Debug, derive_more::Display)]
@@ -58,6 +172,52 @@ pub(crate) enum ServerEvent {
     ShutdownError(hyper::Error),
     #[display("task join error: {_0}")]
     TaskJoinError(tokio::task::JoinError),
+    #[display("reload triggered")]
+    Reload,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ServerOptions {
+    pub(crate) host: IpAddr,
+    pub(crate) port: Option<u16>,
+    /// An inherited listening socket to serve on instead of binding to `host` and `port`
+    pub(crate) listen_fd: Option<RawFd>,
+    /// Whether to serve only HTTP/2, with prior knowledge unless over TLS
+    pub(crate) http2_only: bool,
+    /// Serve HTTPS instead of HTTP
+    pub(crate) tls: Option<TlsCertificate>,
+    pub(crate) redirect_trailing_slash: bool,
+    pub(crate) serve_hidden: bool,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) etag_strategy: EtagStrategy,
+    pub(crate) inject_reload_script: bool,
+    pub(crate) build_log: Option<BuildLog>,
+    pub(crate) compression: bool,
+    pub(crate) page404_status: StatusCode,
+    /// Instead of `50x.html` of the serve path
+    pub(crate) page50x: Option<PathBuf>,
+    /// Whether all requests are responded to with the maintenance page
+    pub(crate) maintenance_mode: bool,
+    /// Responded with while in maintenance, instead of a default message
+    pub(crate) maintenance_file: Option<PathBuf>,
+    pub(crate) maintenance_status: StatusCode,
+    pub(crate) spa: bool,
+    /// Reported on shutdown
+    pub(crate) requested_paths: Option<RequestedPaths>,
+    /// Whether to respond at `/health` according to readiness
+    pub(crate) health: bool,
+    /// Written the URL to once listening, and removed on shutdown
+    pub(crate) url_file: Option<PathBuf>,
+    /// The delay between chunks, if responding at `/__conveyorbelt/stream`
+    pub(crate) stream_test: Option<Duration>,
+    /// Whether to log each request
+    pub(crate) access_log: bool,
+    /// Beyond which requests are logged at warn level, regardless of `access_log`
+    pub(crate) slow_request: Option<Duration>,
+    /// Allowed origins, comma-separated, or `*`
+    pub(crate) cors: Option<String>,
+    /// How long open connections are waited for on shutdown before being dropped; unlimited if `None`
+    pub(crate) shutdown_timeout: Option<Duration>,
 }
 
 pub(crate) struct ServerDriver {
@@ -81,8 +241,8 @@ impl ServerDriver {
         let event_sender = self.event_sender.clone();
         async move {
             let event = match command {
-                ServerCommand::Spawn(serve_dir) => {
-                    match Server::spawn(serve_dir.path().to_path_buf()) {
+                ServerCommand::Spawn(serve_dir, options) => {
+                    match Server::spawn(serve_dir.path().to_path_buf(), *options) {
                         Ok(server) => ServerEvent::Spawn(server),
                         Err(error) => ServerEvent::SpawnError(error),
                     }
@@ -92,6 +252,10 @@ impl ServerDriver {
                     Ok(Err(error)) => ServerEvent::ShutdownError(error),
                     Err(join_error) => ServerEvent::TaskJoinError(join_error),
                 },
+                ServerCommand::Reload(reload_trigger) => {
+                    reload_trigger.trigger();
+                    ServerEvent::Reload
+                }
             };
             event_sender.send(event).await.unwrap();
         }
@@ -101,8 +265,20 @@ impl ServerDriver {
 #[derive(Debug)]
 pub(crate) struct Server {
     address: SocketAddr,
-    shutdown_sender: oneshot::Sender<()>,
+    /// Whether HTTPS is served
+    tls: bool,
+    shutdown_sender: watch::Sender<()>,
     join_handle: JoinHandle<hyper::Result<()>>,
+    reload_trigger: ReloadTrigger,
+    root: PathBuf,
+    requested_paths: Option<RequestedPaths>,
+    /// As of the last call to [`Self::update_output_digest`]
+    output_digest: Option<u64>,
+    readiness: Readiness,
+    /// Switched on and off at runtime, unlike `--maintenance-mode`
+    maintenance: Maintenance,
+    url_file: Option<PathBuf>,
+    shutdown_timeout: Option<Duration>,
 }
 
 impl std::fmt::Display for Server {
@@ -112,35 +288,82 @@ impl std::fmt::Display for Server {
 }
 
 impl Server {
-    fn spawn(path: PathBuf) -> anyhow::Result<Self> {
+    fn spawn(path: PathBuf, options: ServerOptions) -> anyhow::Result<Self> {
+        let ServerOptions {
+            host,
+            port,
+            listen_fd,
+            http2_only,
+            tls,
+            redirect_trailing_slash,
+            serve_hidden,
+            follow_symlinks,
+            etag_strategy,
+            inject_reload_script,
+            build_log,
+            compression,
+            page404_status,
+            page50x,
+            maintenance_mode,
+            maintenance_file,
+            maintenance_status,
+            spa,
+            requested_paths,
+            health,
+            url_file,
+            stream_test,
+            access_log,
+            slow_request,
+            cors,
+            shutdown_timeout,
+        } = options;
+
+        // in lieu of which a default message is responded with
+        let maintenance_file = maintenance_file.unwrap_or_default();
+
         let handler_opts = RequestHandlerOpts {
             root_dir: path.clone(),
-            compression: false,
-            compression_static: false,
-            cors: None,
+            compression,
+            compression_level: CompressionLevel::Default,
+            compression_static: compression,
+            cors: cors.and_then(|origins| static_web_server::cors::new(&origins, "", "")),
             security_headers: false,
             cache_control_headers: false,
             page404: path.join("404.html"),
-            page50x: PathBuf::new(),
+            page50x: page50x.unwrap_or_else(|| path.join("50x.html")),
             index_files: ["index.html"].iter().map(|s| s.to_string()).collect(),
             log_remote_address: false,
             log_x_real_ip: false,
             log_forwarded_for: false,
             trusted_proxies: Vec::new(),
-            redirect_trailing_slash: false,
-            ignore_hidden_files: true,
-            disable_symlinks: true,
+            redirect_trailing_slash,
+            ignore_hidden_files: !serve_hidden,
+            disable_symlinks: !follow_symlinks,
             accept_markdown: false,
             health: false,
-            maintenance_mode: false,
-            maintenance_mode_status: StatusCode::SERVICE_UNAVAILABLE,
-            maintenance_mode_file: PathBuf::new(),
+            maintenance_mode,
+            maintenance_mode_status: maintenance_status,
+            maintenance_mode_file: maintenance_file.clone(),
             advanced_opts: None,
         };
 
-        let address = SocketAddr::from((IpAddr::V4(Ipv4Addr::LOCALHOST), 0));
-        let listener =
-            TcpListener::bind(address).with_context(|| format!("failed to bind to {address}"))?;
+        let listener = match listen_fd {
+            Some(fd) => Self::inherit_listener(fd)?,
+            None => {
+                let address = SocketAddr::from((host, port.unwrap_or(0)));
+
+                TcpListener::bind(address).map_err(|error| {
+                    let context = match (port, error.kind()) {
+                        (Some(port), ErrorKind::AddrInUse) => {
+                            format!("port {port} is already in use")
+                        }
+                        _ => format!("failed to bind to {address}"),
+                    };
+
+                    anyhow::Error::new(error).context(context)
+                })?
+            }
+        };
 
         listener.set_nonblocking(true).with_context(|| {
             format!("could not set TCP stream non-blocking for listener {listener:?}")
@@ -150,30 +373,334 @@ impl Server {
             format!("failed to create hyper server from listener {listener:?}");
 
         let address = listener.local_addr()?;
-        let (shutdown_sender, shutdown_signal) = oneshot::channel();
-        let server_task = hyper::Server::from_tcp(listener)
-            .context(failed_to_create_server_msg)?
-            .tcp_nodelay(true)
-            .serve(RouterService::new(RequestHandler {
-                opts: Arc::from(handler_opts),
-            }))
-            .with_graceful_shutdown(async move {
-                shutdown_signal.await.unwrap();
-            });
+        let (shutdown_sender, shutdown_receiver) = watch::channel(());
+        let reload_trigger = ReloadTrigger::new();
+        let readiness = Readiness::default();
+        let maintenance = Maintenance::default();
+
+        let request_handler = Arc::new(RequestHandler {
+            opts: Arc::from(handler_opts),
+        });
+
+        let service = {
+            let shutdown_receiver = shutdown_receiver.clone();
+            let reload_trigger = reload_trigger.clone();
+            let requested_paths = requested_paths.clone();
+            let readiness = readiness.clone();
+            let maintenance = maintenance.clone();
+            let root = path.clone();
+
+            move |remote_address: Option<SocketAddr>| {
+                let request_handler = request_handler.clone();
+                let shutdown_receiver = shutdown_receiver.clone();
+                let reload_trigger = reload_trigger.clone();
+                let build_log = build_log.clone();
+                let requested_paths = requested_paths.clone();
+                let readiness = readiness.clone();
+                let maintenance = maintenance.clone();
+                let maintenance_file = maintenance_file.clone();
+                let root = root.clone();
+
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |mut request| {
+                        let request_handler = request_handler.clone();
+                        let shutdown_receiver = shutdown_receiver.clone();
+                        let reload_trigger = reload_trigger.clone();
+                        let build_log = build_log.clone();
+                        let requested_paths = requested_paths.clone();
+                        let readiness = readiness.clone();
+                        let maintenance = maintenance.clone();
+                        let maintenance_file = maintenance_file.clone();
+                        let root = root.clone();
+
+                        async move {
+                            let method = request.method().clone();
+                            let uri = request.uri().clone();
+                            let received_at = Instant::now();
+
+                            let response = async move {
+                                if health && request.uri().path() == health::PATH {
+                                    return Ok(health::respond(&readiness));
+                                }
+
+                                if let Some(delay) = stream_test
+                                    && request.uri().path() == stream_test::PATH
+                                {
+                                    return Ok(stream_test::respond(
+                                        &root,
+                                        &request,
+                                        delay,
+                                        shutdown_receiver,
+                                    ));
+                                }
+
+                                if let Some(build_log) = &build_log
+                                    && request.uri().path() == build_log::PATH
+                                {
+                                    return Ok(build_log::respond(
+                                        build_log,
+                                        &request,
+                                        shutdown_receiver,
+                                    ));
+                                }
+
+                                if inject_reload_script
+                                    && request.uri().path() == live_reload::EVENTS_PATH
+                                {
+                                    return Ok(live_reload::events(
+                                        reload_trigger.subscribe(),
+                                        shutdown_receiver,
+                                    ));
+                                }
+
+                                if inject_reload_script {
+                                    live_reload::prepare_request(&mut request);
+                                }
+
+                                let method = request.method().clone();
+                                let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+
+                                if let Some(requested_paths) = &requested_paths {
+                                    requested_paths.record(request.uri().path());
+                                }
+
+                                let mut response = if maintenance.is_on() {
+                                    maintenance_mode::get_response(
+                                        request.method(),
+                                        &maintenance_status,
+                                        &maintenance_file,
+                                    )?
+                                } else {
+                                    request_handler.handle(&mut request, remote_address).await?
+                                };
+
+                                // client side routes are presumed to lack an extension
+                                if spa
+                                    && response.status() == StatusCode::NOT_FOUND
+                                    && Path::new(request.uri().path()).extension().is_none()
+                                {
+                                    let mut index_request = Request::new(Body::empty());
+                                    *index_request.method_mut() = request.method().clone();
+                                    *index_request.uri_mut() = Uri::from_static("/");
+                                    *index_request.headers_mut() = request.headers().clone();
+
+                                    response = request_handler
+                                        .handle(&mut index_request, remote_address)
+                                        .await?;
+
+                                    if let Some(requested_paths) = &requested_paths {
+                                        requested_paths.record("/");
+                                    }
+                                }
+
+                                if response.status() == StatusCode::NOT_FOUND {
+                                    if let Some(requested_paths) = &requested_paths {
+                                        requested_paths.record("/404.html");
+                                    }
 
-        Ok(Self {
-            join_handle: tokio::spawn(server_task),
+                                    *response.status_mut() = page404_status;
+                                }
+
+                                if inject_reload_script {
+                                    response = live_reload::inject_script(
+                                        &method,
+                                        page404_status,
+                                        response,
+                                    )
+                                    .await?;
+                                }
+
+                                etag::apply(
+                                    etag_strategy,
+                                    &method,
+                                    if_none_match.as_ref(),
+                                    response,
+                                )
+                                .await
+                            }
+                            .await;
+
+                            let elapsed = received_at.elapsed();
+                            let is_slow = slow_request.is_some_and(|threshold| elapsed > threshold);
+
+                            if access_log || is_slow {
+                                access_log::record(&method, &uri, elapsed, is_slow, &response);
+                            }
+
+                            response
+                        }
+                    }))
+                }
+            }
+        };
+
+        let graceful_shutdown = {
+            let mut shutdown_receiver = shutdown_receiver.clone();
+
+            async move {
+                shutdown_receiver.changed().await.unwrap();
+            }
+        };
+
+        let join_handle = match &tls {
+            None => tokio::spawn(
+                hyper::Server::from_tcp(listener)
+                    .context(failed_to_create_server_msg)?
+                    .tcp_nodelay(true)
+                    .http2_only(http2_only)
+                    .serve(make_service_fn(move |connection: &AddrStream| {
+                        service(Some(connection.remote_addr()))
+                    }))
+                    .with_graceful_shutdown(graceful_shutdown),
+            ),
+            Some(certificate) => {
+                let listener = tokio::net::TcpListener::from_std(listener)
+                    .context(failed_to_create_server_msg)?;
+
+                let incoming = tls::incoming(
+                    listener,
+                    tls::acceptor(certificate, http2_only)?,
+                    shutdown_receiver,
+                );
+
+                tokio::spawn(
+                    hyper::Server::builder(incoming)
+                        .http2_only(http2_only)
+                        .serve(make_service_fn(move |connection: &TlsStream<TcpStream>| {
+                            service(connection.get_ref().0.peer_addr().ok())
+                        }))
+                        .with_graceful_shutdown(graceful_shutdown),
+                )
+            }
+        };
+
+        let server = Self {
+            join_handle,
+            tls: tls.is_some(),
             address,
             shutdown_sender,
-        })
+            reload_trigger,
+            root: path,
+            requested_paths,
+            output_digest: None,
+            readiness,
+            maintenance,
+            url_file,
+            shutdown_timeout,
+        };
+
+        if let Some(url_file) = &server.url_file {
+            std::fs::write(url_file, format!("{}/\n", server.url()))
+                .with_context(|| format!("failed to write URL to {url_file:?}"))?;
+        }
+
+        Ok(server)
+    }
+
+    /// Takes ownership of `fd` once it is verified to be a listening TCP socket
+    fn inherit_listener(fd: RawFd) -> anyhow::Result<TcpListener> {
+        let context = || format!("file descriptor {fd} is not a listening TCP socket");
+
+        {
+            // SAFETY: merely queried, and an invalid descriptor results in an error
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let socket = SockRef::from(&borrowed);
+
+            if socket.r#type().with_context(context)? != Type::STREAM
+                || !socket.is_listener().with_context(context)?
+                || socket
+                    .local_addr()
+                    .with_context(context)?
+                    .as_socket()
+                    .is_none()
+            {
+                bail!(context());
+            }
+        }
+
+        // SAFETY: inherited for this purpose and not used elsewhere
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        Ok(socket.into())
     }
 
     pub(crate) fn address(&self) -> SocketAddr {
         self.address
     }
 
-    async fn shutdown(self) -> Result<Result<(), hyper::Error>, tokio::task::JoinError> {
+    /// A URL at which the server is reachable from this host
+    pub(crate) fn url(&self) -> String {
+        let mut address = self.address;
+
+        if address.ip().is_unspecified() {
+            address.set_ip(match address.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+
+        let scheme = if self.tls { "https" } else { "http" };
+        format!("{scheme}://{address}")
+    }
+
+    pub(crate) fn readiness(&self) -> &Readiness {
+        &self.readiness
+    }
+
+    pub(crate) fn maintenance(&self) -> &Maintenance {
+        &self.maintenance
+    }
+
+    pub(crate) fn reload_trigger(&self) -> ReloadTrigger {
+        self.reload_trigger.clone()
+    }
+
+    /// Failure is logged
+    pub(crate) fn write_manifest(&self, path: &Path) {
+        if let Err(error) = manifest::write(&self.root, path) {
+            warn!("failed to write manifest: {error:#}");
+        }
+    }
+
+    /// Returns whether the served files changed since the previous call;
+    /// they are considered changed if they cannot be read
+    pub(crate) fn update_output_digest(&mut self) -> bool {
+        let digest = match output_digest::compute(&self.root) {
+            Ok(digest) => digest,
+            Err(error) => {
+                warn!("failed to digest served files at {:?}: {error}", self.root);
+                self.output_digest = None;
+                return true;
+            }
+        };
+
+        self.output_digest.replace(digest) != Some(digest)
+    }
+
+    async fn shutdown(mut self) -> Result<Result<(), hyper::Error>, tokio::task::JoinError> {
         self.shutdown_sender.send(()).unwrap();
-        self.join_handle.await
+
+        let result = match self.shutdown_timeout {
+            None => (&mut self.join_handle).await,
+            Some(timeout) => match tokio::time::timeout(timeout, &mut self.join_handle).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!("connections remain open {timeout:?} into shutdown; dropping them");
+                    self.join_handle.abort();
+                    Ok(Ok(()))
+                }
+            },
+        };
+
+        if let Some(requested_paths) = &self.requested_paths {
+            requested_paths.report(&self.root);
+        }
+
+        if let Some(url_file) = &self.url_file
+            && let Err(error) = std::fs::remove_file(url_file)
+        {
+            warn!("failed to remove {url_file:?}: {error}");
+        }
+
+        result
     }
 }
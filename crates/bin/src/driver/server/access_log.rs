@@ -0,0 +1,39 @@
+use std::{fmt::Display, time::Duration};
+
+use hyper::{Body, Method, Response, Uri};
+use tracing::{info, warn};
+
+/// Logs a served request along with the outcome of responding to it,
+/// at warn level if it is slow
+pub(super) fn record<E: Display>(
+    method: &Method,
+    uri: &Uri,
+    elapsed: Duration,
+    is_slow: bool,
+    response: &Result<Response<Body>, E>,
+) {
+    let elapsed_ms = elapsed.as_millis();
+
+    match response {
+        Ok(response) if is_slow => warn!(
+            method = %method,
+            uri = %uri,
+            status = response.status().as_u16(),
+            elapsed_ms,
+            "access: slow request"
+        ),
+        Ok(response) => info!(
+            method = %method,
+            uri = %uri,
+            status = response.status().as_u16(),
+            elapsed_ms,
+            "access"
+        ),
+        Err(error) => warn!(
+            method = %method,
+            uri = %uri,
+            elapsed_ms,
+            "access: failed to respond: {error}"
+        ),
+    }
+}
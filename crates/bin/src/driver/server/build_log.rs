@@ -0,0 +1,109 @@
+use hyper::{
+    Body, Request, Response, StatusCode,
+    body::Bytes,
+    header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE},
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch,
+};
+
+use crate::driver::build::BuildLog;
+
+pub(super) const PATH: &str = "/__conveyorbelt/build-log";
+
+/// The build log as `text/plain`,
+/// or as a `text/event-stream` of lines as they are logged until the server is shut down.
+/// A `tail` query parameter limits the lines from before the request to that many.
+pub(super) fn respond(
+    build_log: &BuildLog,
+    request: &Request<Body>,
+    shutdown: watch::Receiver<()>,
+) -> Response<Body> {
+    let tail = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("tail="))
+        .map(str::parse::<usize>)
+        .transpose();
+
+    let Ok(tail) = tail else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("tail is not a non-negative integer"))
+            .unwrap();
+    };
+
+    let is_event_stream = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+
+    if is_event_stream {
+        let tail = if tail.is_some() {
+            build_log.tail(tail)
+        } else {
+            Vec::new()
+        };
+
+        events(tail, build_log.subscribe(), shutdown)
+    } else {
+        let text: String = build_log
+            .tail(tail)
+            .into_iter()
+            .map(|line| line + "\n")
+            .collect();
+
+        Response::builder()
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(Body::from(text))
+            .unwrap()
+    }
+}
+
+fn events(
+    tail: Vec<String>,
+    mut lines: broadcast::Receiver<String>,
+    mut shutdown: watch::Receiver<()>,
+) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        for line in tail {
+            if sender.send_data(event(&line)).await.is_err() {
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                result = lines.recv() => {
+                    let line = match result {
+                        Ok(line) => line,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    };
+
+                    if sender.send_data(event(&line)).await.is_err() {
+                        break;
+                    }
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+    });
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+fn event(line: &str) -> Bytes {
+    Bytes::from(format!("data: {line}\n\n"))
+}
@@ -0,0 +1,88 @@
+use std::hash::{DefaultHasher, Hash as _, Hasher as _};
+
+use hyper::{
+    Body, Method, Response, StatusCode,
+    header::{CONTENT_LENGTH, ETAG, HeaderValue, LAST_MODIFIED},
+};
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub(crate) enum EtagStrategy {
+    /// Derived from modification time and size
+    Mtime,
+    /// Derived from content; stable across builds that produce identical bytes.
+    /// Not sent in response to `HEAD`, as its response lacks the content
+    Hash,
+    /// No ETag is sent
+    #[default]
+    None,
+}
+
+pub(super) async fn apply(
+    strategy: EtagStrategy,
+    method: &Method,
+    if_none_match: Option<&HeaderValue>,
+    response: Response<Body>,
+) -> anyhow::Result<Response<Body>> {
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(ETAG);
+
+    if parts.status != StatusCode::OK {
+        return Ok(Response::from_parts(parts, body));
+    }
+
+    let mut hasher = DefaultHasher::new();
+
+    let body = match strategy {
+        EtagStrategy::None => return Ok(Response::from_parts(parts, body)),
+        EtagStrategy::Mtime => {
+            let (Some(last_modified), Some(content_length)) = (
+                parts.headers.get(LAST_MODIFIED),
+                parts.headers.get(CONTENT_LENGTH),
+            ) else {
+                return Ok(Response::from_parts(parts, body));
+            };
+
+            last_modified.as_bytes().hash(&mut hasher);
+            content_length.as_bytes().hash(&mut hasher);
+            body
+        }
+        // the hash of an empty body would not match that of the `GET` response
+        EtagStrategy::Hash if method == Method::HEAD => {
+            return Ok(Response::from_parts(parts, body));
+        }
+        EtagStrategy::Hash => {
+            let bytes = hyper::body::to_bytes(body).await?;
+            bytes.hash(&mut hasher);
+            Body::from(bytes)
+        }
+    };
+
+    let etag = HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))?;
+
+    if if_none_match.is_some_and(|if_none_match| matches(if_none_match, &etag)) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.insert(ETAG, etag);
+        return Ok(Response::from_parts(parts, Body::empty()));
+    }
+
+    parts.headers.insert(ETAG, etag);
+    Ok(Response::from_parts(parts, body))
+}
+
+/// Per RFC 9110, `If-None-Match` is either `*` or a list of entity tags, compared weakly
+fn matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let (Ok(if_none_match), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+        return false;
+    };
+
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+
+    // as `etag` contains no comma, splitting an entity tag that does cannot produce a match
+    if_none_match.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag) == etag
+    })
+}
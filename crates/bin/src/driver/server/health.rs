@@ -0,0 +1,36 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use hyper::{Body, Response, StatusCode};
+
+pub(super) const PATH: &str = "/health";
+
+/// Whether the initial build has succeeded, such that there is content to serve
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Readiness(Arc<AtomicBool>);
+
+impl Readiness {
+    pub(crate) fn set(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// `503 Service Unavailable` until ready, `200 OK` thereafter
+pub(super) fn respond(readiness: &Readiness) -> Response<Body> {
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,139 @@
+use hyper::{
+    Body, Method, Request, Response, StatusCode,
+    body::Bytes,
+    header::{
+        ACCEPT, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+        CONTENT_TYPE,
+    },
+};
+use tokio::sync::watch;
+
+pub(super) const EVENTS_PATH: &str = "/__conveyorbelt/reload";
+
+const SCRIPT: &str = r#"<script>new EventSource("/__conveyorbelt/reload").addEventListener("reload", () => location.reload());</script>"#;
+
+/// How pages are reloaded following successful builds
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub(crate) enum ReloadMethod {
+    /// The launched browser's page, via CDP
+    #[default]
+    Cdp,
+    /// Any page, by a script that is injected into served HTML,
+    /// which listens for server-sent events
+    #[value(alias = "websocket")]
+    Script,
+    Both,
+}
+
+impl ReloadMethod {
+    pub(crate) fn uses_cdp(self) -> bool {
+        matches!(self, Self::Cdp | Self::Both)
+    }
+
+    pub(crate) fn uses_script(self) -> bool {
+        matches!(self, Self::Script | Self::Both)
+    }
+}
+
+/// Notifies pages that run the injected script that they should reload
+#[derive(Debug, Clone)]
+pub(crate) struct ReloadTrigger(watch::Sender<u64>);
+
+impl ReloadTrigger {
+    pub(super) fn new() -> Self {
+        Self(watch::Sender::new(0))
+    }
+
+    pub(crate) fn trigger(&self) {
+        self.0.send_modify(|count| *count += 1);
+    }
+
+    pub(super) fn subscribe(&self) -> watch::Receiver<u64> {
+        self.0.subscribe()
+    }
+}
+
+/// A `text/event-stream` response that emits an event for each reload
+/// until the server is shut down
+pub(super) fn events(
+    mut reloads: watch::Receiver<u64>,
+    mut shutdown: watch::Receiver<()>,
+) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                result = reloads.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+
+                    let event = Bytes::from_static(b"event: reload\ndata:\n\n");
+
+                    if sender.send_data(event).await.is_err() {
+                        break;
+                    }
+                },
+                _ = shutdown.changed() => break,
+            }
+        }
+    });
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// The script can not be injected into compressed HTML,
+/// so requests that accept HTML are made to not accept compression
+pub(super) fn prepare_request(request: &mut Request<Body>) {
+    let accepts_html = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    if accepts_html {
+        request.headers_mut().remove(ACCEPT_ENCODING);
+    }
+}
+
+/// Only into complete documents; neither into responses to `HEAD` nor into `304` or partial ones
+pub(super) async fn inject_script(
+    method: &Method,
+    page404_status: StatusCode,
+    response: Response<Body>,
+) -> anyhow::Result<Response<Body>> {
+    let is_complete = method == Method::GET
+        && (response.status() == StatusCode::OK || response.status() == page404_status)
+        && !response.headers().contains_key(CONTENT_RANGE);
+
+    if !is_complete {
+        return Ok(response);
+    }
+
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+
+    if !is_html || response.headers().contains_key(CONTENT_ENCODING) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let mut html = hyper::body::to_bytes(body).await?.to_vec();
+
+    let index = html
+        .windows(b"</body>".len())
+        .rposition(|window| window.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(html.len());
+
+    html.splice(index..index, SCRIPT.bytes());
+    parts.headers.insert(CONTENT_LENGTH, html.len().into());
+    Ok(Response::from_parts(parts, Body::from(html)))
+}
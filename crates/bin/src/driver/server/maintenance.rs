@@ -0,0 +1,18 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Whether requests are responded to with the maintenance page, e.g. while building
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub(crate) fn set(&self, is_on: bool) {
+        self.0.store(is_on, Ordering::Relaxed);
+    }
+
+    pub(super) fn is_on(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
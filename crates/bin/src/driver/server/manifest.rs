@@ -0,0 +1,65 @@
+use std::{collections::BTreeMap, fmt::Write as _, io::Write as _, path::Path, time::UNIX_EPOCH};
+
+use anyhow::Context as _;
+use ring::digest::{SHA256, digest};
+use serde::Serialize;
+
+use super::unused_assets::collect_files;
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    size: u64,
+    /// Milliseconds since the Unix epoch
+    mtime: u128,
+    /// Hex encoded, so that external tools can compute it as well
+    sha256: String,
+}
+
+/// Writes to `path`, atomically, a JSON object of the served files, by their paths relative to `root`
+pub(super) fn write(root: &Path, path: &Path) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    collect_files(root, Path::new(""), &mut files)
+        .with_context(|| format!("failed to list served files at {root:?}"))?;
+
+    let entries = files
+        .into_iter()
+        .map(|file| {
+            let absolute = root.join(&file);
+            let metadata = std::fs::metadata(&absolute)?;
+            let digest = digest(&SHA256, &std::fs::read(&absolute)?);
+
+            let entry = Entry {
+                size: metadata.len(),
+                mtime: metadata
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis(),
+                sha256: digest.as_ref().iter().fold(String::new(), |mut hex, byte| {
+                    write!(hex, "{byte:02x}").unwrap();
+                    hex
+                }),
+            };
+
+            Ok((file.to_string_lossy().into_owned(), entry))
+        })
+        .collect::<std::io::Result<BTreeMap<_, _>>>()
+        .with_context(|| format!("failed to read served files at {root:?}"))?;
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut temp_file = tempfile::NamedTempFile::new_in(parent)
+        .with_context(|| format!("failed to create a temporary file in {parent:?}"))?;
+
+    serde_json::to_writer_pretty(&mut temp_file, &entries)?;
+    temp_file.write_all(b"\n")?;
+
+    temp_file
+        .persist(path)
+        .with_context(|| format!("failed to write {path:?}"))?;
+
+    Ok(())
+}
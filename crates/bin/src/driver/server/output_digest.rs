@@ -0,0 +1,22 @@
+use std::{
+    hash::{DefaultHasher, Hash as _, Hasher as _},
+    path::Path,
+};
+
+use super::unused_assets::collect_files;
+
+/// Of the paths and contents of the served files, for telling whether a build changed them
+/// within this process only, as the algorithm of `DefaultHasher` is unspecified across releases
+pub(super) fn compute(root: &Path) -> std::io::Result<u64> {
+    let mut files = Vec::new();
+    collect_files(root, Path::new(""), &mut files)?;
+    files.sort();
+    let mut hasher = DefaultHasher::new();
+
+    for file in files {
+        file.hash(&mut hasher);
+        std::fs::read(root.join(&file))?.hash(&mut hasher);
+    }
+
+    Ok(hasher.finish())
+}
@@ -0,0 +1,91 @@
+use std::{
+    path::{Component, Path},
+    time::Duration,
+};
+
+use hyper::{
+    Body, HeaderMap, Request, Response, StatusCode,
+    body::Bytes,
+    header::{CACHE_CONTROL, CONTENT_TYPE, HeaderName, HeaderValue, TRAILER},
+};
+use percent_encoding::percent_decode_str;
+use tokio::sync::watch;
+
+pub(super) const PATH: &str = "/__conveyorbelt/stream";
+
+const CHUNK_SIZE: usize = 64;
+const DEFAULT_FILE: &str = "index.html";
+const CHUNKS_TRAILER: HeaderName = HeaderName::from_static("x-conveyorbelt-chunks");
+
+/// The served file at the `path` query parameter, by default `index.html`,
+/// streamed in chunks of [`CHUNK_SIZE`] bytes, `delay` apart,
+/// followed by a trailer of their count.
+/// hyper sends trailers only over HTTP/2; over HTTP/1 the response is merely chunked.
+pub(super) fn respond(
+    root: &Path,
+    request: &Request<Body>,
+    delay: Duration,
+    mut shutdown: watch::Receiver<()>,
+) -> Response<Body> {
+    let file = request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("path="))
+        .map(|path| percent_decode_str(path).decode_utf8_lossy().into_owned())
+        .unwrap_or_else(|| DEFAULT_FILE.to_owned());
+
+    let file = Path::new(file.trim_start_matches('/'));
+
+    if !file
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return status(StatusCode::BAD_REQUEST, "path is not within the serve path");
+    }
+
+    let Ok(content) = std::fs::read(root.join(file)) else {
+        return status(StatusCode::NOT_FOUND, "path is not a readable file");
+    };
+
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let chunks = content.chunks(CHUNK_SIZE).map(Bytes::copy_from_slice);
+        let mut count = 0_usize;
+
+        for chunk in chunks {
+            if count > 0 {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {},
+                    _ = shutdown.changed() => return,
+                }
+            }
+
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+
+            count += 1;
+        }
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert(CHUNKS_TRAILER, HeaderValue::from(count));
+        let _ = sender.send_trailers(trailers).await;
+    });
+
+    Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .header(TRAILER, CHUNKS_TRAILER)
+        .body(body)
+        .unwrap()
+}
+
+fn status(status: StatusCode, message: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message))
+        .unwrap()
+}
@@ -0,0 +1,229 @@
+use std::{
+    convert::Infallible,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::Context as _;
+use hyper::server::accept::Accept;
+use ring::{
+    rand::{SecureRandom as _, SystemRandom},
+    signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair as _},
+};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, pem::PemObject as _};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch},
+};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{ServerConfig, crypto::ring::default_provider},
+    server::TlsStream,
+};
+use tracing::{debug, info, warn};
+
+/// That which HTTPS is served with
+#[derive(Debug, Clone)]
+pub(crate) enum TlsCertificate {
+    /// Generated on startup, for `localhost`, `127.0.0.1` and `::1`
+    SelfSigned,
+    /// PEM files
+    Files { cert: PathBuf, key: PathBuf },
+}
+
+pub(crate) fn acceptor(
+    certificate: &TlsCertificate,
+    http2_only: bool,
+) -> anyhow::Result<TlsAcceptor> {
+    let (chain, key) = match certificate {
+        TlsCertificate::SelfSigned => {
+            let (cert, key) =
+                self_signed().context("failed to generate a self-signed certificate")?;
+            info!("serving HTTPS with an ephemeral self-signed certificate");
+            (vec![cert], key)
+        }
+        TlsCertificate::Files { cert, key } => {
+            let chain = CertificateDer::pem_file_iter(cert)
+                .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+                .with_context(|| format!("failed to read TLS certificate chain {cert:?}"))?;
+
+            let key = PrivateKeyDer::from_pem_file(key)
+                .with_context(|| format!("failed to read TLS private key {key:?}"))?;
+
+            (chain, key)
+        }
+    };
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .context("invalid TLS certificate or private key")?;
+
+    config.alpn_protocols = if http2_only {
+        vec![b"h2".to_vec()]
+    } else {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts connections on `listener` until `shutdown` changes,
+/// performing the TLS handshakes concurrently
+pub(crate) fn incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    mut shutdown: watch::Receiver<()>,
+) -> impl Accept<Conn = TlsStream<TcpStream>, Error = Infallible> {
+    let (connection_sender, mut connection_receiver) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote_address) = tokio::select! {
+                _ = shutdown.changed() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        warn!("failed to accept a connection: {error}");
+                        continue;
+                    }
+                },
+            };
+
+            if let Err(error) = stream.set_nodelay(true) {
+                warn!("failed to set TCP_NODELAY for {remote_address}: {error}");
+            }
+
+            let acceptor = acceptor.clone();
+            let connection_sender = connection_sender.clone();
+
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        // the server is shutting down
+                        let _ = connection_sender.send(stream).await;
+                    }
+                    Err(error) => debug!("TLS handshake with {remote_address} failed: {error}"),
+                }
+            });
+        }
+    });
+
+    hyper::server::accept::poll_fn(move |cx| {
+        connection_receiver
+            .poll_recv(cx)
+            .map(|stream| stream.map(Ok))
+    })
+}
+
+/// A certificate for ECDSA P-256 signed by its own key,
+/// encoded by hand in DER, as X.509 requires little of it
+fn self_signed() -> anyhow::Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    const ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    const EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    const PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    const COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+    const SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+
+    let rng = SystemRandom::new();
+
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .map_err(|_| anyhow::anyhow!("failed to generate a key pair"))?;
+
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+        .map_err(|error| anyhow::anyhow!("failed to parse the generated key pair: {error}"))?;
+
+    let mut serial = [0; 16];
+
+    rng.fill(&mut serial)
+        .map_err(|_| anyhow::anyhow!("failed to generate a serial number"))?;
+
+    // positive
+    serial[0] &= 0x7f;
+
+    let name = der::sequence(&[&der::tlv(
+        0x31,
+        &der::sequence(&[
+            COMMON_NAME,
+            &der::tlv(0x0c, env!("CARGO_PKG_NAME").as_bytes()),
+        ]),
+    )]);
+
+    let alt_names = der::sequence(&[
+        &der::tlv(0x82, b"localhost"),
+        &der::tlv(0x87, &Ipv4Addr::LOCALHOST.octets()),
+        &der::tlv(0x87, &Ipv6Addr::LOCALHOST.octets()),
+    ]);
+
+    let tbs_certificate = der::sequence(&[
+        // version 3
+        &der::tlv(0xa0, &der::tlv(0x02, &[0x02])),
+        &der::tlv(0x02, &serial),
+        &der::sequence(&[ECDSA_WITH_SHA256]),
+        &name,
+        // validity, 1970 through 2049, which UTCTime spans
+        &der::sequence(&[
+            &der::tlv(0x17, b"700101000000Z"),
+            &der::tlv(0x17, b"491231235959Z"),
+        ]),
+        &name,
+        &der::sequence(&[
+            &der::sequence(&[EC_PUBLIC_KEY, PRIME256V1]),
+            &der::bit_string(key_pair.public_key().as_ref()),
+        ]),
+        // extensions
+        &der::tlv(
+            0xa3,
+            &der::sequence(&[&der::sequence(&[
+                SUBJECT_ALT_NAME,
+                &der::tlv(0x04, &alt_names),
+            ])]),
+        ),
+    ]);
+
+    let signature = key_pair
+        .sign(&rng, &tbs_certificate)
+        .map_err(|_| anyhow::anyhow!("failed to sign the certificate"))?;
+
+    let certificate = der::sequence(&[
+        &tbs_certificate,
+        &der::sequence(&[ECDSA_WITH_SHA256]),
+        &der::bit_string(signature.as_ref()),
+    ]);
+
+    Ok((
+        CertificateDer::from(certificate),
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.as_ref().to_vec())),
+    ))
+}
+
+mod der {
+    pub(super) fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        let length = value.len();
+
+        if length < 0x80 {
+            encoded.push(length as u8);
+        } else {
+            let length_bytes = length.to_be_bytes();
+            let significant =
+                &length_bytes[length_bytes.iter().take_while(|byte| **byte == 0).count()..];
+            encoded.push(0x80 | significant.len() as u8);
+            encoded.extend_from_slice(significant);
+        }
+
+        encoded.extend_from_slice(value);
+        encoded
+    }
+
+    pub(super) fn sequence(elements: &[&[u8]]) -> Vec<u8> {
+        tlv(0x30, &elements.concat())
+    }
+
+    /// Without unused bits
+    pub(super) fn bit_string(bytes: &[u8]) -> Vec<u8> {
+        tlv(0x03, &[&[0], bytes].concat())
+    }
+}
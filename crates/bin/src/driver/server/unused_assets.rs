@@ -0,0 +1,87 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use percent_encoding::percent_decode_str;
+use tracing::{info, warn};
+
+/// The paths requested during the session, for reporting the served files that never were
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestedPaths(Arc<Mutex<BTreeSet<String>>>);
+
+impl RequestedPaths {
+    pub(super) fn record(&self, uri_path: &str) {
+        let path = percent_decode_str(uri_path)
+            .decode_utf8_lossy()
+            .into_owned();
+        self.0.lock().unwrap().insert(path);
+    }
+
+    /// Including conditional requests, so files that the browser had cached count as requested
+    fn is_requested(&self, relative_path: &Path) -> bool {
+        let requested = self.0.lock().unwrap();
+        let Some(relative_path) = relative_path.to_str() else {
+            return false;
+        };
+
+        if requested.contains(&format!("/{relative_path}")) {
+            return true;
+        }
+
+        let Some(directory) = relative_path.strip_suffix("index.html") else {
+            return false;
+        };
+
+        requested.contains(&format!("/{directory}"))
+            || requested.contains(&format!("/{}", directory.trim_end_matches('/')))
+    }
+
+    pub(super) fn report(&self, root: &Path) {
+        let mut files = Vec::new();
+
+        if let Err(error) = collect_files(root, Path::new(""), &mut files) {
+            warn!("failed to list served files at {root:?}: {error}");
+            return;
+        }
+
+        let unused = files
+            .iter()
+            .filter(|file| !self.is_requested(file))
+            .map(|file| format!("{file:?}"))
+            .collect::<Vec<_>>();
+
+        if unused.is_empty() {
+            info!("no unused assets");
+        } else {
+            info!("unused assets: {}", unused.join(", "));
+        }
+    }
+}
+
+/// Relative to `root`, excluding hidden files and symlinks, which are not served
+pub(super) fn collect_files(
+    root: &Path,
+    relative: &Path,
+    files: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+
+        if entry.file_name().as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        let path = relative.join(entry.file_name());
+
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
@@ -26,6 +26,8 @@ pub(crate) enum SignalKind {
     Sigint,
     #[display("SIGTERM")]
     Sigterm,
+    #[display("SIGHUP")]
+    Sighup,
 }
 
 pub(crate) struct SignalDriver {
@@ -76,17 +78,34 @@ impl SignalDriver {
                             }
                         };
 
+                    let mut sighup = match signal::unix::signal(signal::unix::SignalKind::hangup())
+                    {
+                        Ok(signal) => signal,
+                        Err(error) => {
+                            event_sender
+                                .send(SignalEvent::HandlerInstallFail(error))
+                                .await
+                                .unwrap();
+                            return;
+                        }
+                    };
+
                     tokio::spawn(async move {
-                        let event = tokio::select! {
-                            _ = sigint.recv() => {
-                                SignalEvent::Received(SignalKind::Sigint)
-                            },
-                            _ = sigterm.recv() => {
-                                SignalEvent::Received(SignalKind::Sigterm)
-                            }
-                        };
+                        loop {
+                            let event = tokio::select! {
+                                _ = sigint.recv() => {
+                                    SignalEvent::Received(SignalKind::Sigint)
+                                },
+                                _ = sigterm.recv() => {
+                                    SignalEvent::Received(SignalKind::Sigterm)
+                                },
+                                _ = sighup.recv() => {
+                                    SignalEvent::Received(SignalKind::Sighup)
+                                }
+                            };
 
-                        event_sender_clone.send(event).await.unwrap();
+                            event_sender_clone.send(event).await.unwrap();
+                        }
                     });
 
                     event_sender
@@ -0,0 +1,44 @@
+use std::{convert::Infallible, time::Duration};
+
+use rxrust::{Observable as _, ObservableFactory as _, Shared, SharedBoxedObservable};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+#[derive(Debug, derive_more::Display)]
+pub(crate) enum TimerCommand {
+    #[display("wait {_0:?}")]
+    Wait(Duration),
+}
+
+#[derive(Debug, derive_more::Display)]
+pub(crate) enum TimerEvent {
+    #[display("elapsed")]
+    Elapsed,
+}
+
+pub(crate) struct TimerDriver {
+    event_sender: mpsc::Sender<TimerEvent>,
+}
+
+impl TimerDriver {
+    pub(crate) fn new() -> (SharedBoxedObservable<'static, TimerEvent, Infallible>, Self) {
+        let (event_sender, event_receiver) = mpsc::channel(1);
+        let driver = Self { event_sender };
+        (
+            Shared::from_stream(ReceiverStream::new(event_receiver)).box_it(),
+            driver,
+        )
+    }
+
+    pub(crate) fn effect(&self, command: TimerCommand) -> impl Future<Output = ()> + 'static {
+        let event_sender = self.event_sender.clone();
+        async move {
+            match command {
+                TimerCommand::Wait(duration) => {
+                    tokio::time::sleep(duration).await;
+                    event_sender.send(TimerEvent::Elapsed).await.unwrap();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{fs::OpenOptions, io::Write as _, os::unix::fs::OpenOptionsExt as _, path::Path};
+
+use anyhow::Context as _;
+use tracing::info;
+
+use crate::common::SERVE_PATH;
+
+const BUILD_COMMAND_FILE_NAME: &str = "build.sh";
+
+pub(crate) async fn run(origin: &Path) -> anyhow::Result<()> {
+    let project_root = crate::project_path::resolve(origin).await?;
+    let path = project_root.join(BUILD_COMMAND_FILE_NAME);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o755)
+        .open(&path)
+        .with_context(|| format!("failed to create {path:?}; refusing to overwrite"))?;
+
+    file.write_all(build_command_content().as_bytes())
+        .with_context(|| format!("failed to write {path:?}"))?;
+
+    info!("build command scaffolded: {path:?}");
+    info!("to start: conveyorbelt ./{BUILD_COMMAND_FILE_NAME}");
+    Ok(())
+}
+
+fn build_command_content() -> String {
+    format!(
+        r#"#!/bin/sh
+# Invoked by conveyorbelt initially and on each change of a file that is not git ignored.
+# The build output is to be written into the directory at ${SERVE_PATH}.
+# By default, that directory is temporary and outside of the repository,
+# so there is nothing to add to .gitignore.
+# A directory provided with --serve-dir, though, must be ignored by git if it is within the repository.
+# Files from previous builds are not removed between builds.
+# A zero exit status triggers a browser reload.
+set -eu
+
+cat > "${SERVE_PATH}/index.html" <<'HTML'
+<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="UTF-8">
+    <title>Scaffolded by conveyorbelt</title>
+  </head>
+  <body>
+    <p>Edit {BUILD_COMMAND_FILE_NAME} to build your site.</p>
+  </body>
+</html>
+HTML
+"#
+    )
+}
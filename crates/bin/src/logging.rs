@@ -1,19 +1,135 @@
-use tracing::Level;
-use tracing_subscriber::filter::EnvFilter;
+use std::{fs::File, io::IsTerminal as _, sync::Mutex};
 
-pub(crate) fn init() {
+use tracing::{
+    Level, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{
+    Layer as _,
+    filter::{EnvFilter, Targets},
+    fmt::{
+        FmtContext, FormatEvent, FormatFields,
+        format::Writer,
+        time::{FormatTime as _, SystemTime},
+    },
+    layer::SubscriberExt as _,
+    registry::LookupSpan,
+    util::SubscriberInitExt as _,
+};
+
+/// The target at which chromiumoxide traces the CDP messages that it sends and receives
+const CDP_TARGET: &str = "chromiumoxide::conn";
+
+/// Of the log written to stderr
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub(crate) enum LogFormat {
+    #[default]
+    Text,
+    /// A JSON object per line
+    Json,
+}
+
+/// Of this crate's events, unless a filter is provided via the environment
+pub(crate) fn level(quiet: bool, verbose: u8) -> Level {
+    match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    }
+}
+
+pub(crate) fn init(format: LogFormat, level: Level, cdp_log: Option<File>) {
     let filter = EnvFilter::try_from_env(env!("LOG_FILTER_VAR_NAME")).unwrap_or_else(|_| {
         EnvFilter::default()
             .add_directive(Level::WARN.into())
             .add_directive(
-                format!("{}=info", env!("CARGO_CRATE_NAME"))
+                format!("{}={level}", env!("CARGO_CRATE_NAME"))
                     .parse()
                     .unwrap(),
             )
     });
 
-    tracing_subscriber::fmt()
+    let cdp_log_layer = cdp_log.map(|file| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(Mutex::new(file))
+            .with_ansi(false)
+            .with_filter(Targets::new().with_target(CDP_TARGET, Level::TRACE))
+    });
+
+    // so that piped logs remain parseable
+    let stderr_layer = tracing_subscriber::fmt::layer()
         .with_writer(std::io::stderr)
-        .with_env_filter(filter)
+        .with_ansi(std::io::stderr().is_terminal());
+
+    let stderr_layer = match format {
+        LogFormat::Text => stderr_layer.boxed(),
+        LogFormat::Json => stderr_layer.event_format(JsonFormat).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr_layer.with_filter(filter))
+        .with(cdp_log_layer)
         .init();
 }
+
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> std::fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+
+        let object = serde_json::json!({
+            "timestamp": timestamp,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.0,
+        });
+
+        writeln!(writer, "{object}")
+    }
+}
+
+#[derive(Default)]
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl JsonFields {
+    fn insert(&mut self, field: &Field, value: impl Into<serde_json::Value>) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.insert(field, format!("{value:?}"));
+    }
+}
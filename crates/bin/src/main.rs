@@ -3,23 +3,31 @@ mod cli;
 #[path = "../common.rs"]
 mod common;
 mod driver;
+mod init;
 mod logging;
 mod project_path;
+mod single_instance;
 
-use std::sync::Arc;
+use std::{fs::File, path::PathBuf, sync::Arc, time::Duration};
 
+use anyhow::Context as _;
+use clap::Parser as _;
 use futures::{FutureExt as _, StreamExt};
+use hyper::StatusCode;
 use rxrust::prelude::*;
+use tracing::{debug, warn};
 
 use crate::{
     app::{App, Command, Control, Event},
-    cli::Args,
+    cli::{Args, Subcommand},
     driver::{
-        browser::BrowserDriver,
-        build::BuildDriver,
+        browser::{Browser, BrowserDriver, BrowserOptions, HarRecorder},
+        build::{BuildDriver, BuildLog, OnChange, Retry},
+        control::{ControlDriver, ControlSocket, StateSocket},
         fswatch::FsWatchDriver,
-        server::{ServeDir, ServerDriver},
+        server::{RequestedPaths, ServeDir, ServerDriver, ServerOptions, TlsCertificate},
         signal::SignalDriver,
+        timer::TimerDriver,
     },
 };
 
@@ -32,24 +40,332 @@ fn main() -> anyhow::Result<()> {
 }
 
 async fn async_main() -> anyhow::Result<()> {
-    logging::init();
-    let Args { build_command } = crate::cli::parse();
+    let args = Args::parse();
+
+    let cdp_log = args
+        .browser_cdp_log
+        .as_ref()
+        .map(|path| File::create(path).with_context(|| format!("failed to create {path:?}")))
+        .transpose()?;
+
+    logging::init(
+        args.log_format,
+        logging::level(args.quiet, args.verbose),
+        cdp_log,
+    );
+    debug!("arguments parsed: {args:?}");
+
+    let Args {
+        subcommand,
+        build_command,
+        shell,
+        build_arg,
+        build_env,
+        build_clean_env,
+        build_cwd,
+        project_root: explicit_project_root,
+        watch,
+        watch_ext,
+        ignore_file,
+        // implied by the absence of the build command
+        external_build: _,
+        on_change,
+        on_change_required,
+        build_output_stream,
+        timestamp_build_output,
+        stage_build_output,
+        isolate_build_tmp,
+        require_nonempty_output,
+        no_initial_build,
+        warn_empty,
+        serve_path_style,
+        host,
+        port,
+        listen_fd,
+        http2,
+        tls,
+        tls_cert,
+        tls_key,
+        redirect_trailing_slash,
+        serve_hidden,
+        follow_symlinks,
+        spa,
+        page404_status,
+        page50x,
+        maintenance_mode,
+        maintenance_during_build,
+        maintenance_file,
+        maintenance_status,
+        etag_strategy,
+        compression,
+        access_log,
+        slow_request_ms,
+        cors,
+        reload_method,
+        reload_preserve_scroll,
+        start_url_query,
+        defer_browser_until_ready,
+        open,
+        viewport,
+        window_position,
+        maximize,
+        headless,
+        no_browser,
+        check,
+        serve_dir,
+        serve_dir_permissions,
+        browser_ws_url,
+        open_extra,
+        downloads_dir,
+        screenshot_dir,
+        block_url,
+        quiet_browser,
+        browser_executable,
+        har,
+        har_reset_on_reload,
+        build_log_api,
+        build_timeout,
+        build_retries,
+        build_retry_delay,
+        single_instance,
+        browser_cdp_log: _,
+        log_format: _,
+        quiet: _,
+        verbose: _,
+        watch_batch_window,
+        debounce,
+        poll,
+        exit_on_build_failure_count,
+        graceful_build_on_shutdown,
+        shutdown_grace,
+        shutdown_timeout,
+        report_unused_assets,
+        reload_only_on_output_change,
+        health,
+        stream_test,
+        stream_test_delay,
+        control_socket,
+        state_socket,
+        manifest,
+        url_file,
+    } = args;
+
+    if let Some(Subcommand::Init) = subcommand {
+        return crate::init::run(&std::env::current_dir()?).await;
+    }
+
+    let current_dir = std::env::current_dir()?;
 
     // TODO driver?
-    let serve_dir = ServeDir::obtain()?;
+    let project_root =
+        crate::project_path::obtain(explicit_project_root.as_deref(), &current_dir).await?;
+
+    let build_cwd = match build_cwd {
+        Some(path) => path
+            .canonicalize()
+            .with_context(|| format!("failed to canonicalize build cwd {path:?}"))?,
+        None => project_root.clone(),
+    };
+
+    let extra_watch_paths = watch
+        .iter()
+        .map(|path| {
+            path.canonicalize()
+                .with_context(|| format!("failed to canonicalize watch path {path:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let ignore_files = ignore_file
+        .iter()
+        .map(|path| {
+            std::path::absolute(path)
+                .ok()
+                .filter(|path| path.is_file())
+                .with_context(|| format!("ignore file {path:?} does not exist"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
     // TODO driver?
-    let project_root = crate::project_path::resolve(&std::env::current_dir()?)?;
+    let serve_dir = Arc::new(ServeDir::obtain(
+        serve_dir.as_deref(),
+        &project_root,
+        serve_dir_permissions,
+    )?);
+
+    let staging_dir = stage_build_output
+        .then(|| serve_dir.create_staging_dir(&project_root))
+        .transpose()?;
+
+    let _instance_lock = single_instance
+        .then(|| crate::single_instance::acquire(&project_root))
+        .transpose()?;
+
+    let build_log = build_log_api.then(BuildLog::new);
+
+    let create_absolute_dir = |path: PathBuf| {
+        std::fs::create_dir_all(&path).with_context(|| format!("failed to create {path:?}"))?;
+        std::path::absolute(&path).with_context(|| format!("failed to absolutize {path:?}"))
+    };
+
+    let downloads_dir = downloads_dir.map(create_absolute_dir).transpose()?;
+    let screenshot_dir = screenshot_dir.map(create_absolute_dir).transpose()?;
+
+    // rather than failing only once the browser is launched
+    if let Some(path) = &browser_executable
+        && !path.exists()
+    {
+        anyhow::bail!("browser executable {path:?} does not exist");
+    }
+
+    // browsers speak HTTP/2 only over TLS
+    if http2 && !tls && !no_browser {
+        anyhow::bail!(
+            "--http2 without --tls serves only h2c, which browsers do not speak; \
+            provide --tls or --no-browser"
+        );
+    }
+
+    // the command line is passed to the shell, which is run as the build command
+    let (build_command, build_arg) = match shell {
+        Some(command_line) => (
+            vec![PathBuf::from("/bin/sh")],
+            vec!["-c".into(), command_line.into()],
+        ),
+        None => (build_command, build_arg),
+    };
+
+    let har = har.map(|path| (path, HarRecorder::new(har_reset_on_reload)));
+
+    let control_socket_path = control_socket;
+
+    let control_socket = control_socket_path
+        .as_deref()
+        .map(ControlSocket::bind)
+        .transpose()?
+        .map(Arc::new);
+
+    let state_socket_path = state_socket;
+
+    let state_socket = state_socket_path
+        .as_deref()
+        .map(StateSocket::bind)
+        .transpose()?
+        .map(Arc::new);
 
     let (signal_events, signal_driver) = SignalDriver::new();
     let (server_events, server_driver) = ServerDriver::new();
     let (build_events, build_driver) = BuildDriver::new();
-    let (browser_events, browser_driver) = BrowserDriver::new();
+    let (browser_events, browser_driver) = BrowserDriver::<Browser>::new();
     let (fs_watch_events, fs_watch_driver) = FsWatchDriver::new();
+    let (timer_events, timer_driver) = TimerDriver::new();
+    let (control_events, control_driver) = ControlDriver::new();
 
     let app = App {
         project_root,
-        serve_dir: Arc::new(serve_dir),
-        build_command_path: build_command,
+        explicit_project_root,
+        current_dir,
+        extra_watch_paths,
+        watch_extensions: watch_ext,
+        ignore_files,
+        serve_dir: serve_dir.clone(),
+        build_command_paths: build_command,
+        build_args: build_arg,
+        build_env,
+        build_clean_env,
+        build_cwd,
+        staging_path: staging_dir
+            .as_ref()
+            .map(|staging_dir| staging_dir.path().to_path_buf()),
+        serve_path_style,
+        build_output_stream,
+        build_log: build_log.clone(),
+        build_timeout: build_timeout.map(Duration::from_secs),
+        build_retry: (build_retries > 0).then(|| Retry {
+            count: build_retries,
+            delay: Duration::from_millis(build_retry_delay),
+        }),
+        on_change: on_change.map(|path| OnChange {
+            path,
+            is_required: on_change_required,
+        }),
+        timestamp_build_output,
+        isolate_build_tmp,
+        require_nonempty_output,
+        no_initial_build,
+        warn_empty,
+        watch_batch_window: watch_batch_window.map(Duration::from_millis),
+        poll_interval: poll.map(Duration::from_millis),
+        debounce: Duration::from_millis(debounce),
+        exit_on_build_failure_count,
+        graceful_build_on_shutdown,
+        shutdown_grace: shutdown_grace.or(shutdown_timeout).map(Duration::from_secs),
+        reload_only_on_output_change,
+        maintenance_during_build,
+        reload_method,
+        manifest_path: manifest,
+        control_socket,
+        state_socket,
+        server_options: ServerOptions {
+            host,
+            port,
+            listen_fd,
+            http2_only: http2,
+            tls: tls.then_some(match (tls_cert, tls_key) {
+                (Some(cert), Some(key)) => TlsCertificate::Files { cert, key },
+                _ => TlsCertificate::SelfSigned,
+            }),
+            redirect_trailing_slash,
+            serve_hidden,
+            follow_symlinks,
+            etag_strategy,
+            inject_reload_script: reload_method.uses_script(),
+            build_log,
+            compression,
+            page404_status: StatusCode::from_u16(page404_status)?,
+            page50x: page50x
+                .map(|path| {
+                    std::path::absolute(&path)
+                        .with_context(|| format!("failed to absolutize {path:?}"))
+                })
+                .transpose()?,
+            maintenance_mode,
+            maintenance_file: maintenance_file
+                .map(|path| {
+                    std::path::absolute(&path)
+                        .with_context(|| format!("failed to absolutize {path:?}"))
+                })
+                .transpose()?,
+            maintenance_status: StatusCode::from_u16(maintenance_status)?,
+            spa,
+            requested_paths: report_unused_assets.then(RequestedPaths::default),
+            health,
+            url_file,
+            stream_test: stream_test.then(|| Duration::from_millis(stream_test_delay)),
+            access_log,
+            slow_request: slow_request_ms.map(Duration::from_millis),
+            cors,
+            shutdown_timeout: shutdown_timeout.map(Duration::from_secs),
+        },
+        browser_options: BrowserOptions {
+            reload_preserve_scroll,
+            start_url_query,
+            ws_url: browser_ws_url,
+            extra_urls: open_extra,
+            downloads_dir,
+            blocked_urls: block_url,
+            quiet_browser,
+            executable: browser_executable,
+            headless,
+            open,
+            har: har.as_ref().map(|(_, recorder)| recorder.clone()),
+            viewport,
+            window_position,
+            maximize,
+            screenshot_dir,
+        },
+        defer_browser_until_ready,
+        no_browser,
+        check,
     };
 
     // TODO try to avoid having any `unreachable!` invocations
@@ -60,6 +376,8 @@ async fn async_main() -> anyhow::Result<()> {
         build_events.map(Event::Build).box_it(),
         browser_events.map(Event::Browser).box_it(),
         fs_watch_events.map(Event::Fs).box_it(),
+        timer_events.map(Event::Timer).box_it(),
+        control_events.map(Event::Control).box_it(),
     ])
     .box_it();
 
@@ -75,6 +393,10 @@ async fn async_main() -> anyhow::Result<()> {
                         browser_driver.effect(browser_command).boxed()
                     }
                     Command::Signal(signal_command) => signal_driver.effect(signal_command).boxed(),
+                    Command::Timer(timer_command) => timer_driver.effect(timer_command).boxed(),
+                    Command::Control(control_command) => {
+                        control_driver.effect(control_command).boxed()
+                    }
                 };
                 async move {
                     future.await;
@@ -95,5 +417,28 @@ async fn async_main() -> anyhow::Result<()> {
         .unwrap()
         .unwrap();
 
+    if let Some((path, recorder)) = har
+        && let Err(error) = recorder.write(&path)
+    {
+        warn!("failed to write HAR: {error:#}");
+    }
+
+    for path in [control_socket_path, state_socket_path]
+        .into_iter()
+        .flatten()
+    {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // not dropped otherwise, because of the exit
+    drop(staging_dir);
+
+    // still referred to by the app, so removed rather than dropped
+    if let ServeDir::Temporary(temp_dir) = &*serve_dir
+        && let Err(error) = std::fs::remove_dir_all(temp_dir.path())
+    {
+        warn!("failed to remove serve dir {serve_dir}: {error}");
+    }
+
     std::process::exit(exit_code);
 }
@@ -1,12 +1,24 @@
-use std::{
-    path::{Path, PathBuf},
-    process::Command,
-};
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context as _, Ok, bail};
-use tracing::info;
+use anyhow::{Context as _, bail};
+use git2::{ErrorCode, Repository};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::{info, warn};
 
-pub(crate) fn resolve(origin: &Path) -> anyhow::Result<PathBuf> {
+/// `explicit`, if provided, or else the git toplevel of `origin`, canonicalized,
+/// so that the paths of changes within it, e.g. within `.git`, compare with paths derived from it
+pub(crate) async fn obtain(explicit: Option<&Path>, origin: &Path) -> anyhow::Result<PathBuf> {
+    match explicit {
+        Some(path) => path
+            .canonicalize()
+            .with_context(|| format!("failed to canonicalize project root {path:?}")),
+        None => resolve(origin).await,
+    }
+}
+
+/// The git toplevel of `origin`, or, if it is not within a git work tree, `origin` itself
+pub(crate) async fn resolve(origin: &Path) -> anyhow::Result<PathBuf> {
     let mut command = Command::new("git");
 
     command
@@ -15,14 +27,28 @@ pub(crate) fn resolve(origin: &Path) -> anyhow::Result<PathBuf> {
 
     let output = command
         .output()
+        .await
         .with_context(|| format!("failed to run {command:?}"))?;
 
     if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if stderr.contains("not a git repository") {
+            warn!(
+                "{origin:?} is not within a git work tree, so it is the project root; \
+                changes are filtered only by its `.gitignore` and ignore files"
+            );
+
+            return origin
+                .canonicalize()
+                .with_context(|| format!("failed to canonicalize {origin:?}"));
+        }
+
         bail!(
             "command {:?} exited with {}. stderr: {}",
             command,
             output.status,
-            String::from_utf8_lossy(&output.stderr)
+            stderr
         );
     }
 
@@ -33,5 +59,79 @@ pub(crate) fn resolve(origin: &Path) -> anyhow::Result<PathBuf> {
 
     let git_toplevel = git_toplevel.trim_end().to_string();
     info!("git toplevel obtained: {git_toplevel}");
-    Ok(git_toplevel.parse()?)
+
+    Path::new(&git_toplevel)
+        .canonicalize()
+        .with_context(|| format!("failed to canonicalize git toplevel {git_toplevel:?}"))
+}
+
+/// For consulting the ignore rules of a directory,
+/// which need not be within a git work tree
+#[derive(derive_more::Deref)]
+pub(crate) struct IgnoreRepository {
+    #[deref]
+    repository: Repository,
+    /// Of a bare repository whose work tree is the directory, if it is not within one
+    _scratch_dir: Option<TempDir>,
+    /// Where `.git` is a file, as in worktrees and submodules,
+    /// the directories it leads to need not be named `.git`
+    git_dirs: Vec<PathBuf>,
+}
+
+impl IgnoreRepository {
+    pub(crate) fn open(path: &Path) -> Result<Self, git2::Error> {
+        match Repository::discover(path) {
+            Ok(repository) => {
+                let git_dirs = [repository.path(), repository.commondir()]
+                    .into_iter()
+                    .map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()))
+                    .collect();
+
+                Ok(Self {
+                    repository,
+                    _scratch_dir: None,
+                    git_dirs,
+                })
+            }
+            Err(error) if error.code() == ErrorCode::NotFound => {
+                let scratch_dir = TempDir::new().map_err(|error| {
+                    git2::Error::from_str(&format!("failed to create scratch repository: {error}"))
+                })?;
+
+                let repository = Repository::init_bare(scratch_dir.path())?;
+                // without a `.git` file in it
+                repository.set_workdir(path, false)?;
+
+                Ok(Self {
+                    repository,
+                    _scratch_dir: Some(scratch_dir),
+                    git_dirs: Vec::new(),
+                })
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Whether `path` is ignored by git or is within the git directory
+    pub(crate) fn is_ignored(&self, path: &Path) -> Result<bool, git2::Error> {
+        if self
+            .git_dirs
+            .iter()
+            .any(|git_dir| path.starts_with(git_dir))
+        {
+            return Ok(true);
+        }
+
+        self.repository.is_path_ignored(path)
+    }
+}
+
+/// Whether `path`, within `project_root`, is ignored by git
+pub(crate) fn is_ignored(project_root: &Path, path: &Path) -> anyhow::Result<bool> {
+    let repository = IgnoreRepository::open(project_root)
+        .with_context(|| format!("failed to open the repository at {project_root:?}"))?;
+
+    repository
+        .is_ignored(path)
+        .with_context(|| format!("failed to check whether {path:?} is ignored"))
 }
@@ -0,0 +1,53 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    hash::{DefaultHasher, Hash as _, Hasher as _},
+    io::{Read as _, Write as _},
+    path::Path,
+};
+
+use anyhow::{Context as _, bail};
+use tracing::info;
+
+/// While held, no other instance can be started for the same project
+#[derive(Debug)]
+pub(crate) struct InstanceLock {
+    _file: File,
+}
+
+pub(crate) fn acquire(project_root: &Path) -> anyhow::Result<InstanceLock> {
+    let mut hasher = DefaultHasher::new();
+    project_root.hash(&mut hasher);
+    let path = std::env::temp_dir().join(format!("conveyorbelt-{:016x}.lock", hasher.finish()));
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("failed to open {path:?}"))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut pid = String::new();
+            file.read_to_string(&mut pid)
+                .with_context(|| format!("failed to read {path:?}"))?;
+
+            bail!(
+                "another instance (PID {}) is already running for {project_root:?}",
+                pid.trim()
+            );
+        }
+        Err(TryLockError::Error(error)) => {
+            return Err(error).with_context(|| format!("failed to lock {path:?}"));
+        }
+    }
+
+    file.set_len(0)
+        .and_then(|()| write!(file, "{}", std::process::id()))
+        .with_context(|| format!("failed to write {path:?}"))?;
+
+    info!("instance lock acquired: {path:?}");
+    Ok(InstanceLock { _file: file })
+}
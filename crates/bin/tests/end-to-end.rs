@@ -4,27 +4,44 @@ mod common;
 use std::{
     env,
     fs::{self, Permissions},
-    os::unix::fs::{PermissionsExt, symlink},
+    io::{BufRead as _, BufReader, Write as _},
+    net::{Ipv4Addr, TcpListener},
+    os::unix::{
+        fs::{PermissionsExt, symlink},
+        net::UnixListener,
+    },
+    path::Path,
+    thread,
+    time::Duration,
 };
 
-use chromiumoxide::cdp::browser_protocol::{
-    browser::{GetWindowBoundsParams, GetWindowForTargetParams},
-    network::EventResponseReceived,
-    target::GetTargetsParams,
+use chromiumoxide::{
+    Browser,
+    cdp::browser_protocol::{
+        browser::{GetWindowBoundsParams, GetWindowForTargetParams},
+        network::EventResponseReceived,
+        target::GetTargetsParams,
+    },
+    handler::HandlerConfig,
 };
 use futures::StreamExt as _;
 use indoc::{formatdoc, indoc};
 use nix::{
     sys::signal::{
-        Signal::{SIGINT, SIGTERM},
+        Signal::{SIGHUP, SIGINT, SIGTERM},
         kill,
     },
     unistd::Pid,
 };
 
 use crate::{
-    fixture::Fixture, fresh_browser::FreshBrowser, html_page::HtmlPage, kill_wait::KillWait as _,
-    shared_environment::SharedEnvironment, signalable::Signalable,
+    common::{CHANGED_PATHS, MAX_LINE_LENGTH, SERVE_PATH, StateForTesting, TRUNCATION_MARKER},
+    fixture::Fixture,
+    fresh_browser::FreshBrowser,
+    html_page::HtmlPage,
+    kill_wait::KillWait as _,
+    shared_environment::SharedEnvironment,
+    signalable::Signalable,
 };
 
 #[static_init::dynamic(drop)]
@@ -73,6 +90,16 @@ async fn default_404_page() {
     assert_eq!(response_status, 404);
 }
 
+#[test]
+fn serve_dir_removed_on_exit() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let serve_path = subject.state_for_testing().unwrap().serve_path;
+    assert!(fs::exists(&serve_path).unwrap());
+    subject.kill_wait(SIGTERM).unwrap();
+    assert!(!fs::exists(&serve_path).unwrap());
+}
+
 #[tokio::test]
 async fn browser_is_launched() {
     let fixture = Fixture::init().unwrap();
@@ -109,6 +136,26 @@ async fn launched_browser_has_one_page_at_served_root() {
     assert_eq!(actual, expected);
 }
 
+#[tokio::test]
+async fn open_false() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--open", "false"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
+
+    let pages = browser
+        .execute(GetTargetsParams { filter: None })
+        .await
+        .unwrap();
+
+    let [page] = pages.target_infos.as_slice() else {
+        panic!("pages length is not 1");
+    };
+
+    assert_eq!(page.url, "about:blank");
+}
+
 #[tokio::test]
 async fn launched_browser_has_head() {
     let fixture = Fixture::init().unwrap();
@@ -124,6 +171,22 @@ async fn launched_browser_has_head() {
     assert!(!user_agent.contains("HeadlessChrome"), "{user_agent}");
 }
 
+#[tokio::test]
+async fn launched_browser_headless() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--headless"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
+    let page = browser.new_page("about:blank").await.unwrap();
+    let user_agent = page.evaluate("navigator.userAgent").await.unwrap();
+
+    let Some(serde_json::Value::String(user_agent)) = user_agent.value() else {
+        panic!();
+    };
+
+    assert!(user_agent.contains("HeadlessChrome"), "{user_agent}");
+}
+
 #[tokio::test]
 async fn custom_404_page() {
     let fixture = Fixture::init().unwrap();
@@ -288,6 +351,38 @@ async fn forbid_symlinks() {
     assert_eq!(response.status, 403);
 }
 
+#[test]
+fn serve_hidden() {
+    let mut fixture = Fixture::init().unwrap();
+    fs::create_dir(fixture.src_path().join(".well-known")).unwrap();
+
+    fixture
+        .write_source_file(".well-known/assetlinks.json", "[]")
+        .unwrap();
+
+    fixture.add_subject_args(["--serve-hidden", "--no-browser"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let response = subject
+        .http_get("/.well-known/assetlinks.json", &[])
+        .unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"[]");
+}
+
+#[test]
+fn follow_symlinks() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.write_source_file("real.txt", "real").unwrap();
+    symlink("real.txt", fixture.src_path().join("symlink.txt")).unwrap();
+    fixture.add_subject_args(["--follow-symlinks", "--no-browser"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let response = subject.http_get("/symlink.txt", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"real");
+}
+
 #[test]
 fn sigint() {
     let fixture = Fixture::init().unwrap();
@@ -328,6 +423,63 @@ fn sigterm() {
     assert_eq!(status.code(), Some(0));
 }
 
+#[test]
+fn graceful_build_on_shutdown() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            if ($env.SRC_PATH | path join slow | path exists) {
+                sleep 2sec
+                print 'build finished'
+            }
+            if ($env.SERVE_PATH | path exists) {
+                rm --recursive $env.SERVE_PATH
+            }
+            cp --recursive $env.SRC_PATH $env.SERVE_PATH
+        "})
+        .unwrap();
+
+    fixture.add_subject_args(["--graceful-build-on-shutdown"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("slow", "").unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+    subject.signal(SIGTERM).unwrap();
+    subject
+        .wait_stderr_contains("waiting for the build to finish before shutting down")
+        .unwrap();
+    subject
+        .wait_stderr_contains("stream=stdout line=build finished")
+        .unwrap();
+    subject.wait_stderr_contains("exit: 0").unwrap();
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(0));
+}
+
+#[test]
+fn shutdown_timeout() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--shutdown-timeout", "1"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let port = subject.state_for_testing().unwrap().serve_port;
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    // an incomplete request, which graceful shutdown would wait for indefinitely
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n")
+        .unwrap();
+    subject.signal(SIGTERM).unwrap();
+
+    subject
+        .wait_stderr_contains("connections remain open 1s into shutdown; dropping them")
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(0));
+}
+
 #[test]
 fn cannot_find_git_executable() {
     let mut fixture = Fixture::init().unwrap();
@@ -356,18 +508,241 @@ fn cannot_find_browser_executable() {
     assert_eq!(status.code(), Some(1));
 }
 
+#[test]
+fn browser_executable() {
+    let browser_dir = tempfile::TempDir::new().unwrap();
+    let browser_path = browser_dir.path().join("chromium-canary");
+    let marker_path = browser_dir.path().join("launched");
+
+    fs::write(
+        &browser_path,
+        script::Script::new(
+            env!("_BASH_EXECUTABLE"),
+            formatdoc! {r#"
+                touch {}
+                exec {} "$@"
+            "#,
+                marker_path.display(),
+                Path::new(env!("CHROMIUM_BIN_PATH")).join("chromium").display(),
+            },
+        )
+        .0,
+    )
+    .unwrap();
+
+    fs::set_permissions(&browser_path, Permissions::from_mode(0o755)).unwrap();
+
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .subject_path_env_var
+        .remove(env!("CHROMIUM_BIN_PATH"));
+
+    fixture.add_subject_args(["--browser-executable", browser_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    assert!(fs::exists(&marker_path).unwrap());
+}
+
+#[test]
+fn browser_executable_not_found() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--browser-executable", "/nonexistent/chromium"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains(r#"browser executable "/nonexistent/chromium" does not exist"#)
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(1));
+}
+
 #[test]
 fn not_inside_a_git_work_tree() {
     let fixture = Fixture::init().unwrap();
     fs::remove_dir_all(fixture.root().join(".git")).unwrap();
+    fixture
+        .write_source_file(".gitignore", "/ignored.txt")
+        .unwrap();
     let mut subject = fixture.spawn_subject().unwrap();
 
     subject
-        .wait_stderr_contains("not a git repository")
+        .wait_stderr_contains("is not within a git work tree, so it is the project root")
         .unwrap();
 
-    let status = subject.wait().unwrap();
-    assert_eq!(status.code(), Some(1));
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("ignored.txt", "").unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("ignored.txt\" (git ignored)")),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn dot_git_file() {
+    let fixture = Fixture::init().unwrap();
+    fs::remove_dir_all(fixture.root().join(".git")).unwrap();
+    let git_dir = fixture.root().join("git-dir");
+
+    let git_init_status = std::process::Command::new(Path::new(env!("GIT_BIN_PATH")).join("git"))
+        .current_dir(fixture.root())
+        .args(["init", "--quiet", "--separate-git-dir"])
+        .arg(&git_dir)
+        .status()
+        .unwrap();
+
+    assert!(git_init_status.success());
+    assert!(fixture.root().join(".git").is_file());
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fs::write(git_dir.join("foo"), "").unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("git-dir/foo\" (git ignored)")),
+        "{lines:#?}"
+    );
+
+    assert!(
+        !lines
+            .iter()
+            .any(|line| line.contains("git-dir/foo\" create")),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn dot_git_under_symlinked_project_root() {
+    // outside of the repository, so that it is not watched
+    let link_dir = tempfile::TempDir::new().unwrap();
+    let link_path = link_dir.path().join("link");
+    let mut fixture = Fixture::init().unwrap();
+    std::os::unix::fs::symlink(fixture.root(), &link_path).unwrap();
+    fixture.add_subject_args(["--project-root", link_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fs::write(fixture.root().join(".git").join("foo"), "").unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains(".git/foo\" (git ignored)")),
+        "{lines:#?}"
+    );
+
+    assert!(
+        !lines.iter().any(|line| line.contains(".git/foo\" create")),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn project_root() {
+    let mut fixture = Fixture::init().unwrap();
+    let project_root = fixture.src_path().to_str().unwrap().to_owned();
+    fixture.add_subject_args(["--project-root", &project_root]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fs::write(fixture.root().join("outside.txt"), "").unwrap();
+    fixture.write_source_file("inside.txt", "").unwrap();
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        !lines.iter().any(|line| line.contains("outside.txt")),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn quiet() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.set_build_command_nu("exit 1").unwrap();
+    fixture.add_subject_args(["--quiet"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build: exited with Some(1)")
+        .unwrap();
+
+    assert!(
+        !lines.iter().any(|line| line.contains(" INFO ")),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn verbose() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--verbose"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_stderr_contains("arguments parsed").unwrap();
+}
+
+#[test]
+fn watch() {
+    let mut fixture = Fixture::init().unwrap();
+    let extra = tempfile::TempDir::new().unwrap();
+    let extra_path = extra.path().canonicalize().unwrap();
+    fixture.add_subject_args(["--watch", extra_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fs::write(extra_path.join("tokens.json"), "{}").unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    let expected = format!("{:?} create", extra_path.join("tokens.json"));
+
+    assert!(
+        lines.iter().any(|line| line.contains(&expected)),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn watch_ext() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--watch-ext", "html,css"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("toolchain.log", "").unwrap();
+
+    subject
+        .wait_stderr_contains("/toolchain.log\" (extension not watched) create")
+        .unwrap();
+
+    fixture.write_source_file("style.css", "").unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("/style.css\" create")),
+        "{lines:#?}"
+    );
 }
 
 #[test]
@@ -444,7 +819,7 @@ async fn build_process_restart() {
     fixture.write_source_file("trigger", "").unwrap();
 
     subject
-        .wait_stderr_contains("build: stderr: looping")
+        .wait_stderr_contains("stream=stderr line=looping")
         .unwrap();
 
     fixture.set_build_command_nu("print -e hello").unwrap();
@@ -460,7 +835,7 @@ async fn build_process_restart() {
         .unwrap();
 
     subject
-        .wait_stderr_contains("event: build: stderr: hello")
+        .wait_stderr_contains("event: build: output line stream=stderr line=hello")
         .unwrap();
 }
 
@@ -493,69 +868,256 @@ fn initial_build_fail() {
 }
 
 #[test]
-fn build_command_stderr() {
+fn require_nonempty_output() {
     let mut fixture = Fixture::init().unwrap();
-
-    fixture
-        .set_build_command_nu("print -e 'some stderr line'")
-        .unwrap();
-
+    fixture.add_subject_args(["--require-nonempty-output"]);
+    fixture.set_build_command_nu("exit 0").unwrap();
     let mut subject = fixture.spawn_subject().unwrap();
 
     subject
-        .wait_stderr_contains("build: stderr: some stderr line")
+        .wait_stderr_contains("build: no files were written to the serve path")
         .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(1));
 }
 
 #[test]
-fn build_command_stdout() {
+fn require_nonempty_output_of_rebuild() {
     let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--require-nonempty-output"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
     fixture
-        .set_build_command_nu("print 'some stdout line'")
+        .set_build_command_bash(format!(r#"rm -r "${{{SERVE_PATH}}}"/*"#))
         .unwrap();
-    let mut subject = fixture.spawn_subject().unwrap();
+
+    fixture.write_source_file("trigger", "").unwrap();
 
     subject
-        .wait_stderr_contains("build: stdout: some stdout line")
+        .wait_stderr_contains("build: no files were written to the serve path")
         .unwrap();
+
+    assert_eq!(
+        subject
+            .next_state_for_testing()
+            .unwrap()
+            .last_build_exit_code,
+        None
+    );
 }
 
 #[test]
-fn build_failure_followed_by_success() {
+fn warn_empty() {
     let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--warn-empty"]);
+    fixture.set_build_command_nu("exit 0").unwrap();
     let mut subject = fixture.spawn_subject().unwrap();
 
-    subject.wait_browser_spawned().unwrap();
+    subject
+        .wait_stderr_contains("the build succeeded without writing any files to")
+        .unwrap();
 
-    fixture.set_build_command_nu("exit 1").unwrap();
+    fixture
+        .set_build_command_nu(formatdoc! {"
+            touch ($env.{SERVE_PATH} | path join foo.txt)
+        "})
+        .unwrap();
 
-    fixture.write_source_file("trigger", "").unwrap();
+    fixture.write_source_file("foo", "").unwrap();
 
     subject
-        .wait_stderr_contains("build: exited with Some(1)")
+        .wait_stderr_contains("the build succeeded without writing index.html to")
         .unwrap();
+}
 
-    fixture.set_build_command_nu("exit 0").unwrap();
+#[test]
+fn check() {
+    let fixture = Fixture::init().unwrap();
 
-    fixture.write_source_file("trigger", "").unwrap();
+    let output = fixture
+        .run_subject([fixture.build_command().as_os_str(), "--check".as_ref()])
+        .unwrap();
 
-    subject
-        .wait_stderr_contains("build: exited with Some(0)")
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("check passed"), "{stderr}");
+    assert!(!stderr.contains("server: spawn"), "{stderr}");
+    assert!(!stderr.contains("browser: spawn"), "{stderr}");
+}
+
+#[test]
+fn check_build_failure() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.set_build_command_nu("exit 1").unwrap();
+
+    let output = fixture
+        .run_subject([fixture.build_command().as_os_str(), "--check".as_ref()])
         .unwrap();
 
-    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("check failed"), "{stderr}");
 }
 
-#[tokio::test]
-async fn browser_window_not_at_default_chromiumoxide_dimensions() {
-    let fixture = Fixture::init().unwrap();
+#[test]
+fn build_command_stderr() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu("print -e 'some stderr line'")
+        .unwrap();
+
     let mut subject = fixture.spawn_subject().unwrap();
-    let browser = subject.connect_to_browser().await.unwrap();
-    let page = browser.new_page("about:blank").await.unwrap();
 
-    let window_id = browser
-        .execute(
-            GetWindowForTargetParams::builder()
+    subject
+        .wait_stderr_contains("stream=stderr line=some stderr line")
+        .unwrap();
+}
+
+#[test]
+fn build_command_stdout() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture
+        .set_build_command_nu("print 'some stdout line'")
+        .unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("stream=stdout line=some stdout line")
+        .unwrap();
+}
+
+#[test]
+fn timestamp_build_output() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture
+        .set_build_command_nu("print 'some stdout line'")
+        .unwrap();
+    fixture.add_subject_args(["--timestamp-build-output"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let line = subject.wait_stderr_contains("some stdout line").unwrap();
+
+    let timestamp = line
+        .split_once("stream=stdout read_at=")
+        .and_then(|(_, rest)| rest.split_once(" line=some stdout line"))
+        .map(|(timestamp, _)| timestamp)
+        .unwrap();
+
+    let (seconds, micros) = timestamp.split_once('.').unwrap();
+    assert!(seconds.parse::<u64>().unwrap() > 0);
+    assert_eq!(micros.len(), 6);
+    micros.parse::<u32>().unwrap();
+}
+
+#[test]
+fn log_format_json() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture
+        .set_build_command_nu("print 'some stdout line'")
+        .unwrap();
+    fixture.add_subject_args(["--log-format", "json"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let line = subject.wait_stderr_contains("some stdout line").unwrap();
+    let object: serde_json::Value = serde_json::from_str(&line).unwrap();
+    let fields = &object["fields"];
+    assert_eq!(fields["message"], "event: build: output line", "{object}");
+    assert_eq!(fields["stream"], "stdout", "{object}");
+    assert_eq!(fields["line"], "some stdout line", "{object}");
+    assert_eq!(fields["source"], "build", "{object}");
+    assert_eq!(object["level"], "INFO", "{object}");
+}
+
+#[test]
+fn build_stderr_logged_as_warning() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture
+        .set_build_command_nu("print -e 'some stderr line'")
+        .unwrap();
+    fixture.add_subject_args(["--log-format", "json"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let line = subject.wait_stderr_contains("some stderr line").unwrap();
+    let object: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(object["fields"]["stream"], "stderr", "{object}");
+    assert_eq!(object["fields"]["source"], "build", "{object}");
+    assert_eq!(object["level"], "WARN", "{object}");
+}
+
+#[test]
+fn build_failure_followed_by_success() {
+    let mut fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject.wait_browser_spawned().unwrap();
+
+    fixture.set_build_command_nu("exit 1").unwrap();
+
+    fixture.write_source_file("trigger", "").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(1)")
+        .unwrap();
+
+    fixture.set_build_command_nu("exit 0").unwrap();
+
+    fixture.write_source_file("trigger", "").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+}
+
+#[test]
+fn build_exit_status() {
+    let mut fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    assert_eq!(
+        subject.state_for_testing().unwrap().last_build_exit_code,
+        Some(0)
+    );
+
+    fixture.set_build_command_nu("exit 3").unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+
+    subject
+        .wait_stderr_contains("build command exit status: 3")
+        .unwrap();
+
+    assert_eq!(
+        subject
+            .next_state_for_testing()
+            .unwrap()
+            .last_build_exit_code,
+        Some(3)
+    );
+
+    fixture.set_build_command_nu("exit 0").unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+
+    assert_eq!(
+        subject
+            .next_state_for_testing()
+            .unwrap()
+            .last_build_exit_code,
+        Some(0)
+    );
+}
+
+#[tokio::test]
+async fn browser_window_not_at_default_chromiumoxide_dimensions() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
+    let page = browser.new_page("about:blank").await.unwrap();
+
+    let window_id = browser
+        .execute(
+            GetWindowForTargetParams::builder()
                 .target_id(page.target_id().clone())
                 .build(),
         )
@@ -575,6 +1137,109 @@ async fn browser_window_not_at_default_chromiumoxide_dimensions() {
     assert!(window_bounds.height.unwrap() > 600);
 }
 
+#[tokio::test]
+async fn viewport() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--viewport", "500x400"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    let debugging_address = subject
+        .state_for_testing()
+        .unwrap()
+        .browser_debugging_address
+        .unwrap();
+
+    // without the default emulation, which would override that of the subject
+    let handler_config = HandlerConfig {
+        viewport: None,
+        ..HandlerConfig::default()
+    };
+
+    let (mut browser, mut handler) =
+        Browser::connect_with_config(debugging_address, handler_config)
+            .await
+            .unwrap();
+
+    tokio::spawn(async move { while handler.next().await.is_some() {} });
+    browser.fetch_targets().await.unwrap();
+
+    let page = loop {
+        if let Some(page) = browser.pages().await.unwrap().into_iter().next() {
+            break page;
+        }
+    };
+
+    let dimensions: (u32, u32) = page
+        .evaluate("[window.innerWidth, window.innerHeight]")
+        .await
+        .unwrap()
+        .into_value()
+        .unwrap();
+
+    assert_eq!(dimensions, (500, 400));
+}
+
+#[test]
+fn viewport_invalid() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--viewport", "500by400"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("neither `off` nor of the form `<width>x<height>`")
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn window_position() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--window-position", "100,50"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
+    // a tab of the existing window
+    let page = browser.new_page("about:blank").await.unwrap();
+
+    let window_id = browser
+        .execute(
+            GetWindowForTargetParams::builder()
+                .target_id(page.target_id().clone())
+                .build(),
+        )
+        .await
+        .unwrap()
+        .result
+        .window_id;
+
+    let window_bounds = browser
+        .execute(GetWindowBoundsParams::new(window_id))
+        .await
+        .unwrap()
+        .result
+        .bounds;
+
+    assert_eq!(
+        (window_bounds.left, window_bounds.top),
+        (Some(100), Some(50))
+    );
+}
+
+#[test]
+fn window_position_invalid() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--window-position", "100x50"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject
+        .wait_stderr_contains("not of the form `<x>,<y>`")
+        .unwrap();
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(2));
+}
+
 #[test]
 fn build_not_executed_on_git_ignored_file_create() {
     let mut fixture = Fixture::init().unwrap();
@@ -611,188 +1276,2676 @@ fn build_not_executed_on_git_ignored_file_create() {
 #[test]
 fn build_not_executed_on_git_ignored_file_modify() {
     let mut fixture = Fixture::init().unwrap();
-    fixture.write_source_file(".gitignore", "/foo").unwrap();
-    fixture.write_source_file("foo", "create").unwrap();
-    let mut subject = fixture.spawn_subject().unwrap();
-    let serve_path = &subject.state_for_testing().unwrap().serve_path;
-    let serve_path_str = serve_path.to_str().unwrap();
+    fixture.write_source_file(".gitignore", "/foo").unwrap();
+    fixture.write_source_file("foo", "create").unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let serve_path = &subject.state_for_testing().unwrap().serve_path;
+    let serve_path_str = serve_path.to_str().unwrap();
+
+    fixture
+        .set_build_command_nu(format!("touch {serve_path_str}/foo-indicator"))
+        .unwrap();
+
+    fixture.write_source_file("foo", "no trigger").unwrap();
+
+    subject
+        .wait_stderr_contains("/foo\" (git ignored) modify")
+        .unwrap();
+
+    fixture
+        .set_build_command_nu(format!("touch {serve_path_str}/bar-indicator"))
+        .unwrap();
+
+    fixture.write_source_file("bar", "trigger").unwrap();
+    subject.wait_stderr_contains("/bar\" create File").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+
+    assert!(!fs::exists(serve_path.join("foo-indicator")).unwrap());
+    assert!(fs::exists(serve_path.join("bar-indicator")).unwrap());
+}
+
+#[test]
+fn build_not_executed_on_git_ignored_file_remove() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.write_source_file(".gitignore", "/foo").unwrap();
+    fixture.write_source_file("foo", "create").unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let serve_path = &subject.state_for_testing().unwrap().serve_path;
+    let serve_path_str = serve_path.to_str().unwrap();
+
+    fixture
+        .set_build_command_nu(format!("touch {serve_path_str}/foo-indicator"))
+        .unwrap();
+
+    fs::remove_file(fixture.src_path().join("foo")).unwrap();
+
+    subject
+        .wait_stderr_contains("/foo\" (git ignored) remove")
+        .unwrap();
+
+    fixture
+        .set_build_command_nu(format!("touch {serve_path_str}/bar-indicator"))
+        .unwrap();
+
+    fixture.write_source_file("bar", "trigger").unwrap();
+    subject.wait_stderr_contains("/bar\" create File").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+
+    assert!(!fs::exists(serve_path.join("foo-indicator")).unwrap());
+    assert!(fs::exists(serve_path.join("bar-indicator")).unwrap());
+}
+
+#[test]
+fn build_not_executed_on_conveyorbelt_ignored_file_create() {
+    let mut fixture = Fixture::init().unwrap();
+    fs::write(fixture.root().join(".conveyorbeltignore"), "/src/foo").unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let serve_path = &subject.state_for_testing().unwrap().serve_path;
+    let serve_path_str = serve_path.to_str().unwrap();
+
+    fixture
+        .set_build_command_nu(format!("touch {serve_path_str}/foo-indicator"))
+        .unwrap();
+
+    fixture.write_source_file("foo", "no trigger").unwrap();
+
+    subject
+        .wait_stderr_contains("/foo\" (git ignored) create")
+        .unwrap();
+
+    fixture
+        .set_build_command_nu(format!("touch {serve_path_str}/bar-indicator"))
+        .unwrap();
+
+    fixture.write_source_file("bar", "trigger").unwrap();
+    subject.wait_stderr_contains("/bar\" create File").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+
+    assert!(!fs::exists(serve_path.join("foo-indicator")).unwrap());
+    assert!(fs::exists(serve_path.join("bar-indicator")).unwrap());
+}
+
+#[test]
+fn ignore_file() {
+    let mut fixture = Fixture::init().unwrap();
+    let ignore_file = tempfile::NamedTempFile::new().unwrap();
+    fs::write(ignore_file.path(), "/src/foo\n").unwrap();
+    fixture.add_subject_args(["--ignore-file", ignore_file.path().to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.state_for_testing().unwrap();
+    fixture.write_source_file("foo", "no trigger").unwrap();
+
+    subject
+        .wait_stderr_contains("/foo\" (git ignored) create")
+        .unwrap();
+
+    fixture.write_source_file("bar", "trigger").unwrap();
+    subject.wait_stderr_contains("/bar\" create File").unwrap();
+}
+
+#[test]
+fn ignore_file_of_subdirectory_project_root() {
+    let mut fixture = Fixture::init().unwrap();
+    let project_root = fixture.src_path().to_str().unwrap().to_owned();
+    fixture
+        .write_source_file(".conveyorbeltignore", "/foo\n")
+        .unwrap();
+    fixture.add_subject_args(["--project-root", &project_root]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("foo", "no trigger").unwrap();
+
+    subject
+        .wait_stderr_contains("/foo\" (git ignored) create")
+        .unwrap();
+
+    fixture.write_source_file("bar", "trigger").unwrap();
+    subject.wait_stderr_contains("/bar\" create File").unwrap();
+}
+
+#[test]
+fn ignore_file_missing() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--ignore-file", "/nonexistent/ignore"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("ignore file \"/nonexistent/ignore\" does not exist")
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn build_on_file_creation() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("new_file", "").unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+}
+
+#[test]
+fn poll() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--poll", "100"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject
+        .wait_stderr_contains("polling for changes every 100ms")
+        .unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("new_file", "").unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+}
+
+#[test]
+fn build_on_file_change() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("file", "").unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+    fixture.write_source_file("file", "").unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+}
+
+#[test]
+fn build_on_file_removal() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("file", "").unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+    fs::remove_file(fixture.src_path().join("file")).unwrap();
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+}
+
+#[test]
+fn browser_reloads_following_build() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject.wait_browser_spawned().unwrap();
+
+    fixture.write_source_file("trigger", "").unwrap();
+
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+}
+
+#[test]
+fn serve_dir_exists_after_termination() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let state_for_testing = subject.state_for_testing().unwrap();
+    subject.kill_wait(SIGTERM).unwrap();
+    assert!(std::fs::exists(state_for_testing.serve_path).unwrap())
+}
+
+#[tokio::test]
+async fn cdp_errors_are_reported() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let browser_pid = subject.state_for_testing().unwrap().browser_pid.unwrap();
+    let browser_pid = Pid::from_raw(browser_pid as i32);
+    kill(browser_pid, SIGTERM).unwrap();
+
+    subject
+        .wait_stderr_contains("WS Connection error: Ws(Protocol(ResetWithoutClosingHandshake))")
+        .unwrap();
+}
+
+#[test]
+fn build_succeeds_while_being_terminated() {
+    let mut fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    fixture
+        .set_build_command_bash(formatdoc! {r#"
+            trap "exit 0" TERM
+            echo "trap is set"
+            while true; do
+                read -r _
+            done
+        "#})
+        .unwrap();
+
+    fixture.write_source_file("first trigger", "").unwrap();
+
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+
+    subject
+        .wait_stderr_contains("stream=stdout line=trap is set")
+        .unwrap();
+
+    fixture.set_build_command_nu("").unwrap();
+
+    fixture.write_source_file("second trigger", "").unwrap();
+
+    subject
+        .wait_stderr_contains("event: build: sent SIGTERM")
+        .unwrap();
+
+    subject
+        .wait_stderr_contains("event: build: exited with Some(0)")
+        .unwrap();
+
+    subject
+        .wait_stderr_contains("event: build: spawn pid ")
+        .unwrap();
+}
+
+#[test]
+fn etag_strategy_hash_stable_across_identical_builds() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--etag-strategy", "hash"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let before = subject.http_get("/index.html", &[]).unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    let after = subject.http_get("/index.html", &[]).unwrap();
+    let etag = before.header("etag").unwrap();
+    assert_eq!(Some(etag), after.header("etag"));
+    let not_modified = subject
+        .http_get("/index.html", &[("If-None-Match", etag)])
+        .unwrap();
+    assert_eq!(not_modified.status, 304);
+    assert!(not_modified.body.is_empty());
+
+    for if_none_match in [
+        format!("\"other\", {etag}"),
+        format!("W/{etag}"),
+        "*".to_owned(),
+    ] {
+        let response = subject
+            .http_get("/index.html", &[("If-None-Match", &if_none_match)])
+            .unwrap();
+
+        assert_eq!(response.status, 304, "{if_none_match}");
+    }
+
+    let modified = subject
+        .http_get("/index.html", &[("If-None-Match", "\"other\"")])
+        .unwrap();
+
+    assert_eq!(modified.status, 200);
+}
+
+#[test]
+fn etag_strategy_hash_not_sent_for_head() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--etag-strategy", "hash"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let port = subject.state_for_testing().unwrap().serve_port;
+    let mut stream = std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+
+    write!(
+        stream,
+        "HEAD /index.html HTTP/1.0\r\nHost: {}:{port}\r\n\r\n",
+        Ipv4Addr::LOCALHOST
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+    assert!(!response.to_lowercase().contains("etag:"), "{response}");
+}
+
+#[test]
+fn etag_strategy_mtime() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--etag-strategy", "mtime"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/index.html", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    assert!(response.header("etag").is_some());
+}
+
+#[test]
+fn etag_strategy_none_by_default() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/index.html", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("etag"), None);
+}
+
+#[test]
+fn init_scaffolds_build_command() {
+    let fixture = Fixture::init().unwrap();
+    let output = fixture.run_subject(["init"]).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let scaffolded = fixture.root().join("build.sh");
+    let mode = fs::metadata(&scaffolded).unwrap().permissions().mode();
+    assert_eq!(mode & 0o111, 0o111);
+    fs::copy(&scaffolded, fixture.build_command()).unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(
+        body.contains("<title>Scaffolded by conveyorbelt</title>"),
+        "{body}"
+    );
+}
+
+#[test]
+fn init_refuses_to_overwrite() {
+    let fixture = Fixture::init().unwrap();
+    fs::write(fixture.root().join("build.sh"), "precious").unwrap();
+    let output = fixture.run_subject(["init"]).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("refusing to overwrite"), "{stderr}");
+    let content = fs::read_to_string(fixture.root().join("build.sh")).unwrap();
+    assert_eq!(content, "precious");
+}
+
+#[test]
+fn build_output_stream_stderr_only() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--build-output-stream", "stderr"]);
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            1..10000 | each {|i| print $'stdout line ($i)' }
+            print -e 'stderr line'
+        "})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build: exited with Some(0)")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("stream=stderr line=stderr line"))
+    );
+    assert!(!lines.iter().any(|line| line.contains("build: stdout:")));
+}
+
+#[test]
+fn build_output_stream_stdout_only() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--build-output-stream", "stdout"]);
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            print 'stdout line'
+            print -e 'stderr line'
+        "})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build: exited with Some(0)")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("stream=stdout line=stdout line"))
+    );
+    assert!(!lines.iter().any(|line| line.contains("build: stderr:")));
+}
+
+#[test]
+fn fixed_port() {
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--port".to_string(), port.to_string()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    assert_eq!(subject.state_for_testing().unwrap().serve_port, port);
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn listen_fd() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    // to be inherited by the subject
+    socket2::SockRef::from(&listener)
+        .set_cloexec(false)
+        .unwrap();
+    let fd = std::os::fd::AsRawFd::as_raw_fd(&listener);
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--listen-fd".to_string(), fd.to_string()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    assert_eq!(subject.state_for_testing().unwrap().serve_port, port);
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn health() {
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    // outside of the repository, so that creating the release file does not trigger a build
+    let release_dir = tempfile::TempDir::new().unwrap();
+    let release_path = release_dir.path().join("release");
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            while not ({release_path:?} | path exists) {{
+                sleep 100ms
+            }}
+        "#})
+        .unwrap();
+
+    fixture.add_subject_args([
+        "--health".to_string(),
+        "--port".to_string(),
+        port.to_string(),
+    ]);
+
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let response = loop {
+        if let Ok(response) = crate::http_get::http_get(port, "/health", &[]) {
+            break response;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    assert_eq!(response.status, 503);
+    fs::write(&release_path, "").unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/health", &[]).unwrap();
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn fixed_port_in_use() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--port".to_string(), port.to_string()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains(format!("port {port} is already in use"))
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(1));
+}
+
+#[tokio::test]
+async fn reload_preserves_scroll() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--reload-preserve-scroll"]);
+
+    fixture
+        .write_source_file(
+            "index.html",
+            indoc! {r#"
+                <!DOCTYPE html>
+                <html>
+                    <head><link rel="icon" href="data:,"></head>
+                    <body style="height: 10000px"></body>
+                </html>
+            "#},
+        )
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    let mut browser = subject.connect_to_browser().await.unwrap();
+    browser.fetch_targets().await.unwrap();
+
+    let page = loop {
+        if let Some(page) = browser.pages().await.unwrap().into_iter().next() {
+            break page;
+        }
+    };
+
+    page.evaluate("window.scrollTo(0, 3000)").await.unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+
+    let scroll_y: f64 = page
+        .evaluate("window.scrollY")
+        .await
+        .unwrap()
+        .into_value()
+        .unwrap();
+
+    assert!((scroll_y - 3000.0).abs() < 1.0, "{scroll_y}");
+}
+
+#[test]
+fn redirect_trailing_slash() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--redirect-trailing-slash"]);
+    fs::create_dir(fixture.src_path().join("sub")).unwrap();
+
+    fixture
+        .write_source_file("sub/index.html", HtmlPage::new().title("sub"))
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    let response = subject.http_get("/sub", &[]).unwrap();
+    assert!([301, 308].contains(&response.status), "{response:?}");
+    assert_eq!(response.header("location"), Some("/sub/"));
+}
+
+#[test]
+fn no_redirect_trailing_slash_by_default() {
+    let fixture = Fixture::init().unwrap();
+    fs::create_dir(fixture.src_path().join("sub")).unwrap();
+
+    fixture
+        .write_source_file("sub/index.html", HtmlPage::new().title("sub"))
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    let response = subject.http_get("/sub", &[]).unwrap();
+    assert!(!(300..400).contains(&response.status), "{response:?}");
+}
+
+#[test]
+fn host_unspecified() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--host", "0.0.0.0"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("server: spawn: server at address 0.0.0.0:")
+        .unwrap();
+
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn host_invalid() {
+    let fixture = Fixture::init().unwrap();
+
+    let output = fixture
+        .run_subject([
+            fixture.build_command().as_os_str(),
+            "--host".as_ref(),
+            "localhost:8080".as_ref(),
+        ])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn build_output_invalid_utf8() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_bash(indoc! {r"
+            printf 'caf\xe9\n'
+            echo after
+        "})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("stream=stdout line=caf\u{FFFD}")
+        .unwrap();
+
+    subject
+        .wait_stderr_contains("stream=stdout line=after")
+        .unwrap();
+}
+
+#[test]
+fn build_output_long_line_is_truncated() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {"
+            print ('' | fill --character 'x' --width {})
+            print 'after'
+        ", MAX_LINE_LENGTH * 100})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    let line = subject
+        .wait_stderr_contains("stream=stdout line=xxx")
+        .unwrap();
+    assert!(line.ends_with(TRUNCATION_MARKER), "{line}");
+    assert!(line.len() < MAX_LINE_LENGTH * 2);
+    subject
+        .wait_stderr_contains("stream=stdout line=after")
+        .unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn injected_reload_script() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--reload-method", "script"]);
+    fixture.write_source_file("file.txt", "plain").unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let text = subject.http_get("/file.txt", &[]).unwrap();
+    assert_eq!(text.body, b"plain");
+    let html = subject.http_get("/", &[]).unwrap();
+    let html = String::from_utf8(html.body).unwrap();
+    assert!(html.contains("<script>"), "{html}");
+    let browser = FreshBrowser::spawn().await.unwrap();
+
+    let page = browser.new_page(subject.url("/").unwrap()).await.unwrap();
+
+    page.wait_for_navigation().await.unwrap();
+    page.evaluate("window.notReloaded = true").await.unwrap();
+
+    // the event source might not be connected yet, so keep building until it is
+    loop {
+        fixture.write_source_file("trigger", "").unwrap();
+        subject
+            .wait_stderr_contains("server: reload triggered")
+            .unwrap();
+
+        let not_reloaded: bool = page
+            .evaluate("window.notReloaded === true")
+            .await
+            .unwrap()
+            .into_value()
+            .unwrap();
+
+        if !not_reloaded {
+            break;
+        }
+    }
+}
+
+#[test]
+fn injected_reload_script_not_in_head() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--reload-method", "script"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let state = subject.state_for_testing().unwrap();
+    let index_len = fs::metadata(state.serve_path.join("index.html"))
+        .unwrap()
+        .len();
+    let port = state.serve_port;
+    let mut stream = std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+
+    write!(
+        stream,
+        "HEAD / HTTP/1.0\r\nHost: {}:{port}\r\nAccept: text/html\r\n\r\n",
+        Ipv4Addr::LOCALHOST
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+
+    assert!(
+        response
+            .to_lowercase()
+            .contains(&format!("content-length: {index_len}\r\n")),
+        "{response}"
+    );
+}
+
+#[test]
+fn reload_method_cdp() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    let html = subject.http_get("/", &[]).unwrap();
+    let html = String::from_utf8(html.body).unwrap();
+    assert!(!html.contains("<script>"), "{html}");
+    let events = subject.http_get("/__conveyorbelt/reload", &[]).unwrap();
+    assert_eq!(events.status, 404);
+}
+
+#[test]
+fn reload_method_websocket_alias() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--reload-method", "websocket"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let html = subject.http_get("/", &[]).unwrap();
+    let html = String::from_utf8(html.body).unwrap();
+    assert!(html.contains("<script>"), "{html}");
+}
+
+#[test]
+fn reload_method_both() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--reload-method", "both"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let html = subject.http_get("/", &[]).unwrap();
+    let html = String::from_utf8(html.body).unwrap();
+    assert!(html.contains("<script>"), "{html}");
+    fixture.write_source_file("trigger", "").unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("server: reload triggered")),
+        "{lines:#?}"
+    );
+}
+
+#[tokio::test]
+async fn start_url_query() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--start-url-query", "debug=1&build={build}"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
+    let root = subject.url("/").unwrap();
+
+    let page_url = async || {
+        let pages = browser
+            .execute(GetTargetsParams { filter: None })
+            .await
+            .unwrap();
+
+        let [page] = pages.target_infos.as_slice() else {
+            panic!("pages length is not 1");
+        };
+
+        page.url.clone()
+    };
+
+    assert_eq!(page_url().await, format!("{root}?debug=1&build=0"));
+    fixture.write_source_file("trigger", "").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    assert_eq!(page_url().await, format!("{root}?debug=1&build=1"));
+}
+
+#[tokio::test]
+async fn browser_first_navigation_deferred_until_initial_build_succeeds() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            sleep 2sec
+            if ($env.SERVE_PATH | path exists) {
+                rm --recursive $env.SERVE_PATH
+            }
+            cp --recursive $env.SRC_PATH $env.SERVE_PATH
+        "})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    let mut browser = subject.connect_to_browser().await.unwrap();
+    browser.fetch_targets().await.unwrap();
+
+    let page = loop {
+        if let Some(page) = browser.pages().await.unwrap().into_iter().next() {
+            break page;
+        }
+    };
+
+    let status: u16 = page
+        .evaluate(r#"performance.getEntriesByType("navigation")[0].responseStatus"#)
+        .await
+        .unwrap()
+        .into_value()
+        .unwrap();
+
+    assert_eq!(status, 200);
+}
+
+#[test]
+fn no_browser() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .subject_path_env_var
+        .remove(env!("CHROMIUM_BIN_PATH"));
+
+    fixture.add_subject_args(["--no-browser"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let state_for_testing = subject.state_for_testing().unwrap();
+    assert_eq!(state_for_testing.browser_pid, None);
+    assert_eq!(state_for_testing.browser_debugging_address, None);
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+    subject.signal(SIGTERM).unwrap();
+    subject.wait_stderr_contains("exit: 0").unwrap();
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(0));
+}
+
+#[test]
+fn serve_dir() {
+    // outside of the repository, so that it need not be ignored by git
+    let serve_dir = tempfile::TempDir::new().unwrap();
+    let serve_path = serve_dir.path().join("site");
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--serve-dir", serve_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    subject.signal(SIGTERM).unwrap();
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(0));
+    let index = fs::read_to_string(serve_path.join("index.html")).unwrap();
+    assert!(index.contains("Default fixture title"), "{index}");
+}
+
+#[test]
+fn no_initial_build() {
+    // outside of the repository, so that it need not be ignored by git
+    let serve_dir = tempfile::TempDir::new().unwrap();
+    let serve_path = serve_dir.path().join("site");
+    fs::create_dir(&serve_path).unwrap();
+    fs::write(serve_path.join("index.html"), "previous output").unwrap();
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args([
+        "--serve-dir",
+        serve_path.to_str().unwrap(),
+        "--no-initial-build",
+    ]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let lines = subject
+        .wait_stderr_lines_until("event: browser: spawned")
+        .unwrap();
+    assert!(
+        !lines.iter().any(|line| line.contains("build: spawn")),
+        "{lines:#?}"
+    );
+    assert_eq!(
+        subject.state_for_testing().unwrap().last_build_exit_code,
+        None
+    );
+    let response = subject.http_get("/", &[]).unwrap();
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("previous output"), "{body}");
+    fixture.write_source_file("foo", "").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+
+    let index = fs::read_to_string(serve_path.join("index.html")).unwrap();
+    assert!(index.contains("Default fixture title"), "{index}");
+}
+
+#[test]
+fn serve_dir_not_ignored() {
+    let fixture = Fixture::init().unwrap();
+
+    let output = fixture
+        .run_subject([
+            fixture.build_command().as_os_str(),
+            "--serve-dir".as_ref(),
+            "dist".as_ref(),
+        ])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("is in the repository but not ignored by git"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn stdout_empty_outside_of_testing_mode() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.unset_testing_mode();
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            print 'build stdout line'
+            exit 1
+        "})
+        .unwrap();
+
+    let output = fixture
+        .run_subject([
+            fixture.build_command().as_os_str(),
+            "--exit-on-build-failure-count".as_ref(),
+            "1".as_ref(),
+        ])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line=build stdout line"), "{stderr}");
+    assert!(output.stdout.is_empty(), "{:?}", output.stdout);
+}
+
+#[test]
+fn serve_dir_permissions() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.set_build_command_nu("exit 0").unwrap();
+    fixture.add_subject_args(["--serve-dir-permissions", "750"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let serve_path = subject.state_for_testing().unwrap().serve_path;
+    let mode = fs::metadata(serve_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o750);
+}
+
+#[test]
+fn serve_dir_permissions_invalid() {
+    let fixture = Fixture::init().unwrap();
+
+    let output = fixture
+        .run_subject([
+            fixture.build_command().as_os_str(),
+            "--serve-dir-permissions".as_ref(),
+            "799".as_ref(),
+        ])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn browser_ws_url() {
+    let browser = FreshBrowser::spawn().await.unwrap();
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .subject_path_env_var
+        .remove(env!("CHROMIUM_BIN_PATH"));
+
+    fixture.add_subject_args(["--browser-ws-url", browser.websocket_address()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    assert_eq!(subject.state_for_testing().unwrap().browser_pid, None);
+
+    let pages = browser
+        .execute(GetTargetsParams { filter: None })
+        .await
+        .unwrap();
+
+    let expected = subject.url("/").unwrap();
+
+    assert!(
+        pages.target_infos.iter().any(|page| page.url == expected),
+        "{:?}",
+        pages.target_infos
+    );
+}
+
+#[test]
+fn build_log_api() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            print -e 'stderr line'
+            sleep 1sec
+            print 'first line'
+            print 'second line'
+        "})
+        .unwrap();
+
+    fixture.add_subject_args(["--build-log-api"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+    let response = subject.http_get("/__conveyorbelt/build-log", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("stderr line\n"), "{body:?}");
+    assert!(body.ends_with("first line\nsecond line\n"), "{body:?}");
+
+    let response = subject
+        .http_get("/__conveyorbelt/build-log?tail=1", &[])
+        .unwrap();
+
+    assert_eq!(String::from_utf8(response.body).unwrap(), "second line\n");
+
+    let response = subject
+        .http_get("/__conveyorbelt/build-log?tail=x", &[])
+        .unwrap();
+
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn build_log_api_disabled_by_default() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/__conveyorbelt/build-log", &[]).unwrap();
+    assert_eq!(response.status, 404);
+}
+
+#[test]
+fn build_timeout() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            if ($env.SRC_PATH | path join hang | path exists) {
+                sleep 1hr
+            }
+            if ($env.SERVE_PATH | path exists) {
+                rm --recursive $env.SERVE_PATH
+            }
+            cp --recursive $env.SRC_PATH $env.SERVE_PATH
+        "})
+        .unwrap();
+
+    fixture.add_subject_args(["--build-timeout", "1"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("hang", "").unwrap();
+    subject
+        .wait_stderr_contains("build: timed out after 1s")
+        .unwrap();
+    subject
+        .wait_stderr_contains("build: exited with None")
+        .unwrap();
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    assert!(subject.try_wait().unwrap().is_none());
+}
+
+#[test]
+fn exit_on_build_failure_count() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(indoc! {"
+            if ($env.SRC_PATH | path join fail | path exists) {
+                print --stderr failing
+                exit 1
+            }
+            if ($env.SERVE_PATH | path exists) {
+                rm --recursive $env.SERVE_PATH
+            }
+            cp --recursive $env.SRC_PATH $env.SERVE_PATH
+        "})
+        .unwrap();
+
+    fixture.add_subject_args(["--exit-on-build-failure-count", "2"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("fail", "").unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(1)")
+        .unwrap();
+
+    assert!(subject.try_wait().unwrap().is_none());
+    fixture.write_source_file("foo", "").unwrap();
+
+    let line = subject
+        .wait_stderr_contains("build failed 2 consecutive times")
+        .unwrap();
+
+    assert!(line.contains("output of the last:"), "{line}");
+    subject.wait_stderr_contains("stderr: failing").unwrap();
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(1));
+}
+
+#[test]
+fn single_instance() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--single-instance"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    let output = fixture
+        .run_subject([
+            fixture.build_command().as_os_str(),
+            "--single-instance".as_ref(),
+        ])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let expected = format!("another instance (PID {}) is already running", subject.id());
+    assert!(stderr.contains(&expected), "{stderr}");
+}
+
+#[test]
+fn changed_paths() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            if ($env.{SERVE_PATH} | path exists) {{
+                rm --recursive $env.{SERVE_PATH}
+            }}
+            cp --recursive $env.SRC_PATH $env.{SERVE_PATH}
+            let changed_paths = try {{ $env.{CHANGED_PATHS} }} catch {{ "unset" }}
+            $changed_paths | save ($env.{SERVE_PATH} | path join changed-paths)
+        "#})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/changed-paths", &[]).unwrap();
+    assert_eq!(response.body, b"unset");
+    fixture.write_source_file("foo", "").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    let response = subject.http_get("/changed-paths", &[]).unwrap();
+    let changed_paths = String::from_utf8(response.body).unwrap();
+    let expected = fixture.src_path().join("foo");
+
+    assert!(
+        changed_paths
+            .lines()
+            .any(|line| Path::new(line) == expected),
+        "{changed_paths:?}"
+    );
+}
+
+#[test]
+fn changed_paths_of_a_burst() {
+    // outside of the repository, so that appending to it does not trigger a build
+    let record_dir = tempfile::TempDir::new().unwrap();
+    let record_path = record_dir.path().join("record");
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            if ($env.{SERVE_PATH} | path exists) {{
+                rm --recursive $env.{SERVE_PATH}
+            }}
+            cp --recursive $env.SRC_PATH $env.{SERVE_PATH}
+            let changed_paths = try {{ $env.{CHANGED_PATHS} }} catch {{ "" }}
+            $"($changed_paths)\n" | save --append {record_path:?}
+        "#})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let burst = (0..20).map(|i| format!("burst-{i}")).collect::<Vec<_>>();
+
+    for file_name in &burst {
+        fixture.write_source_file(file_name, "").unwrap();
+    }
+
+    thread::sleep(Duration::from_secs(1));
+    fixture.write_source_file("sentinel", "").unwrap();
+
+    loop {
+        subject
+            .wait_stderr_contains("build: exited with Some(0)")
+            .unwrap();
+
+        if fs::read_to_string(&record_path)
+            .unwrap()
+            .contains("sentinel")
+        {
+            break;
+        }
+    }
+
+    let record = fs::read_to_string(&record_path).unwrap();
+
+    for file_name in &burst {
+        let expected = fixture.src_path().join(file_name);
+
+        assert!(
+            record.lines().any(|line| Path::new(line) == expected),
+            "{file_name} missing from {record}"
+        );
+    }
+}
+
+#[test]
+fn burst_of_changes_coalesced() {
+    // outside of the repository, so that appending to it does not trigger a build
+    let record_dir = tempfile::TempDir::new().unwrap();
+    let record_path = record_dir.path().join("record");
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            "invoked\n" | save --append {record_path:?}
+            sleep 500ms
+        "#})
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    for i in 0..100 {
+        fixture.write_source_file(format!("burst-{i}"), "").unwrap();
+    }
+
+    thread::sleep(Duration::from_secs(3));
+    let invocations = fs::read_to_string(&record_path).unwrap().lines().count();
+    // including the initial build
+    assert!((2..=10).contains(&invocations), "{invocations}");
+}
+
+#[test]
+fn serve_path_style_relative() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            if ($env.{SERVE_PATH} | path type) != "dir" or ($env.{SERVE_PATH} | str starts-with "/") {{
+                exit 1
+            }}
+            cp ($env.SRC_PATH | path join index.html) $env.{SERVE_PATH}
+        "#})
+        .unwrap();
+
+    fixture.add_subject_args(["--serve-path-style", "relative"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Default fixture title"), "{body}");
+}
+
+#[test]
+fn browser_cdp_log() {
+    // outside of the repository, so that writing to it does not trigger builds
+    let log_dir = tempfile::TempDir::new().unwrap();
+    let log_path = log_dir.path().join("cdp.log");
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--browser-cdp-log", log_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let log = fs::read_to_string(log_path).unwrap();
+    assert!(log.contains("Target.getTargets"), "{log}");
+    assert!(log.contains("Target.createTarget"), "{log}");
+}
+
+#[test]
+fn compression() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--compression"]);
+
+    fixture
+        .write_source_file(
+            "app.js",
+            "console.log('compressible');\n".repeat(1000).as_str(),
+        )
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    let response = subject
+        .http_get("/app.js", &[("Accept-Encoding", "gzip")])
+        .unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+}
+
+#[test]
+fn no_compression_by_default() {
+    let fixture = Fixture::init().unwrap();
+
+    fixture
+        .write_source_file(
+            "app.js",
+            "console.log('compressible');\n".repeat(1000).as_str(),
+        )
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    let response = subject
+        .http_get("/app.js", &[("Accept-Encoding", "gzip")])
+        .unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+}
+
+#[test]
+fn page404_status() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--page404-status", "410"]);
+
+    fixture
+        .write_source_file("404.html", HtmlPage::new().title("Ain't found"))
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/nope.html", &[]).unwrap();
+    assert_eq!(response.status, 410);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Ain't found"), "{body}");
+}
+
+#[test]
+fn page50x() {
+    let fixture = Fixture::init().unwrap();
+
+    fixture
+        .write_source_file("50x.html", HtmlPage::new().title("Server trouble"))
+        .unwrap();
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let serve_path = subject.state_for_testing().unwrap().serve_path;
+    // which cannot be opened, resulting in an internal server error
+    let _socket = UnixListener::bind(serve_path.join("socket")).unwrap();
+    let response = subject.http_get("/socket", &[]).unwrap();
+    assert_eq!(response.status, 500);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Server trouble"), "{body}");
+}
+
+#[test]
+fn page50x_provided() {
+    let mut fixture = Fixture::init().unwrap();
+    let page = tempfile::NamedTempFile::new().unwrap();
+    fs::write(
+        page.path(),
+        HtmlPage::new().title("Branded trouble").to_string(),
+    )
+    .unwrap();
+    fixture.add_subject_args(["--page50x", page.path().to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let serve_path = subject.state_for_testing().unwrap().serve_path;
+    let _socket = UnixListener::bind(serve_path.join("socket")).unwrap();
+    let response = subject.http_get("/socket", &[]).unwrap();
+    assert_eq!(response.status, 500);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Branded trouble"), "{body}");
+}
+
+#[test]
+fn maintenance_mode() {
+    let mut fixture = Fixture::init().unwrap();
+    let page = tempfile::NamedTempFile::new().unwrap();
+    fs::write(page.path(), "back soon").unwrap();
+
+    fixture.add_subject_args([
+        "--maintenance-mode",
+        "--maintenance-file",
+        page.path().to_str().unwrap(),
+        "--maintenance-status",
+        "418",
+    ]);
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 418);
+    assert_eq!(String::from_utf8(response.body).unwrap(), "back soon");
+}
+
+#[test]
+fn maintenance_during_build() {
+    let mut fixture = Fixture::init().unwrap();
+    // outside of the repository, so that creating it does not trigger a build
+    let release = tempfile::TempDir::new().unwrap();
+    let release_path = release.path().join("release");
+    let release_path = release_path.to_str().unwrap();
+    let src_path = fixture.src_path();
+    let src_path = src_path.to_str().unwrap();
+
+    fixture
+        .set_build_command_bash(formatdoc! {r#"
+            if [ -e {src_path}/trigger ]; then
+                while [ ! -e {release_path} ]; do sleep 0.1; done
+            fi
+            echo built > "${SERVE_PATH}/index.html"
+        "#})
+        .unwrap();
+
+    fixture.add_subject_args(["--maintenance-during-build"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    assert_eq!(subject.http_get("/", &[]).unwrap().status, 200);
+    fixture.write_source_file("trigger", "").unwrap();
+    subject.wait_stderr_contains("build: spawn pid").unwrap();
+    let response = subject.http_get("/", &[]).unwrap();
+    assert_eq!(response.status, 503);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("maintenance mode"), "{body}");
+    fs::write(release_path, "").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    assert_eq!(subject.http_get("/", &[]).unwrap().status, 200);
+}
+
+#[test]
+fn spa() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--spa"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let response = subject.http_get("/users/42", &[]).unwrap();
+    assert_eq!(response.status, 200);
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Default fixture title"), "{body}");
+    let response = subject.http_get("/missing.js", &[]).unwrap();
+    assert_eq!(response.status, 404);
+}
+
+#[test]
+fn watch_batch_window() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--watch-batch-window", "1000"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let file_names = ["foo", "bar", "baz"];
+
+    for file_name in file_names {
+        fixture.write_source_file(file_name, "").unwrap();
+    }
+
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    let builds = lines
+        .iter()
+        .filter(|line| line.contains("event: build: spawn pid"))
+        .count();
+
+    assert_eq!(builds, 1, "{lines:#?}");
+
+    let batch = lines
+        .iter()
+        .find(|line| line.contains("batch of "))
+        .unwrap();
+
+    for file_name in file_names {
+        let path = fixture.src_path().join(file_name);
+        assert!(batch.contains(&format!("{path:?}")), "{batch}");
+    }
+}
+
+#[test]
+fn debounce() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--debounce", "1000"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    for file_name in ["foo", "bar", "baz"] {
+        fixture.write_source_file(file_name, "").unwrap();
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    let builds = lines
+        .iter()
+        .filter(|line| line.contains("event: build: spawn pid"))
+        .count();
+
+    assert_eq!(builds, 1, "{lines:#?}");
+}
+
+#[tokio::test]
+async fn open_extra() {
+    let extra_url = "data:text/plain,extra";
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--open-extra", extra_url]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
+
+    let pages = browser
+        .execute(GetTargetsParams { filter: None })
+        .await
+        .unwrap();
+
+    let mut urls = pages
+        .target_infos
+        .iter()
+        .map(|page| page.url.as_str())
+        .collect::<Vec<_>>();
+
+    urls.sort_unstable();
+    let root = subject.url("/").unwrap();
+    let mut expected = vec![root.as_str(), extra_url];
+    expected.sort_unstable();
+    assert_eq!(urls, expected);
+}
+
+#[test]
+fn external_build() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.set_build_external();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let serve_path = subject.state_for_testing().unwrap().serve_path;
+
+    fs::write(
+        serve_path.join("index.html"),
+        HtmlPage::new().title("Externally built").to_string(),
+    )
+    .unwrap();
+
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    let response = subject.http_get("/", &[]).unwrap();
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Externally built"), "{body}");
+}
+
+#[test]
+fn report_unused_assets() {
+    let mut fixture = Fixture::init().unwrap();
+
+    for file_name in ["used.js", "unused.js", "unused.css"] {
+        fixture.write_source_file(file_name, "").unwrap();
+    }
+
+    fixture.add_subject_args(["--report-unused-assets"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    subject.http_get("/used.js", &[]).unwrap();
+    subject.signal(SIGTERM).unwrap();
+    let line = subject.wait_stderr_contains("unused assets: ").unwrap();
+    assert!(line.contains("\"unused.js\""), "{line}");
+    assert!(line.contains("\"unused.css\""), "{line}");
+    assert!(!line.contains("\"used.js\""), "{line}");
+    assert!(!line.contains("\"index.html\""), "{line}");
+}
+
+#[test]
+fn downloads_dir() {
+    // outside of the repository, so that downloading does not trigger builds
+    let downloads_dir = tempfile::TempDir::new().unwrap();
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .write_source_file(
+            "index.html",
+            indoc! {r#"
+                <!DOCTYPE html>
+                <html>
+                <body>
+                <script>
+                    const anchor = document.createElement("a");
+                    anchor.href = "/report.csv";
+                    anchor.download = "report.csv";
+                    document.body.append(anchor);
+                    anchor.click();
+                </script>
+                </body>
+                </html>
+            "#},
+        )
+        .unwrap();
+
+    fixture
+        .write_source_file("report.csv", "a,b\n1,2\n")
+        .unwrap();
+    fixture.add_subject_args(["--downloads-dir", downloads_dir.path().to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let download_path = downloads_dir.path().join("report.csv");
+
+    for _ in 0..100 {
+        if fs::exists(&download_path).unwrap() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    assert_eq!(fs::read_to_string(download_path).unwrap(), "a,b\n1,2\n");
+}
+
+#[test]
+fn reload_only_on_output_change() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--reload-only-on-output-change"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    fixture
+        .write_source_file("index.html", HtmlPage::new().title("Default fixture title"))
+        .unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build output unchanged; not reloading")
+        .unwrap();
+
+    assert!(
+        !lines.iter().any(|line| line.contains("browser: reload")),
+        "{lines:#?}"
+    );
+
+    fixture
+        .write_source_file("index.html", HtmlPage::new().title("Changed title"))
+        .unwrap();
+
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+}
+
+#[tokio::test]
+async fn block_url() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .write_source_file(
+            "index.html",
+            indoc! {r#"
+                <!DOCTYPE html>
+                <html>
+                <head><link rel="icon" href="data:,"></head>
+                <body>
+                <script>
+                    const outcome = (path) =>
+                        fetch(path).then(() => "fetched", () => "failed");
+                    window.outcomes = Promise.all([outcome("/blocked.txt"), outcome("/allowed.txt")]);
+                </script>
+                </body>
+                </html>
+            "#},
+        )
+        .unwrap();
+
+    for file_name in ["blocked.txt", "allowed.txt"] {
+        fixture.write_source_file(file_name, "").unwrap();
+    }
+
+    fixture.add_subject_args(["--block-url", "*://*:*/blocked.txt"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let mut browser = subject.connect_to_browser().await.unwrap();
+    browser.fetch_targets().await.unwrap();
+
+    let page = loop {
+        if let Some(page) = browser.pages().await.unwrap().into_iter().next() {
+            break page;
+        }
+    };
+
+    let outcomes: Vec<String> = loop {
+        if let Ok(result) = page.evaluate("window.outcomes").await
+            && let Ok(outcomes) = result.into_value()
+        {
+            break outcomes;
+        }
+    };
+
+    assert_eq!(outcomes, ["failed", "fetched"]);
+}
+
+#[test]
+fn screenshot_dir() {
+    // outside of the repository, so that writing to it does not trigger a build
+    let screenshot_dir = tempfile::TempDir::new().unwrap();
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--screenshot-dir", screenshot_dir.path().to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+
+    let mut file_names = fs::read_dir(screenshot_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+
+    file_names.sort();
+
+    let [initial, reloaded] = file_names.as_slice() else {
+        panic!("{file_names:?}");
+    };
+
+    assert!(initial.ends_with("-build-0.png"), "{initial}");
+    assert!(reloaded.ends_with("-build-1.png"), "{reloaded}");
+
+    for file_name in [initial, reloaded] {
+        let png = fs::read(screenshot_dir.path().join(file_name)).unwrap();
+        assert!(png.starts_with(b"\x89PNG"), "{file_name}");
+    }
+}
+
+#[tokio::test]
+async fn har() {
+    // outside of the repository, so that writing it does not trigger a build
+    let har_dir = tempfile::TempDir::new().unwrap();
+    let har_path = har_dir.path().join("session.har");
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .write_source_file(
+            "index.html",
+            indoc! {r#"
+                <!DOCTYPE html>
+                <html>
+                <head><link rel="icon" href="data:,"></head>
+                <body>
+                <script>
+                    window.statuses = Promise.all(
+                        ["/found.txt", "/missing.txt"].map((path) =>
+                            // the body is read, so that loading has finished
+                            fetch(path).then(async (response) => {
+                                await response.text();
+                                return response.status;
+                            }),
+                        ),
+                    );
+                </script>
+                </body>
+                </html>
+            "#},
+        )
+        .unwrap();
+
+    fixture.write_source_file("found.txt", "found").unwrap();
+    fixture.add_subject_args(["--har", har_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let mut browser = subject.connect_to_browser().await.unwrap();
+    browser.fetch_targets().await.unwrap();
+
+    let page = loop {
+        if let Some(page) = browser.pages().await.unwrap().into_iter().next() {
+            break page;
+        }
+    };
+
+    let statuses: Vec<u16> = loop {
+        if let Ok(result) = page.evaluate("window.statuses").await
+            && let Ok(statuses) = result.into_value()
+        {
+            break statuses;
+        }
+    };
+
+    assert_eq!(statuses, [200, 404]);
+    let url = subject.url("/").unwrap();
+    subject.kill_wait(SIGTERM).unwrap();
+    let har: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&har_path).unwrap()).unwrap();
+
+    let entries = har["log"]["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        // excluding, e.g., the favicon's data URL
+        .filter(|entry| entry["request"]["url"].as_str().unwrap().starts_with(&url))
+        .map(|entry| {
+            (
+                entry["request"]["url"].as_str().unwrap().to_owned(),
+                entry["response"]["status"].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        entries,
+        [
+            (url.clone(), 200),
+            (format!("{url}found.txt"), 200),
+            (format!("{url}missing.txt"), 404),
+        ],
+        "{har:#}"
+    );
+}
+
+#[test]
+fn control_socket_rebuild() {
+    // outside of the repository, so that creating the socket does not trigger a build
+    let socket_dir = tempfile::TempDir::new().unwrap();
+    let socket_path = socket_dir.path().join("control.sock");
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--control-socket", socket_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let mut stream = std::os::unix::net::UnixStream::connect(&socket_path).unwrap();
+    stream.write_all(b"rebuild\n").unwrap();
+    let mut response = String::new();
+    BufReader::new(&stream).read_line(&mut response).unwrap();
+    assert_eq!(response, "ok\n");
+    subject
+        .wait_stderr_contains("event: control: rebuild requested")
+        .unwrap();
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    subject.signal(SIGTERM).unwrap();
+    subject.wait().unwrap();
+    assert!(!fs::exists(&socket_path).unwrap());
+}
+
+#[test]
+fn state_socket() {
+    // outside of the repository, so that creating the socket does not trigger a build
+    let socket_dir = tempfile::TempDir::new().unwrap();
+    let socket_path = socket_dir.path().join("state.sock");
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--state-socket", socket_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let expected = subject.state_for_testing().unwrap();
+    let stream = std::os::unix::net::UnixStream::connect(&socket_path).unwrap();
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    let state: StateForTesting = serde_json::from_str(&line).unwrap();
+    assert_eq!(state.serve_port, expected.serve_port);
+    assert_eq!(state.serve_path, expected.serve_path);
+    assert_eq!(state.browser_pid, expected.browser_pid);
+    assert_eq!(state.last_build_exit_code, Some(0));
+    subject.signal(SIGTERM).unwrap();
+    subject.wait().unwrap();
+    assert!(!fs::exists(&socket_path).unwrap());
+}
+
+#[test]
+fn build_arg() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_bash(r#"echo "args: $#: $1|$2""#)
+        .unwrap();
+
+    fixture.add_subject_args(["--build-arg", "--release", "--build-arg", "two words"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let expected = "line=args: 2: --release|two words";
+    subject.wait_stderr_contains(expected).unwrap();
+    fixture.write_source_file("foo", "").unwrap();
+    subject.wait_stderr_contains(expected).unwrap();
+}
+
+#[test]
+fn shell() {
+    let fixture = Fixture::init().unwrap();
+
+    let output = fixture
+        .run_subject(["--shell", r#"echo "serve path: $SERVE_PATH""#, "--check"])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line=serve path: /"), "{stderr}");
+}
+
+#[test]
+fn shell_and_build_command() {
+    let fixture = Fixture::init().unwrap();
+
+    let output = fixture
+        .run_subject([
+            fixture.build_command().as_os_str(),
+            "--shell".as_ref(),
+            "true".as_ref(),
+        ])
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn build_cwd() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture
+        .set_build_command_bash(r#"echo "pwd: $PWD""#)
+        .unwrap();
+    fixture.add_subject_args(["--build-cwd", "src"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let src_path = fixture.src_path().canonicalize().unwrap();
+
+    subject
+        .wait_stderr_contains(format!("line=pwd: {}", src_path.display()))
+        .unwrap();
+}
+
+#[test]
+fn build_env() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_bash(r#"echo "env: $FOO|${SRC_PATH:+inherited}""#)
+        .unwrap();
+
+    fixture.add_subject_args(["--build-env", "FOO=bar=baz"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("line=env: bar=baz|inherited")
+        .unwrap();
+}
+
+#[test]
+fn build_clean_env() {
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture
+        .set_build_command_bash(formatdoc! {r#"
+            echo "env: $FOO|${{SRC_PATH:+inherited}}|${{{SERVE_PATH}:+set}}"
+        "#})
+        .unwrap();
+
+    fixture.add_subject_args(["--build-env", "FOO=bar", "--build-clean-env"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_stderr_contains("line=env: bar||set").unwrap();
+}
+
+#[test]
+fn build_retries() {
+    let mut fixture = Fixture::init().unwrap();
+    // outside of the repository, so that writing to it does not trigger a build
+    let attempts = tempfile::NamedTempFile::new().unwrap();
+    let attempts_path = attempts.path().to_str().unwrap();
+
+    fixture
+        .set_build_command_bash(formatdoc! {r#"
+            echo attempt >> {attempts_path}
+            [ "$(wc -l < {attempts_path})" -ge 3 ]
+        "#})
+        .unwrap();
+
+    fixture.add_subject_args(["--build-retries", "2", "--build-retry-delay", "10"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build: exited with Some(0)")
+        .unwrap();
+
+    assert!(lines.iter().any(|line| line.contains("retry 2 of 2")));
+}
+
+#[test]
+fn build_retry_cancelled_on_change() {
+    let mut fixture = Fixture::init().unwrap();
+    let bad_path = fixture.src_path().join("bad");
+    let bad_path_str = bad_path.to_str().unwrap();
+
+    fixture
+        .set_build_command_bash(format!("[ ! -e {bad_path_str} ]"))
+        .unwrap();
+
+    fixture.add_subject_args(["--build-retries", "1", "--build-retry-delay", "60000"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("bad", "").unwrap();
+    subject.wait_stderr_contains("retry 1 of 1").unwrap();
+    fs::remove_file(&bad_path).unwrap();
+
+    subject
+        .wait_stderr_contains("cancelling the pending retry")
+        .unwrap();
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+}
+
+#[test]
+fn build_retries_exhausted() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.set_build_command_bash("exit 3").unwrap();
+    fixture.add_subject_args(["--build-retries", "1", "--build-retry-delay", "10"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build: exited with Some(3)")
+        .unwrap();
+
+    let retries = lines.iter().filter(|line| line.contains("retry")).count();
+    assert_eq!(retries, 1);
+}
+
+#[test]
+fn build_env_invalid() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--build-env", "FOO"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    subject
+        .wait_stderr_contains("not of the form `<key>=<value>`")
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(2));
+}
+
+#[test]
+fn on_change() {
+    // outside of the repository, so that appending to it does not trigger a build
+    let record_dir = tempfile::TempDir::new().unwrap();
+    let record_path = record_dir.path().join("record");
+
+    let on_change = script::Script::new(
+        env!("NU_EXECUTABLE"),
+        formatdoc! {"
+            $env.{CHANGED_PATHS} | save --append {record_path:?}
+        "},
+    )
+    .into_executable()
+    .unwrap();
+
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--on-change", on_change.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    for file_name in ["foo", "bar"] {
+        fixture.write_source_file(file_name, "").unwrap();
+
+        let lines = subject
+            .wait_stderr_lines_until("build: exited with Some(0)")
+            .unwrap();
+
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.contains("on-change command exited with Some(0)")),
+            "{lines:#?}"
+        );
+    }
+
+    let record = fs::read_to_string(record_path).unwrap();
+
+    for file_name in ["foo", "bar"] {
+        assert!(record.contains(file_name), "{record}");
+    }
+}
+
+#[test]
+fn on_change_required() {
+    let on_change = script::Script::new(env!("NU_EXECUTABLE"), "exit 1")
+        .into_executable()
+        .unwrap();
+
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture.add_subject_args([
+        "--on-change",
+        on_change.to_str().unwrap(),
+        "--on-change-required",
+    ]);
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+    let lines = subject.wait_stderr_lines_until("build skipped").unwrap();
+
+    assert!(
+        !lines.iter().any(|line| line.contains("build: spawn pid")),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn on_change_required_restarted() {
+    let on_change = script::Script::new(
+        env!("NU_EXECUTABLE"),
+        formatdoc! {"
+            sleep 2sec
+            if not ($env.{CHANGED_PATHS} | str contains good) {{ exit 1 }}
+        "},
+    )
+    .into_executable()
+    .unwrap();
+
+    let mut fixture = Fixture::init().unwrap();
+
+    fixture.add_subject_args([
+        "--on-change",
+        on_change.to_str().unwrap(),
+        "--on-change-required",
+    ]);
+
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fixture.write_source_file("bad", "").unwrap();
+    subject.wait_stderr_contains("/bad\" create File").unwrap();
+    fixture.write_source_file("good", "").unwrap();
+    let lines = subject.wait_stderr_lines_until("build skipped").unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("/good\" create File")),
+        "{lines:#?}"
+    );
+
+    subject
+        .wait_stderr_contains("build: exited with Some(0)")
+        .unwrap();
+}
+
+#[test]
+fn sighup_restarts() {
+    let fixture = Fixture::init().unwrap();
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    fs::write(fixture.root().join(".conveyorbeltignore"), "/src/ignored\n").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    subject.signal(SIGHUP).unwrap();
+    subject
+        .wait_stderr_contains("restarting, re-resolving the project root")
+        .unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("browser: reloaded")
+        .unwrap();
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("event: build: spawn pid")),
+        "{lines:#?}"
+    );
+
+    fixture.write_source_file("ignored", "").unwrap();
+    fixture.write_source_file("trigger", "").unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build: exited with Some(0)")
+        .unwrap();
+
+    let ignored_path = fixture.src_path().join("ignored");
+
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains(&format!("{ignored_path:?} (git ignored)"))),
+        "{lines:#?}"
+    );
+}
+
+#[test]
+fn manifest() {
+    // outside of the repository, so that writing it does not trigger a build
+    let manifest_dir = tempfile::TempDir::new().unwrap();
+    let manifest_path = manifest_dir.path().join("manifest.json");
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--manifest", manifest_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let serve_path = subject.state_for_testing().unwrap().serve_path;
+
+    let read_manifest = || -> serde_json::Map<String, serde_json::Value> {
+        serde_json::from_str(&fs::read_to_string(&manifest_path).unwrap()).unwrap()
+    };
+
+    let manifest = read_manifest();
+    let index_size = fs::metadata(serve_path.join("index.html")).unwrap().len();
+    assert_eq!(manifest["index.html"]["size"], index_size, "{manifest:#?}");
+    assert!(!manifest.contains_key("added.txt"), "{manifest:#?}");
+    let index_sha256 = manifest["index.html"]["sha256"].clone();
+    assert_eq!(index_sha256.as_str().unwrap().len(), 64, "{manifest:#?}");
+    fixture.write_source_file("added.txt", "added").unwrap();
+    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    let manifest = read_manifest();
+    assert_eq!(manifest["added.txt"]["size"], 5, "{manifest:#?}");
+    assert_eq!(
+        manifest["index.html"]["sha256"], index_sha256,
+        "{manifest:#?}"
+    );
+}
+
+#[test]
+fn url_file() {
+    // outside of the repository, so that writing it does not trigger a build
+    let url_dir = tempfile::TempDir::new().unwrap();
+    let url_path = url_dir.path().join("url");
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--url-file", url_path.to_str().unwrap()]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    let url = subject.url("/").unwrap();
+    assert_eq!(fs::read_to_string(&url_path).unwrap().trim_end(), url);
+    subject.signal(SIGTERM).unwrap();
+    subject.wait().unwrap();
+    assert!(!fs::exists(&url_path).unwrap());
+}
+
+#[test]
+fn isolate_build_tmp() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--isolate-build-tmp"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    fixture
+        .set_build_command_nu(indoc! {r#"
+            let marker = $env.TMPDIR | path join marker
+            if ($marker | path exists) {
+                print "stale marker"
+            }
+            "" | save $marker
+            print $"build tmp: ($env.TMPDIR)"
+        "#})
+        .unwrap();
+
+    let mut build_tmps = Vec::new();
+
+    for _ in 0..2 {
+        fixture.write_source_file("trigger", "").unwrap();
+
+        let lines = subject
+            .wait_stderr_lines_until("build: exited with Some(0)")
+            .unwrap();
+
+        assert!(
+            !lines.iter().any(|line| line.contains("stale marker")),
+            "{lines:#?}"
+        );
+
+        let build_tmp = lines
+            .iter()
+            .find_map(|line| line.split_once("build tmp: "))
+            .map(|(_, path)| path.trim().to_owned())
+            .unwrap();
+
+        assert!(!fs::exists(&build_tmp).unwrap(), "{build_tmp}");
+        build_tmps.push(build_tmp);
+    }
+
+    assert_ne!(build_tmps[0], build_tmps[1]);
+}
+
+#[test]
+fn stage_build_output() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--stage-build-output"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            rm --recursive $env.{SERVE_PATH}
+            mkdir $env.{SERVE_PATH}
+            "broken" | save ($env.{SERVE_PATH} | path join index.html)
+            exit 1
+        "#})
+        .unwrap();
+
+    fixture.write_source_file("trigger", "").unwrap();
+
+    subject
+        .wait_stderr_contains("build command exit status: 1")
+        .unwrap();
+
+    let response = subject.http_get("/", &[]).unwrap();
+    let body = String::from_utf8(response.body).unwrap();
+    assert!(body.contains("Default fixture title"), "{body}");
+    assert!(!body.contains("broken"), "{body}");
+}
+
+#[test]
+fn stage_build_output_from_empty() {
+    let mut fixture = Fixture::init().unwrap();
+    let stale_path = fixture.src_path().join("stale");
+    let stale_path_str = stale_path.to_str().unwrap();
+    fixture.write_source_file("stale", "0").unwrap();
+
+    fixture
+        .set_build_command_nu(formatdoc! {r#"
+            "fresh" | save --force ($env.{SERVE_PATH} | path join index.html)
+            if (open {stale_path_str}) == "1" {{
+                "stale" | save --force ($env.{SERVE_PATH} | path join stale.html)
+            }}
+        "#})
+        .unwrap();
+
+    fixture.add_subject_args(["--stage-build-output"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+
+    // the third build is staged in the output of the first, once exchanged twice
+    for content in ["1", "0", "0 "] {
+        fixture.write_source_file("stale", content).unwrap();
+
+        subject
+            .wait_stderr_contains("build: exited with Some(0)")
+            .unwrap();
+    }
+
+    let response = subject.http_get("/stale.html", &[]).unwrap();
+    assert_eq!(response.status, 404);
+}
+
+#[tokio::test]
+async fn stream_test() {
+    use hyper::body::HttpBody as _;
+
+    let streamed = "0123456789".repeat(20);
+    let mut fixture = Fixture::init().unwrap();
 
     fixture
-        .set_build_command_nu(format!("touch {serve_path_str}/foo-indicator"))
+        .write_source_file("stream.txt", streamed.as_str())
         .unwrap();
 
-    fixture.write_source_file("foo", "no trigger").unwrap();
+    fixture.add_subject_args(["--stream-test", "--stream-test-delay", "10"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+    subject.wait_browser_spawned().unwrap();
+    let port = subject.state_for_testing().unwrap().serve_port;
+    let path = "/__conveyorbelt/stream?path=stream.txt";
 
-    subject
-        .wait_stderr_contains("/foo\" (git ignored) modify")
+    let mut stream = std::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {}:{port}\r\nConnection: close\r\n\r\n",
+        Ipv4Addr::LOCALHOST
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+
+    assert!(
+        response
+            .to_lowercase()
+            .contains("transfer-encoding: chunked"),
+        "{response}"
+    );
+
+    let stream = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
         .unwrap();
 
-    fixture
-        .set_build_command_nu(format!("touch {serve_path_str}/bar-indicator"))
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake::<_, hyper::Body>(stream)
+        .await
         .unwrap();
 
-    fixture.write_source_file("bar", "trigger").unwrap();
-    subject.wait_stderr_contains("/bar\" create File").unwrap();
+    tokio::spawn(connection);
 
-    subject
-        .wait_stderr_contains("build: exited with Some(0)")
+    let request = hyper::Request::get(format!("http://{}:{port}{path}", Ipv4Addr::LOCALHOST))
+        .body(hyper::Body::empty())
         .unwrap();
 
-    assert!(!fs::exists(serve_path.join("foo-indicator")).unwrap());
-    assert!(fs::exists(serve_path.join("bar-indicator")).unwrap());
+    let mut body = sender.send_request(request).await.unwrap().into_body();
+    let mut content = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        content.extend_from_slice(&chunk.unwrap());
+    }
+
+    assert_eq!(content, streamed.into_bytes());
+    let trailers = body.trailers().await.unwrap().unwrap();
+    assert_eq!(trailers.get("x-conveyorbelt-chunks").unwrap(), "4");
 }
 
 #[test]
-fn build_not_executed_on_git_ignored_file_remove() {
+fn cors() {
     let mut fixture = Fixture::init().unwrap();
-    fixture.write_source_file(".gitignore", "/foo").unwrap();
-    fixture.write_source_file("foo", "create").unwrap();
+    fixture.add_subject_args(["--cors", "http://allowed.test", "--no-browser"]);
     let mut subject = fixture.spawn_subject().unwrap();
-    let serve_path = &subject.state_for_testing().unwrap().serve_path;
-    let serve_path_str = serve_path.to_str().unwrap();
 
-    fixture
-        .set_build_command_nu(format!("touch {serve_path_str}/foo-indicator"))
+    let allowed = subject
+        .http_get("/", &[("Origin", "http://allowed.test")])
         .unwrap();
 
-    fs::remove_file(fixture.src_path().join("foo")).unwrap();
+    assert_eq!(
+        allowed.header("access-control-allow-origin"),
+        Some("http://allowed.test")
+    );
 
-    subject
-        .wait_stderr_contains("/foo\" (git ignored) remove")
+    let other = subject
+        .http_get("/", &[("Origin", "http://other.test")])
         .unwrap();
 
-    fixture
-        .set_build_command_nu(format!("touch {serve_path_str}/bar-indicator"))
-        .unwrap();
+    assert_eq!(other.status, 403);
+    assert_eq!(other.header("access-control-allow-origin"), None);
+}
 
-    fixture.write_source_file("bar", "trigger").unwrap();
-    subject.wait_stderr_contains("/bar\" create File").unwrap();
+#[test]
+fn cors_invalid() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--cors", "allowed.test"]);
+    let mut subject = fixture.spawn_subject().unwrap();
 
     subject
-        .wait_stderr_contains("build: exited with Some(0)")
+        .wait_stderr_contains("neither `*` nor an origin of the form")
         .unwrap();
 
-    assert!(!fs::exists(serve_path.join("foo-indicator")).unwrap());
-    assert!(fs::exists(serve_path.join("bar-indicator")).unwrap());
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(2));
 }
 
 #[test]
-fn build_on_file_creation() {
-    let fixture = Fixture::init().unwrap();
+fn access_log() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--access-log", "--no-browser"]);
     let mut subject = fixture.spawn_subject().unwrap();
-    subject.wait_browser_spawned().unwrap();
-    fixture.write_source_file("new_file", "").unwrap();
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
-        .unwrap();
+    let response = subject.http_get("/missing.txt", &[]).unwrap();
+    assert_eq!(response.status, 404);
+    let line = subject.wait_stderr_contains("uri=/missing.txt").unwrap();
+    assert!(line.contains("method=GET"), "{line}");
+    assert!(line.contains("status=404"), "{line}");
 }
 
 #[test]
-fn build_on_file_change() {
-    let fixture = Fixture::init().unwrap();
+fn slow_request() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--slow-request-ms", "0", "--no-browser"]);
     let mut subject = fixture.spawn_subject().unwrap();
-    subject.wait_browser_spawned().unwrap();
-    fixture.write_source_file("file", "").unwrap();
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
-        .unwrap();
-    fixture.write_source_file("file", "").unwrap();
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
-        .unwrap();
+    subject.http_get("/missing.txt", &[]).unwrap();
+    let line = subject.wait_stderr_contains("slow request").unwrap();
+    assert!(line.contains("WARN"), "{line}");
+    assert!(line.contains("uri=/missing.txt"), "{line}");
+    assert!(line.contains("elapsed_ms="), "{line}");
 }
 
-#[test]
-fn build_on_file_removal() {
-    let fixture = Fixture::init().unwrap();
+#[tokio::test]
+async fn http2() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--http2", "--no-browser"]);
     let mut subject = fixture.spawn_subject().unwrap();
-    subject.wait_browser_spawned().unwrap();
-    fixture.write_source_file("file", "").unwrap();
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
-        .unwrap();
-    fs::remove_file(fixture.src_path().join("file")).unwrap();
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
+    let port = subject.state_for_testing().unwrap().serve_port;
+    assert!(subject.http_get("/", &[]).is_err());
+
+    let stream = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
         .unwrap();
-}
 
-#[test]
-fn browser_reloads_following_build() {
-    let fixture = Fixture::init().unwrap();
-    let mut subject = fixture.spawn_subject().unwrap();
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake::<_, hyper::Body>(stream)
+        .await
+        .unwrap();
 
-    subject.wait_browser_spawned().unwrap();
+    tokio::spawn(connection);
 
-    fixture.write_source_file("trigger", "").unwrap();
+    let request = hyper::Request::get(format!("http://{}:{port}/", Ipv4Addr::LOCALHOST))
+        .body(hyper::Body::empty())
+        .unwrap();
 
-    subject.wait_stderr_contains("browser: reloaded").unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
 }
 
 #[test]
-fn serve_dir_exists_after_termination() {
-    let fixture = Fixture::init().unwrap();
+fn http2_without_tls_requires_no_browser() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--http2"]);
     let mut subject = fixture.spawn_subject().unwrap();
-    let state_for_testing = subject.state_for_testing().unwrap();
-    subject.kill_wait(SIGTERM).unwrap();
-    assert!(std::fs::exists(state_for_testing.serve_path).unwrap())
+
+    subject
+        .wait_stderr_contains("--http2 without --tls serves only h2c")
+        .unwrap();
+
+    let status = subject.wait().unwrap();
+    assert_eq!(status.code(), Some(1));
 }
 
 #[tokio::test]
-async fn cdp_errors_are_reported() {
-    let fixture = Fixture::init().unwrap();
+async fn tls() {
+    let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args(["--tls"]);
     let mut subject = fixture.spawn_subject().unwrap();
-    subject.wait_browser_spawned().unwrap();
-    let browser_pid = subject.state_for_testing().unwrap().browser_pid;
-    let browser_pid = Pid::from_raw(browser_pid as i32);
-    kill(browser_pid, SIGTERM).unwrap();
+    let browser = subject.connect_to_browser().await.unwrap();
 
-    subject
-        .wait_stderr_contains("WS Connection error: Ws(Protocol(ResetWithoutClosingHandshake))")
+    let pages = browser
+        .execute(GetTargetsParams { filter: None })
+        .await
         .unwrap();
+
+    let [page] = pages.target_infos.as_slice() else {
+        panic!("pages length is not 1");
+    };
+
+    assert!(page.url.starts_with("https://"), "{}", page.url);
+    assert_eq!(page.title, "Default fixture title");
 }
 
 #[test]
-fn build_succeeds_while_being_terminated() {
+fn multiple_build_commands() {
+    let second = script::Script::new(
+        env!("NU_EXECUTABLE"),
+        indoc! {r#"
+            print --stderr "second build command"
+            if ($env.SRC_PATH | path join fail | path exists) {
+                exit 4
+            }
+        "#},
+    )
+    .into_executable()
+    .unwrap();
+
+    let third = script::Script::new(env!("NU_EXECUTABLE"), r#"print "third build command""#)
+        .into_executable()
+        .unwrap();
+
     let mut fixture = Fixture::init().unwrap();
+    fixture.add_subject_args([second.to_str().unwrap(), third.to_str().unwrap()]);
     let mut subject = fixture.spawn_subject().unwrap();
-    subject.wait_browser_spawned().unwrap();
 
-    fixture
-        .set_build_command_bash(formatdoc! {r#"
-            trap "exit 0" TERM
-            echo "trap is set"
-            while true; do
-                read -r _
-            done
-        "#})
+    let lines = subject
+        .wait_stderr_lines_until("third build command")
         .unwrap();
 
-    fixture.write_source_file("first trigger", "").unwrap();
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.contains("command=1") && line.contains("second build command")),
+        "{lines:#?}"
+    );
 
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
-        .unwrap();
+    subject.wait_browser_spawned().unwrap();
 
-    subject
-        .wait_stderr_contains("build: stdout: trap is set")
+    assert_eq!(
+        subject.state_for_testing().unwrap().last_build_exit_code,
+        Some(0)
+    );
+
+    fixture.write_source_file("fail", "").unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("build command exit status: 4")
         .unwrap();
 
-    fixture.set_build_command_nu("").unwrap();
+    assert!(
+        !lines
+            .iter()
+            .any(|line| line.contains("third build command")),
+        "{lines:#?}"
+    );
+
+    assert_eq!(
+        subject
+            .next_state_for_testing()
+            .unwrap()
+            .last_build_exit_code,
+        Some(4)
+    );
+}
 
-    fixture.write_source_file("second trigger", "").unwrap();
+#[test]
+fn quiet_browser() {
+    let browser_dir = tempfile::TempDir::new().unwrap();
+    let browser_path = browser_dir.path().join("chromium");
+
+    fs::write(
+        &browser_path,
+        script::Script::new(
+            env!("_BASH_EXECUTABLE"),
+            formatdoc! {r#"
+                echo "[1:1:0101/000000.000000:ERROR:bus.cc(408)] Failed to connect to the bus: Failed to connect to socket /run/dbus/system_bus_socket" >&2
+                echo "[1:1:0101/000000.000000:ERROR:viz_main_impl.cc(196)] Exiting GPU process due to errors during initialization" >&2
+                echo "[1:1:0101/000000.000000:ERROR:example.cc(1)] an arbitrary error" >&2
+                exec {} "$@"
+            "#, Path::new(env!("CHROMIUM_BIN_PATH")).join("chromium").display()},
+        )
+        .0,
+    )
+    .unwrap();
 
-    subject
-        .wait_stderr_contains("event: build: sent SIGTERM")
-        .unwrap();
+    fs::set_permissions(&browser_path, Permissions::from_mode(0o755)).unwrap();
 
-    subject
-        .wait_stderr_contains("event: build: exited with Some(0)")
-        .unwrap();
+    let mut fixture = Fixture::init().unwrap();
 
-    subject
-        .wait_stderr_contains("event: build: spawn pid ")
+    fixture
+        .subject_path_env_var
+        .remove(env!("CHROMIUM_BIN_PATH"));
+
+    fixture
+        .subject_path_env_var
+        .insert(browser_dir.path().to_str().unwrap().to_owned().leak());
+
+    fixture.add_subject_args(["--quiet-browser"]);
+    let mut subject = fixture.spawn_subject().unwrap();
+
+    let lines = subject
+        .wait_stderr_lines_until("an arbitrary error")
         .unwrap();
+
+    assert!(
+        !lines
+            .iter()
+            .any(|line| line.contains("Failed to connect to the bus")
+                || line.contains("Exiting GPU process")),
+        "{lines:#?}"
+    );
+
+    subject.wait_browser_spawned().unwrap();
 }
 
 mod kill_wait {
@@ -1053,8 +4206,9 @@ mod fresh_browser {
 
 mod fixture {
     use std::collections::BTreeSet;
+    use std::ffi::OsStr;
     use std::path::{Path, PathBuf};
-    use std::process::Stdio;
+    use std::process::{Output, Stdio};
     use std::sync::{Arc, Mutex};
     use std::{env, fs};
 
@@ -1073,6 +4227,11 @@ mod fixture {
         root: TempDir,
         build_command: ScriptFile,
         pub(crate) subject_path_env_var: BTreeSet<&'static str>,
+        subject_args: Vec<String>,
+        /// Whether the subject is spawned with `--external-build` instead of the build command
+        is_build_external: bool,
+        /// Whether the subject prints its state to stdout for the tests
+        is_testing_mode: bool,
     }
 
     impl Fixture {
@@ -1112,7 +4271,10 @@ mod fixture {
             let fixture = Self {
                 root,
                 subject_path_env_var,
+                subject_args: Vec::new(),
                 build_command,
+                is_build_external: false,
+                is_testing_mode: true,
             };
 
             fs::create_dir(fixture.src_path()).context("creating fixture source dir")?;
@@ -1130,6 +4292,21 @@ mod fixture {
             &self.build_command.0
         }
 
+        pub(crate) fn add_subject_args<S: Into<String>>(
+            &mut self,
+            args: impl IntoIterator<Item = S>,
+        ) {
+            self.subject_args.extend(args.into_iter().map(Into::into));
+        }
+
+        pub(crate) fn set_build_external(&mut self) {
+            self.is_build_external = true;
+        }
+
+        pub(crate) fn unset_testing_mode(&mut self) {
+            self.is_testing_mode = false;
+        }
+
         pub(crate) fn set_build_command_nu(
             &mut self,
             script: impl Into<String>,
@@ -1160,20 +4337,67 @@ mod fixture {
         }
 
         pub(crate) fn spawn_subject(&self) -> anyhow::Result<Subject> {
+            let mut command = self.subject_command()?;
+
+            if self.is_build_external {
+                command.arg("--external-build");
+            } else {
+                command.arg(self.build_command.as_os_str());
+            }
+
+            command
+                .args(&self.subject_args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+
+            let mut process = command.spawn().context("failed to spawn subject")?;
+            let stdout = process.stdout.take().context("taking subject stdout")?;
+            let stderr = Arc::new(Mutex::new(String::new()));
+            let stderr_clone = Arc::clone(&stderr);
+
+            process
+                .for_stderr_line(move |line| {
+                    eprintln!("subject stderr: {line}");
+                    let mut lock = stderr_clone.lock().unwrap();
+                    lock.push_str(line);
+                    lock.push('\n');
+                })
+                .context("handling subject stderr")?;
+
+            Ok(Subject::new(process, stdout, stderr))
+        }
+
+        pub(crate) fn run_subject<S: AsRef<OsStr>>(
+            &self,
+            args: impl IntoIterator<Item = S>,
+        ) -> anyhow::Result<Output> {
+            let mut command = self.subject_command()?;
+            command.args(args);
+            let output = command.output().context("failed to run subject")?;
+            eprintln!(
+                "subject stderr: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            Ok(output)
+        }
+
+        fn subject_command(&self) -> anyhow::Result<std::process::Command> {
             let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_conveyorbelt"));
 
             command
                 .current_dir(&self.root)
                 .env_clear()
                 .env("DISPLAY", Xvfb::DISPLAY)
-                .env(TESTING_MODE, "true")
                 .env("LOG_FILTER_VAR_NAME", env!("LOG_FILTER_VAR_NAME"))
                 .env(
                     "PATH",
                     std::env::join_paths(&self.subject_path_env_var).unwrap(),
                 )
-                .env("SRC_PATH", self.src_path())
-                .arg(self.build_command.as_os_str());
+                .env("SRC_PATH", self.src_path());
+
+            if self.is_testing_mode {
+                command.env(TESTING_MODE, "true");
+            }
 
             match env::var(env!("LOG_FILTER_VAR_NAME")) {
                 Ok(log_filter) => {
@@ -1185,22 +4409,7 @@ mod fixture {
                 }
             }
 
-            command.stdout(Stdio::piped()).stderr(Stdio::piped());
-
-            let mut process = command.spawn().context("failed to spawn subject")?;
-            let stderr = Arc::new(Mutex::new(String::new()));
-            let stderr_clone = Arc::clone(&stderr);
-
-            process
-                .for_stderr_line(move |line| {
-                    eprintln!("subject stderr: {line}");
-                    let mut lock = stderr_clone.lock().unwrap();
-                    lock.push_str(line);
-                    lock.push('\n');
-                })
-                .context("handling subject stderr")?;
-
-            Ok(Subject::new(process, stderr))
+            Ok(command)
         }
 
         pub(crate) fn src_path(&self) -> PathBuf {
@@ -1210,8 +4419,9 @@ mod fixture {
 
     mod subject {
         use std::{
-            io::BufRead as _,
+            io::{BufRead as _, BufReader, Lines},
             net::Ipv4Addr,
+            process::ChildStdout,
             sync::{Arc, Mutex},
         };
 
@@ -1219,21 +4429,31 @@ mod fixture {
         use chromiumoxide::Browser;
         use futures::StreamExt as _;
 
-        use crate::{common::StateForTesting, droppy_child::DroppyChild};
+        use crate::{
+            common::StateForTesting,
+            droppy_child::DroppyChild,
+            http_get::{HttpResponse, http_get},
+        };
 
         #[derive(Debug, derive_more::Deref, derive_more::DerefMut)]
         pub(crate) struct Subject {
             #[deref]
             #[deref_mut]
             process: DroppyChild,
+            /// Persistent, so that lines that are buffered but not yet consumed are retained
+            stdout_lines: Lines<BufReader<ChildStdout>>,
             state_for_testing: Option<StateForTesting>,
             stderr: Arc<Mutex<String>>,
         }
 
         impl Subject {
             pub(crate) async fn connect_to_browser(&mut self) -> anyhow::Result<Browser> {
-                let (browser, handler) =
-                    Browser::connect(self.state_for_testing()?.browser_debugging_address).await?;
+                let debugging_address = self
+                    .state_for_testing()?
+                    .browser_debugging_address
+                    .context("no browser was launched")?;
+
+                let (browser, handler) = Browser::connect(debugging_address).await?;
 
                 tokio::spawn(async move {
                     handler
@@ -1256,12 +4476,30 @@ mod fixture {
                 ))
             }
 
+            pub(crate) fn http_get(
+                &mut self,
+                path: &str,
+                headers: &[(&str, &str)],
+            ) -> anyhow::Result<HttpResponse> {
+                http_get(self.state_for_testing()?.serve_port, path, headers)
+            }
+
             pub(crate) fn wait_stderr_contains(
                 &mut self,
                 pat: impl AsRef<str>,
             ) -> anyhow::Result<String> {
+                let mut lines = self.wait_stderr_lines_until(pat)?;
+                Ok(lines.pop().unwrap())
+            }
+
+            /// Returns the lines up to and including the first one that contains `pat`
+            pub(crate) fn wait_stderr_lines_until(
+                &mut self,
+                pat: impl AsRef<str>,
+            ) -> anyhow::Result<Vec<String>> {
                 let pat = pat.as_ref();
                 eprintln!("waiting for subject stderr line that contains: {pat}");
+                let mut lines = Vec::new();
 
                 loop {
                     let mut stderr_lock = self.stderr.lock().map_err(|e| anyhow!("{e}"))?;
@@ -1278,8 +4516,11 @@ mod fixture {
                         .take(line_feed_index)
                         .collect::<String>();
 
-                    if line.contains(pat) {
-                        return Ok(line);
+                    let is_match = line.contains(pat);
+                    lines.push(line);
+
+                    if is_match {
+                        return Ok(lines);
                     }
                 }
             }
@@ -1289,25 +4530,27 @@ mod fixture {
                     return Ok(state_for_testing.clone());
                 }
 
-                let stdout = self
-                    .process
-                    .stdout
-                    .as_mut()
-                    .context("obtaining subject stdout mutable reference")?;
-
-                let mut stdout_lines = std::io::BufReader::new(stdout).lines();
+                let state_for_testing = self.read_state_for_testing()?;
+                let _ = self.state_for_testing.insert(state_for_testing);
+                Ok(self.state_for_testing.as_ref().unwrap().clone())
+            }
 
-                let line = loop {
-                    if let Some(line) = stdout_lines.next() {
-                        break line?;
-                    }
-                };
+            /// Reads the state that is printed following the next build
+            pub(crate) fn next_state_for_testing(&mut self) -> anyhow::Result<StateForTesting> {
+                // the initial one
+                self.state_for_testing()?;
+                self.read_state_for_testing()
+            }
 
-                let state_for_testing = serde_json::from_str(&line)
-                    .with_context(|| format!("failed to parse state for testing: {line:?}"))?;
+            fn read_state_for_testing(&mut self) -> anyhow::Result<StateForTesting> {
+                let line = self
+                    .stdout_lines
+                    .next()
+                    .context("subject stdout ended before a state for testing was printed")?
+                    .context("reading subject stdout")?;
 
-                let _ = self.state_for_testing.insert(state_for_testing);
-                Ok(self.state_for_testing.as_ref().unwrap().clone())
+                serde_json::from_str(&line)
+                    .with_context(|| format!("failed to parse state for testing: {line:?}"))
             }
 
             pub(crate) fn wait_browser_spawned(&mut self) -> anyhow::Result<()> {
@@ -1317,9 +4560,14 @@ mod fixture {
                 Ok(())
             }
 
-            pub(super) fn new(process: std::process::Child, stderr: Arc<Mutex<String>>) -> Self {
+            pub(super) fn new(
+                process: std::process::Child,
+                stdout: ChildStdout,
+                stderr: Arc<Mutex<String>>,
+            ) -> Self {
                 Self {
                     process: DroppyChild::new(process),
+                    stdout_lines: BufReader::new(stdout).lines(),
                     state_for_testing: None,
                     stderr,
                 }
@@ -1328,6 +4576,81 @@ mod fixture {
     }
 }
 
+mod http_get {
+    use std::{
+        io::{Read as _, Write as _},
+        net::{Ipv4Addr, TcpStream},
+    };
+
+    use anyhow::Context as _;
+
+    #[derive(Debug)]
+    pub(super) struct HttpResponse {
+        pub(crate) status: u16,
+        pub(crate) headers: Vec<(String, String)>,
+        pub(crate) body: Vec<u8>,
+    }
+
+    impl HttpResponse {
+        pub(crate) fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    pub(super) fn http_get(
+        port: u16,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<HttpResponse> {
+        let mut stream =
+            TcpStream::connect((Ipv4Addr::LOCALHOST, port)).context("connect to subject")?;
+
+        let mut request = format!(
+            "GET {path} HTTP/1.0\r\nHost: {}:{port}\r\n",
+            Ipv4Addr::LOCALHOST
+        );
+
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let head_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .context("response head not terminated")?;
+
+        let body = response.split_off(head_end + 4);
+        let head = String::from_utf8(response).context("response head is not UTF-8")?;
+        let mut lines = head.trim_end().split("\r\n");
+        let status_line = lines.next().context("no status line")?;
+
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .with_context(|| format!("malformed status line: {status_line:?}"))?
+            .parse()?;
+
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_owned(), value.trim().to_owned()))
+            .collect();
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
 mod html_page {
     use maud::{DOCTYPE, html};
 
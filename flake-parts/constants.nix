@@ -1,5 +1,6 @@
 {
   perSystem.buildEnv = {
     SERVE_PATH = "SERVE_PATH";
+    CHANGED_PATHS = "CHANGED_PATHS";
   };
 }
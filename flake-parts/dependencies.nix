@@ -23,7 +23,6 @@ in
         "notify"
         "serde"
         "serde_json"
-        "static-web-server"
         "static_init"
         "tempfile"
         "tokio-stream"
@@ -38,16 +37,28 @@ in
         ];
         hyper = {
           features = [
+            "client"
             "http1"
+            "http2"
             "server"
           ];
           version = "0";
         };
-        nix.features = [ "signal" ];
+        nix.features = [
+          "fs"
+          "signal"
+        ];
         process-wrap = {
           features = [ "tokio1" ];
         };
         replace_with.features = [ "std" ];
+        ring.features = [ "alloc" ];
+        rustls-pki-types.features = [ "std" ];
+        socket2.features = [ "all" ];
+        static-web-server.features = [
+          "compression-brotli"
+          "compression-gzip"
+        ];
         rxrust = {
           features = [ "scheduler" ];
           version = "1.0.0-rc.3";
@@ -56,7 +67,13 @@ in
           "io-util"
           "process"
         ];
+        tokio-rustls.features = [
+          "logging"
+          "ring"
+          "tls12"
+        ];
         tracing-subscriber.features = [ "env-filter" ];
+        percent-encoding.features = [ "alloc" ];
         anyhow.features = [
           "backtrace"
           "std"
@@ -79,13 +96,18 @@ in
           "hyper"
           "nix"
           "notify"
+          "percent-encoding"
           "replace_with"
+          "ring"
+          "rustls-pki-types"
           "rxrust"
           "serde"
           "serde_json"
+          "socket2"
           "static-web-server"
           "tempfile"
           "tokio"
+          "tokio-rustls"
           "tokio-stream"
           "tracing"
         ]
@@ -36,15 +36,82 @@
               $ ${config.metadata.title} <build command>
               ```
 
+              ### Scaffolding
+
+              ```
+              $ ${config.metadata.title} init
+              ```
+
+              Writes an example executable build command `build.sh` to the root of the current git repository.
+              Existing files are not overwritten.
+
               ### Behavior summary
 
-              A temporary directory *serve path* is created
-              and its contents statically served at `http://localhost:<available port>/`.
-              A chromium browser is launched with that URL.
+              A temporary directory *serve path* is created,
+              or the one provided via `--serve-dir`, which is retained on exit and, if within the repository, must be ignored by git,
+              and its contents statically served at `http://<host>:<port>/`,
+              where `<host>` is the one provided via `--host` or else `127.0.0.1`
+              and `<port>` is the one provided via `--port` or else an available one.
+              Files and directories whose names begin with a dot are not served unless `--serve-hidden` is provided, and symbolic links are responded to with `403` unless `--follow-symlinks` is.
+              With `--cors <origins>`, comma-separated, or `*`, requests from those origins are responded to with CORS headers and those from other origins with `403`.
+              With `--spa`, requests for paths that are not found and have no extension are responded to with the root `index.html`, for single-page applications with client side routing.
+              Other requests for files that are not found are responded to with the *serve path*'s `404.html`, with `404` or the status provided via `--page404-status`, and server errors with its `50x.html` or the page provided via `--page50x`.
+              With `--etag-strategy mtime`, served files carry an ETag derived from their modification time and size, and with `--etag-strategy hash`, one derived from their content, which is not sent in response to `HEAD`; requests with a matching `If-None-Match` are responded to with `304`.
+              With `--compression`, responses are compressed with gzip or brotli, as accepted by the client, and precompressed `.gz` and `.br` files are served where present.
+              With `--access-log`, the method, URI and response status of each served request are logged.
+              With `--slow-request-ms <milliseconds>`, requests that took longer to respond to are logged at warn level regardless.
+              With `--maintenance-mode`, all requests are responded to with a maintenance page, the one provided via `--maintenance-file` or else a default message, with `503` or the status provided via `--maintenance-status`; with `--maintenance-during-build`, only while a build following a change runs.
+              With `--tls`, HTTPS is served instead, with an ephemeral self-signed certificate, which the launched browser accepts, or with the one provided via `--tls-cert` and `--tls-key`.
+              It is served over HTTP/1.1 and over HTTP/2, which is negotiated via ALPN over TLS and otherwise requires prior knowledge (h2c), or, with `--http2`, over HTTP/2 only, which requires `--tls` or `--no-browser`, as browsers do not speak h2c.
+              Once the initial build succeeds, a chromium browser is launched with that URL,
+              the one provided via `--browser-executable` or else one found on `PATH`.
+              With `--defer-browser-until-ready false`, it is launched as soon as the server is up.
+              With `--open false`, it is launched but its page remains blank.
+              With `--headless`, it is launched without a window.
+              Its pages are rendered at the dimensions of its window, or at those provided via `--viewport <width>x<height>`.
+              Its window is placed at `--window-position <x>,<y>` and maximized with `--maximize`, e.g. so that it lands on a particular display.
+              With `--no-browser`, none is launched,
+              and with `--browser-ws-url`, an already running one is connected to instead.
+              Each URL provided via `--open-extra` is opened in an additional page, which is not reloaded.
+              The launched browser's stderr is logged at debug level; with `--quiet-browser`, those of its lines that are not known noise, such as GPU and D-Bus warnings, are logged as warnings instead.
+              With `--har <path>`, the network activity of the browser's page is written to an HTTP Archive on exit, a HAR page per navigation, of which those preceding each reload are discarded with `--har-reset-on-reload`.
+              With `--screenshot-dir <path>`, its page is captured into that directory as a PNG once loaded following each successful build.
 
-              On file changes the `<build command>` is invoked.
+              On file changes the `<build command>` is invoked,
+              except for files ignored by git or by the patterns in `.ignore` or `.conveyorbeltignore` at the repository root.
+              The repository root is the git toplevel of the current directory or, outside of a git work tree, the current directory itself, whose `.gitignore` files are respected all the same; `--project-root` overrides it.
+              The `<build command>` runs in the repository root, or in the directory provided via `--build-cwd`.
+              Each `--build-env <key>=<value>` sets an environment variable for the `<build command>`, which with `--build-clean-env` inherits no others, receiving only those and the ones set by conveyorbelt, such as `SERVE_PATH`.
+              Each directory provided via `--watch`, e.g. one outside of the repository, is watched as well, without regard to ignore files.
+              Further ignore files may be provided via `--ignore-file`, repeatably, with patterns relative to the repository root.
+              With `--watch-ext <extensions>`, comma-separated, e.g. `html,css,js`, changes to files of other extensions are ignored as well.
               The *build process* receives the *serve path* via the environment variable `${psArgs.config.buildEnv.SERVE_PATH}`.
+              Unless it is the initial one, it also receives the paths that changed, newline separated, via `${psArgs.config.buildEnv.CHANGED_PATHS}`.
+              With `--on-change <command>`, that command is invoked with the same environment before each build that is due to changes,
+              which is skipped if it fails and `--on-change-required` is provided.
+              Multiple `<build command>`s are invoked in sequence, which ends at the first that fails, and the build succeeds only if all of them do; their output is logged along with the index of each.
+              Each `--build-arg <arg>` is passed to every `<build command>` as an argument, in order, e.g. `--build-arg --release`.
+              Instead of a `<build command>`, `--shell <command>` runs a command line via `/bin/sh -c`, e.g. `--shell 'cp -r src "$SERVE_PATH"'`.
+              With `--isolate-build-tmp`, each build receives via `TMPDIR` a fresh directory, which is removed once it finishes.
+              With `--build-retries <count>`, a `<build command>` that exits with a non-zero status is rerun up to that many times per build, `--build-retry-delay` milliseconds apart, `1000` by default, before the build is considered failed.
+              With `--require-nonempty-output`, a build that succeeds without writing any files to the *serve path* is considered failed.
+              With `--warn-empty`, a build that succeeds without writing any files or an `index.html` to the *serve path* is warned about.
+              With `--no-initial-build`, the *serve path*, typically provided via `--serve-dir`, is served as is on startup, and the `<build command>` is first invoked on the first change.
+              With `--check`, the setup is validated instead: the repository root is resolved, the watcher is created and the `<build command>` is invoked once, without serving or launching a browser, and the exit code is `0` only if all succeeded.
+              With `--stage-build-output`, the *build process* receives a staging directory beside the *serve path* instead, which is atomically exchanged with it only once the build succeeds, so that the output of a failed build is never served.
               When the *build process* exits successfully, the page reloads.
+              With `--reload-method script` (or its alias `websocket`), a script injected into served HTML pages reloads them instead,
+              including pages open in browsers other than the launched one, and with `--reload-method both`, both do.
+              With `--build-log-api`, recent output of the *build process* is served at `/__conveyorbelt/build-log`.
+              On `SIGHUP` while idle, the repository root is resolved again, the ignore files are reread and an initial build follows.
+              With `--health`, `/health` responds with `503` until the initial build succeeds and with `200` thereafter.
+              With `--stream-test`, `/__conveyorbelt/stream?path=<path>` streams the served file at `<path>` in chunks `--stream-test-delay` milliseconds apart, followed by an `x-conveyorbelt-chunks` trailer, which is sent only over HTTP/2.
+              With `--control-socket <path>`, writing the line `rebuild` to the Unix domain socket created at `<path>` triggers a build, e.g. `echo rebuild | socat - UNIX-CONNECT:<path>`.
+              With `--state-socket <path>`, each connection to the Unix domain socket created at `<path>` receives, once initialized, a JSON line of the serve path and port, the browser pid and debugging address and the exit code of the last build, e.g. `socat - UNIX-CONNECT:<path>`.
+              With `--url-file <path>`, the URL that is served at is written to that file once listening, e.g. for editor integrations, and removed on exit.
+              With `--manifest <path>`, a JSON object of the served files' paths to their sizes, modification times and SHA-256 content hashes is written to that file following each successful build; if it is within the repository, it should be ignored by git.
+              With `--external-build` and no `<build command>`, the *serve path*, typically provided via `--serve-dir`, is watched instead, and changes to it reload the page.
+              Events are logged to stderr at info level, or with `-q`/`--quiet` at warn level and with `-v`/`--verbose` at debug level, or trace level if repeated, unless a filter is provided via the environment variable `${psArgs.config.buildEnv.LOG_FILTER_VAR_NAME}`.
 
               ## Prior art
 